@@ -7,6 +7,27 @@ pub use sea_orm_migration::prelude::*;
 //mod m20250401_031514_add_patient_metadata;
 //mod m20250407_035528_create_base_schema;
 mod m20250424_233306_create_base_schema;
+mod m20250501_190412_add_user_role;
+mod m20250506_021733_create_refresh_token;
+mod m20250509_174520_create_revoked_token;
+mod m20250514_203158_add_user_active_flag;
+mod m20250519_151047_create_password_history;
+mod m20250523_094215_create_api_key;
+mod m20250528_162341_add_totp_mfa;
+mod m20250603_110927_add_name_search;
+mod m20250607_141502_create_idempotency_key;
+mod m20250611_092317_add_patient_version;
+mod m20250614_160844_create_patient_history;
+mod m20250618_103355_create_audit_log;
+mod m20250623_140912_add_field_encryption;
+mod m20250627_093126_add_patient_deletion;
+mod m20250702_151820_create_patient_tombstone;
+mod m20250704_102233_add_idempotency_key_patient;
+mod m20250706_084512_drop_idempotency_response_body;
+mod m20250706_091238_add_data_key_initial;
+mod m20250708_143017_add_birthdate_year_index;
+mod m20250710_093412_create_revoked_user;
+mod m20250712_081905_add_idempotency_key_claimed_at;
 
 pub struct Migrator;
 
@@ -15,6 +36,27 @@ impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
         vec![
             Box::new(m20250424_233306_create_base_schema::Migration),
+            Box::new(m20250501_190412_add_user_role::Migration),
+            Box::new(m20250506_021733_create_refresh_token::Migration),
+            Box::new(m20250509_174520_create_revoked_token::Migration),
+            Box::new(m20250514_203158_add_user_active_flag::Migration),
+            Box::new(m20250519_151047_create_password_history::Migration),
+            Box::new(m20250523_094215_create_api_key::Migration),
+            Box::new(m20250528_162341_add_totp_mfa::Migration),
+            Box::new(m20250603_110927_add_name_search::Migration),
+            Box::new(m20250607_141502_create_idempotency_key::Migration),
+            Box::new(m20250611_092317_add_patient_version::Migration),
+            Box::new(m20250614_160844_create_patient_history::Migration),
+            Box::new(m20250618_103355_create_audit_log::Migration),
+            Box::new(m20250623_140912_add_field_encryption::Migration),
+            Box::new(m20250627_093126_add_patient_deletion::Migration),
+            Box::new(m20250702_151820_create_patient_tombstone::Migration),
+            Box::new(m20250704_102233_add_idempotency_key_patient::Migration),
+            Box::new(m20250706_084512_drop_idempotency_response_body::Migration),
+            Box::new(m20250706_091238_add_data_key_initial::Migration),
+            Box::new(m20250708_143017_add_birthdate_year_index::Migration),
+            Box::new(m20250710_093412_create_revoked_user::Migration),
+            Box::new(m20250712_081905_add_idempotency_key_claimed_at::Migration),
         ]
     }
 }
@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // pg_trgm provides similarity() and the trigram index operators;
+        // fuzzystrmatch provides the Double Metaphone functions
+        db.execute_unprepared("CREATE EXTENSION IF NOT EXISTS pg_trgm")
+            .await?;
+        db.execute_unprepared("CREATE EXTENSION IF NOT EXISTS fuzzystrmatch")
+            .await?;
+
+        // Trigram indexes on the lowercased names serve both the
+        // case-insensitive prefix searches and similarity ranking
+        db.execute_unprepared(
+            "CREATE INDEX IF NOT EXISTS idx_name_first_trgm \
+             ON name USING gin (lower(first) gin_trgm_ops)",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE INDEX IF NOT EXISTS idx_name_surname_trgm \
+             ON name USING gin (lower(surname) gin_trgm_ops)",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // The extensions are left installed, since other schemas in the
+        // database may depend on them
+        db.execute_unprepared("DROP INDEX IF EXISTS idx_name_surname_trgm")
+            .await?;
+        db.execute_unprepared("DROP INDEX IF EXISTS idx_name_first_trgm")
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A full snapshot of a patient after each change, so any past state can be read back
+        manager
+            .create_table(
+                Table::create()
+                    .table(PatientHistory::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PatientHistory::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PatientHistory::PatientId).uuid().not_null())
+                    .col(ColumnDef::new(PatientHistory::Version).integer().not_null())
+                    .col(ColumnDef::new(PatientHistory::Action).string().not_null())
+                    .col(
+                        ColumnDef::new(PatientHistory::ChangedBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PatientHistory::ChangedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("CURRENT_TIMESTAMP")),
+                    )
+                    .col(
+                        ColumnDef::new(PatientHistory::ActiveFlag)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PatientHistory::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PatientHistory::First).string().not_null())
+                    .col(ColumnDef::new(PatientHistory::Middle).string().not_null())
+                    .col(ColumnDef::new(PatientHistory::Surname).string().not_null())
+                    .col(
+                        ColumnDef::new(PatientHistory::AddressLines)
+                            .array(ColumnType::String(StringLen::N(60)))
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PatientHistory::Sublocality)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PatientHistory::Locality).string().not_null())
+                    .col(
+                        ColumnDef::new(PatientHistory::AdministrativeArea)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PatientHistory::PostalCode)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PatientHistory::CountryRegion)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PatientHistory::Day).integer().not_null())
+                    .col(ColumnDef::new(PatientHistory::Month).integer().not_null())
+                    .col(ColumnDef::new(PatientHistory::Year).integer().not_null())
+                    .index(
+                        Index::create()
+                            .name("idx_patient_history_patient_id_version")
+                            .col(PatientHistory::PatientId)
+                            .col(PatientHistory::Version)
+                            .unique(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_patient_history_patient_id_changed_at")
+                    .table(PatientHistory::Table)
+                    .col(PatientHistory::PatientId)
+                    .col(PatientHistory::ChangedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Earlier changes weren't recorded, so existing patients start from their current state
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "INSERT INTO patient_history (
+                    patient_id, version, action, changed_by, active_flag, created_at,
+                    first, middle, surname,
+                    address_lines, sublocality, locality, administrative_area, postal_code,
+                    country_region, day, month, year
+                )
+                SELECT
+                    p.patient_id, p.version, 'baseline', 'system', p.active_flag, p.created_at,
+                    n.first, coalesce(n.middle, ''), n.surname,
+                    a.address_lines, coalesce(a.sublocality, ''), coalesce(a.locality, ''),
+                    coalesce(a.administrative_area, ''), coalesce(a.postal_code, ''),
+                    a.country_region, b.day, b.month, b.year
+                FROM patient p
+                JOIN name n ON n.id = p.name_id
+                JOIN address a ON a.id = p.address_id
+                JOIN birthdate b ON b.id = p.birthdate_id",
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PatientHistory::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum PatientHistory {
+    Table,
+    Id,
+    PatientId,
+    Version,
+    Action,
+    ChangedBy,
+    ChangedAt,
+    ActiveFlag,
+    CreatedAt,
+    First,
+    Middle,
+    Surname,
+    AddressLines,
+    Sublocality,
+    Locality,
+    AdministrativeArea,
+    PostalCode,
+    CountryRegion,
+    Day,
+    Month,
+    Year,
+}
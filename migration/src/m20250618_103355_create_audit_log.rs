@@ -0,0 +1,112 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // One row per API request, each chained to the one before by its hash
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuditLog::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AuditLog::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(AuditLog::OccurredAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(AuditLog::Actor).string().not_null())
+                    .col(ColumnDef::new(AuditLog::Action).string().not_null())
+                    .col(ColumnDef::new(AuditLog::PatientId).uuid())
+                    .col(ColumnDef::new(AuditLog::RequestId).string().not_null())
+                    .col(ColumnDef::new(AuditLog::ClientIp).string())
+                    .col(
+                        ColumnDef::new(AuditLog::StatusCode)
+                            .small_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(AuditLog::Outcome).string().not_null())
+                    .col(ColumnDef::new(AuditLog::PrevHash).string().not_null())
+                    .col(ColumnDef::new(AuditLog::Hash).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_log_patient_id")
+                    .table(AuditLog::Table)
+                    .col(AuditLog::PatientId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_log_actor")
+                    .table(AuditLog::Table)
+                    .col(AuditLog::Actor)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Rows can only be added; changing or removing one fails in the database itself
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+                BEGIN
+                    RAISE EXCEPTION 'audit_log is append-only';
+                END;
+                $$ LANGUAGE plpgsql;
+
+                CREATE TRIGGER audit_log_no_update_or_delete
+                    BEFORE UPDATE OR DELETE ON audit_log
+                    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+                CREATE TRIGGER audit_log_no_truncate
+                    BEFORE TRUNCATE ON audit_log
+                    FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();",
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AuditLog::Table).to_owned())
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared("DROP FUNCTION IF EXISTS audit_log_append_only()")
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Iden)]
+enum AuditLog {
+    Table,
+    Id,
+    OccurredAt,
+    Actor,
+    Action,
+    PatientId,
+    RequestId,
+    ClientIp,
+    StatusCode,
+    Outcome,
+    PrevHash,
+    Hash,
+}
@@ -0,0 +1,208 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Keys that encrypt patient fields, each wrapped by the master key
+        manager
+            .create_table(
+                Table::create()
+                    .table(DataKey::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(DataKey::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(DataKey::WrappedKey).string().not_null())
+                    .col(ColumnDef::new(DataKey::MasterKeyId).string().not_null())
+                    .col(
+                        ColumnDef::new(DataKey::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // The data key a patient's fields are encrypted with, or null while they are in plaintext.
+        // Keys can't be dropped while a patient or history entry still uses them.
+        for (table, fk) in [
+            (Patient::Table.into_iden(), "fk_patient_key_version"),
+            (
+                PatientHistory::Table.into_iden(),
+                "fk_patient_history_key_version",
+            ),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(table.clone())
+                        .add_column(ColumnDef::new(Patient::KeyVersion).integer())
+                        .add_foreign_key(
+                            TableForeignKey::new()
+                                .name(fk)
+                                .from_tbl(table)
+                                .from_col(Patient::KeyVersion)
+                                .to_tbl(DataKey::Table)
+                                .to_col(DataKey::Id),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // Keyed hashes of the searchable fields, for exact matches on encrypted values
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Name::Table)
+                    .add_column(ColumnDef::new(Name::FirstIndex).string())
+                    .add_column(ColumnDef::new(Name::SurnameIndex).string())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Address::Table)
+                    .add_column(ColumnDef::new(Address::LocalityIndex).string())
+                    .add_column(ColumnDef::new(Address::PostalCodeIndex).string())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Birthdate::Table)
+                    .add_column(ColumnDef::new(Birthdate::BirthDateIndex).string())
+                    .to_owned(),
+            )
+            .await?;
+        for (name, table, column) in [
+            (
+                "idx_name_first_index",
+                Name::Table.into_iden(),
+                Name::FirstIndex.into_iden(),
+            ),
+            (
+                "idx_name_surname_index",
+                Name::Table.into_iden(),
+                Name::SurnameIndex.into_iden(),
+            ),
+            (
+                "idx_address_locality_index",
+                Address::Table.into_iden(),
+                Address::LocalityIndex.into_iden(),
+            ),
+            (
+                "idx_address_postal_code_index",
+                Address::Table.into_iden(),
+                Address::PostalCodeIndex.into_iden(),
+            ),
+            (
+                "idx_birthdate_birth_date_index",
+                Birthdate::Table.into_iden(),
+                Birthdate::BirthDateIndex.into_iden(),
+            ),
+        ] {
+            manager
+                .create_index(
+                    Index::create()
+                        .name(name)
+                        .table(table)
+                        .col(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // An encrypted birth date is stored whole in `sealed_date`, leaving only the year in the
+        // clear
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "ALTER TABLE birthdate
+                ADD COLUMN sealed_date varchar,
+                ALTER COLUMN day DROP NOT NULL,
+                ALTER COLUMN month DROP NOT NULL;
+            ALTER TABLE patient_history
+                ADD COLUMN sealed_date varchar,
+                ALTER COLUMN day DROP NOT NULL,
+                ALTER COLUMN month DROP NOT NULL",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Encrypted rows can't be restored without the key, so this only succeeds once none remain
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "ALTER TABLE birthdate
+                DROP COLUMN sealed_date,
+                DROP COLUMN birth_date_index,
+                ALTER COLUMN day SET NOT NULL,
+                ALTER COLUMN month SET NOT NULL;
+            ALTER TABLE patient_history
+                DROP COLUMN sealed_date,
+                DROP COLUMN key_version,
+                ALTER COLUMN day SET NOT NULL,
+                ALTER COLUMN month SET NOT NULL;
+            ALTER TABLE address DROP COLUMN locality_index, DROP COLUMN postal_code_index;
+            ALTER TABLE name DROP COLUMN first_index, DROP COLUMN surname_index;
+            ALTER TABLE patient DROP COLUMN key_version",
+        )
+        .await?;
+
+        manager
+            .drop_table(Table::drop().table(DataKey::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum DataKey {
+    Table,
+    Id,
+    WrappedKey,
+    MasterKeyId,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum Patient {
+    Table,
+    KeyVersion,
+}
+
+#[derive(Iden)]
+enum PatientHistory {
+    Table,
+}
+
+#[derive(Iden)]
+enum Name {
+    Table,
+    FirstIndex,
+    SurnameIndex,
+}
+
+#[derive(Iden)]
+enum Address {
+    Table,
+    LocalityIndex,
+    PostalCodeIndex,
+}
+
+#[derive(Iden)]
+enum Birthdate {
+    Table,
+    BirthDateIndex,
+}
@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Who deleted a patient and when, cleared again when the patient is restored
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Patient::Table)
+                    .add_column(ColumnDef::new(Patient::DeletedAt).timestamp_with_time_zone())
+                    .add_column(ColumnDef::new(Patient::DeletedBy).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Patients deleted before now take both from their latest history entry, if it recorded
+        // the deletion
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE patient p
+                SET deleted_at = h.changed_at, deleted_by = h.changed_by
+                FROM patient_history h
+                WHERE p.active_flag = false
+                    AND h.patient_id = p.patient_id
+                    AND h.version = p.version
+                    AND h.action = 'deleted'",
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Patient::Table)
+                    .drop_column(Patient::DeletedAt)
+                    .drop_column(Patient::DeletedBy)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Patient {
+    Table,
+    DeletedAt,
+    DeletedBy,
+}
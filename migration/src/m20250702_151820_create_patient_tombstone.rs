@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // What remains of a purged patient: its ID and the dates of its lifecycle, with no PHI
+        manager
+            .create_table(
+                Table::create()
+                    .table(PatientTombstone::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PatientTombstone::PatientId)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PatientTombstone::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PatientTombstone::DeletedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(PatientTombstone::DeletedBy).string())
+                    .col(
+                        ColumnDef::new(PatientTombstone::PurgedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PatientTombstone::PurgedBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PatientTombstone::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum PatientTombstone {
+    Table,
+    PatientId,
+    CreatedAt,
+    DeletedAt,
+    DeletedBy,
+    PurgedAt,
+    PurgedBy,
+}
@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The patient a request created and its version then, for the replay's audit and ETag
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(IdempotencyKey::Table)
+                    .add_column(ColumnDef::new(IdempotencyKey::PatientId).uuid())
+                    .add_column(ColumnDef::new(IdempotencyKey::Version).integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_idempotency_key_patient_id")
+                    .table(IdempotencyKey::Table)
+                    .col(IdempotencyKey::PatientId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(IdempotencyKey::Table)
+                    .drop_column(IdempotencyKey::PatientId)
+                    .drop_column(IdempotencyKey::Version)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum IdempotencyKey {
+    Table,
+    PatientId,
+    Version,
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Replays are rebuilt from the patient's encrypted history, so the plaintext bodies go.
+        // Keys finished before the patient was recorded with them can't be replayed without one.
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "DELETE FROM idempotency_key WHERE response_status IS NOT NULL AND patient_id IS NULL",
+        )
+        .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(IdempotencyKey::Table)
+                    .drop_column(IdempotencyKey::ResponseBody)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(IdempotencyKey::Table)
+                    .add_column(ColumnDef::new(IdempotencyKey::ResponseBody).text())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum IdempotencyKey {
+    Table,
+    ResponseBody,
+}
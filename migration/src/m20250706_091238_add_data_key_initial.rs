@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Marks the first data key of each master key, which only one of several servers starting
+        // together may create
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "ALTER TABLE data_key ADD COLUMN initial boolean NOT NULL DEFAULT false;
+            UPDATE data_key SET initial = true
+                WHERE id IN (SELECT min(id) FROM data_key GROUP BY master_key_id);
+            CREATE UNIQUE INDEX idx_data_key_initial ON data_key (master_key_id) WHERE initial",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "DROP INDEX IF EXISTS idx_data_key_initial;
+            ALTER TABLE data_key DROP COLUMN initial",
+        )
+        .await?;
+
+        Ok(())
+    }
+}
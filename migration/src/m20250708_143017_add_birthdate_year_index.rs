@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The duplicates report reads patients a birth year at a time, and the year is never
+        // encrypted
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_birthdate_year")
+                    .table(Birthdate::Table)
+                    .col(Birthdate::Year)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_birthdate_year")
+                    .table(Birthdate::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum Birthdate {
+    Table,
+    Year,
+}
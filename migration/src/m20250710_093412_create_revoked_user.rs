@@ -0,0 +1,54 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Cutoffs for revoking every token of a user, kept by username so they outlive a
+        // deleted user until the tokens would have expired anyway
+        manager
+            .create_table(
+                Table::create()
+                    .table(RevokedUser::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RevokedUser::Username)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RevokedUser::RevokedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"INSERT INTO revoked_user (username, revoked_at)
+                   SELECT username, tokens_revoked_at FROM "user"
+                   WHERE tokens_revoked_at IS NOT NULL"#,
+            )
+            .await
+            .map(|_| ())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RevokedUser::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum RevokedUser {
+    Table,
+    Username,
+    RevokedAt,
+}
@@ -0,0 +1,338 @@
+use crate::api::auth::tokens::hash_token;
+use crate::entities::audit_log::{self, Outcome};
+use crate::state::ApplicationState;
+
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// The `prev_hash` of the first record in the chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Recorded as the actor of requests that didn't authenticate
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Records per query when walking the whole log
+const BATCH_SIZE: u64 = 1000;
+
+/// The most requests whose events the writer appends in one transaction
+const MAX_WRITE_BATCH: usize = 256;
+
+/// Who made a request, set on the response by `jwt::auth` for the audit middleware
+#[derive(Clone, Debug)]
+pub struct AuditActor(pub String);
+
+/// The patients a response disclosed or changed, for handlers whose path doesn't name one
+#[derive(Clone, Debug, Default)]
+pub struct AuditPatients(pub Vec<Uuid>);
+
+/// One request to record
+pub struct Event {
+    pub actor: String,
+    pub action: String,
+    pub patient_id: Option<Uuid>,
+    pub request_id: String,
+    pub client_ip: Option<String>,
+    pub status: StatusCode,
+}
+
+/// Events waiting for the writer, and where to report whether they were stored
+struct Pending {
+    events: Vec<Event>,
+    stored: oneshot::Sender<Result<(), String>>,
+}
+
+/// Hands events to the single task that appends them, which `spawn_writer` starts
+///
+/// The events of requests that finish together are appended in one transaction, so they take
+/// one turn on the log's lock between them rather than queueing for it request by request.
+pub struct AuditWriter {
+    sender: mpsc::UnboundedSender<Pending>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<Pending>>>,
+}
+
+impl AuditWriter {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Queues events for the writer and waits until they are stored
+    pub async fn write(&self, events: Vec<Event>) -> anyhow::Result<()> {
+        let (stored, result) = oneshot::channel();
+        self.sender
+            .send(Pending { events, stored })
+            .map_err(|_| anyhow!("The audit writer isn't running"))?;
+        result
+            .await
+            .map_err(|_| anyhow!("The audit writer stopped"))?
+            .map_err(|err| anyhow!(err))
+    }
+}
+
+impl Default for AuditWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Starts the task that appends queued events to the log, once per state
+///
+/// The task stops once the state is dropped.
+pub fn spawn_writer(state: Arc<ApplicationState>) {
+    let Some(mut receiver) = state.audit_writer.receiver.lock().unwrap().take() else {
+        return;
+    };
+    let state = Arc::downgrade(&state);
+    tokio::spawn(async move {
+        while let Some(first) = receiver.recv().await {
+            let mut batch = vec![first];
+            while batch.len() < MAX_WRITE_BATCH {
+                match receiver.try_recv() {
+                    Ok(pending) => batch.push(pending),
+                    Err(_) => break,
+                }
+            }
+
+            let events = batch
+                .iter_mut()
+                .flat_map(|pending| std::mem::take(&mut pending.events))
+                .collect();
+            let result = match state.upgrade() {
+                Some(state) => append(state.db_conn.load().as_ref(), events)
+                    .await
+                    .map_err(|err| err.to_string()),
+                None => break,
+            };
+            for pending in batch {
+                // The request may have been cancelled while it waited
+                let _ = pending.stored.send(result.clone());
+            }
+        }
+    });
+}
+
+/// The fields covered by a record's hash, in a fixed order
+#[derive(Serialize)]
+struct Chained<'a> {
+    prev_hash: &'a str,
+    occurred_at: String,
+    actor: &'a str,
+    action: &'a str,
+    patient_id: Option<Uuid>,
+    request_id: &'a str,
+    client_ip: Option<&'a str>,
+    status_code: i16,
+    outcome: &'a str,
+}
+
+/// Classifies a response status for the log
+pub fn outcome(status: StatusCode) -> Outcome {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Outcome::Denied,
+        status if status.is_client_error() || status.is_server_error() => Outcome::Failure,
+        _ => Outcome::Success,
+    }
+}
+
+/// Computes the hash of a record from its fields and the hash of the one before it
+pub fn hash(entry: &audit_log::Model) -> String {
+    let chained = Chained {
+        prev_hash: &entry.prev_hash,
+        occurred_at: timestamp(&entry.occurred_at),
+        actor: &entry.actor,
+        action: &entry.action,
+        patient_id: entry.patient_id,
+        request_id: &entry.request_id,
+        client_ip: entry.client_ip.as_deref(),
+        status_code: entry.status_code,
+        outcome: entry.outcome.as_str(),
+    };
+    hash_token(&serde_json::to_string(&chained).expect("audit fields serialize to JSON"))
+}
+
+/// Appends events to the log, extending the hash chain
+///
+/// An advisory lock makes concurrent writers take turns, so every record links to the one
+/// stored just before it. Within a server the writer task is the only caller, so the lock only
+/// orders it against other instances and the CLI.
+pub async fn append(db: &DatabaseConnection, events: Vec<Event>) -> Result<(), DbErr> {
+    // Postgres stores microseconds, so hashing finer times would break verification
+    let occurred_at = Utc::now().trunc_subsecs(6);
+
+    let txn = db.begin().await?;
+    txn.execute_unprepared("SELECT pg_advisory_xact_lock(hashtext('audit_log'))")
+        .await?;
+    let mut prev_hash = audit_log::Entity::find()
+        .order_by_desc(audit_log::Column::Id)
+        .one(&txn)
+        .await?
+        .map_or_else(|| GENESIS_HASH.to_string(), |last| last.hash);
+
+    for event in events {
+        let mut entry = audit_log::Model {
+            id: 0,
+            occurred_at,
+            actor: event.actor,
+            action: event.action,
+            patient_id: event.patient_id,
+            request_id: event.request_id,
+            client_ip: event.client_ip,
+            status_code: event.status.as_u16() as i16,
+            outcome: outcome(event.status),
+            prev_hash,
+            hash: String::new(),
+        };
+        entry.hash = hash(&entry);
+        prev_hash = entry.hash.clone();
+
+        audit_log::ActiveModel {
+            occurred_at: Set(entry.occurred_at),
+            actor: Set(entry.actor),
+            action: Set(entry.action),
+            patient_id: Set(entry.patient_id),
+            request_id: Set(entry.request_id),
+            client_ip: Set(entry.client_ip),
+            status_code: Set(entry.status_code),
+            outcome: Set(entry.outcome),
+            prev_hash: Set(entry.prev_hash),
+            hash: Set(entry.hash),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+    }
+
+    txn.commit().await
+}
+
+/// Walks the whole log in order, checking each record's hash and its link to the one before
+///
+/// Calls `visit` for every record, and returns the ID of the first record that doesn't check
+/// out, if any.
+pub async fn walk<F>(db: &DatabaseConnection, mut visit: F) -> anyhow::Result<Option<i32>>
+where
+    F: FnMut(&audit_log::Model) -> anyhow::Result<()>,
+{
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut last_id = 0;
+    let mut broken = None;
+    loop {
+        let batch = audit_log::Entity::find()
+            .filter(audit_log::Column::Id.gt(last_id))
+            .order_by_asc(audit_log::Column::Id)
+            .limit(BATCH_SIZE)
+            .all(db)
+            .await?;
+        let Some(last) = batch.last() else {
+            return Ok(broken);
+        };
+        last_id = last.id;
+
+        for entry in &batch {
+            if broken.is_none() && (entry.prev_hash != prev_hash || entry.hash != hash(entry)) {
+                broken = Some(entry.id);
+            }
+            prev_hash = entry.hash.clone();
+            visit(entry)?;
+        }
+    }
+}
+
+/// Formats a timestamp the way the log is exported and returned by the API
+pub fn timestamp(at: &DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    fn event(actor: &str) -> Event {
+        Event {
+            actor: actor.to_string(),
+            action: "GET /v1/patient/:patient_id".to_string(),
+            patient_id: Some(Uuid::new_v4()),
+            request_id: Uuid::new_v4().to_string(),
+            client_ip: None,
+            status: StatusCode::OK,
+        }
+    }
+
+    fn stored(id: i32, prev_hash: &str) -> audit_log::Model {
+        let mut entry = audit_log::Model {
+            id,
+            occurred_at: Utc::now().trunc_subsecs(6),
+            actor: "jdoe".to_string(),
+            action: "GET /v1/patient".to_string(),
+            patient_id: None,
+            request_id: id.to_string(),
+            client_ip: None,
+            status_code: 200,
+            outcome: Outcome::Success,
+            prev_hash: prev_hash.to_string(),
+            hash: String::new(),
+        };
+        entry.hash = hash(&entry);
+        entry
+    }
+
+    #[tokio::test]
+    async fn appends_requests_that_finish_together_in_one_transaction() {
+        let first = stored(1, GENESIS_HASH);
+        let second = stored(2, &first.hash);
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_exec_results([MockExecResult {
+                    last_insert_id: 0,
+                    rows_affected: 1,
+                }])
+                .append_query_results([Vec::<audit_log::Model>::new()])
+                .append_query_results([[first], [second]])
+                .into_connection(),
+        );
+        spawn_writer(state.clone());
+
+        let (a, b) = tokio::join!(
+            state.audit_writer.write(vec![event("jdoe")]),
+            state.audit_writer.write(vec![event("asmith")]),
+        );
+        a.unwrap();
+        b.unwrap();
+
+        // One turn on the lock covers both requests
+        let log = state.into_transaction_log();
+        assert_eq!(log.len(), 1);
+        let statements = log[0].statements();
+        let count = |prefix: &str| {
+            statements
+                .iter()
+                .filter(|statement| statement.sql.starts_with(prefix))
+                .count()
+        };
+        assert_eq!(count("SELECT pg_advisory_xact_lock"), 1);
+        assert_eq!(count("INSERT INTO \"audit_log\""), 2);
+        assert_eq!(statements.last().unwrap().sql, "COMMIT");
+    }
+
+    #[test]
+    fn hashes_change_with_any_field() {
+        let entry = stored(1, GENESIS_HASH);
+        let mut tampered = entry.clone();
+        tampered.actor = "asmith".to_string();
+        assert_eq!(hash(&entry), entry.hash);
+        assert_ne!(hash(&tampered), entry.hash);
+    }
+}
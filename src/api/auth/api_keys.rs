@@ -0,0 +1,128 @@
+use crate::api::auth::revocation;
+use crate::api::auth::tokens::{generate_token, hash_token};
+use crate::api::response::TokenClaims;
+use crate::entities::api_key::{self, Scope};
+use crate::entities::user::Role;
+
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DbErr, EntityTrait,
+    QueryFilter,
+};
+use uuid::Uuid;
+
+/// Starts the `sub` of API keys, which are named `api-key:<id>` since key names aren't unique
+pub const PRINCIPAL_PREFIX: &str = "api-key:";
+
+/// The request header that carries an API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Marks API keys so that they are recognisable in logs and secret scanners
+const KEY_PREFIX: &str = "doc_";
+
+/// The number of leading key characters stored in the clear to identify the key
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+/// The last-used timestamp is only written when it is older than this, so
+/// that busy clients don't cause a write on every request
+const LAST_USED_RESOLUTION: chrono::Duration = chrono::Duration::seconds(60);
+
+/// The scopes of the API key that authenticated the request
+///
+/// Placed in the request extensions alongside the `TokenClaims`; `rbac::authorize` checks these
+/// instead of the role.
+#[derive(Clone, Debug)]
+pub struct ApiKeyScopes(pub Vec<Scope>);
+
+/// Stores a new API key and returns it with its plaintext value, which is never shown again
+pub async fn create_api_key<C: ConnectionTrait>(
+    db: &C,
+    name: &str,
+    scopes: &[Scope],
+    expires_at: Option<DateTime<Utc>>,
+    created_by: &str,
+) -> Result<(api_key::Model, String), DbErr> {
+    let key = format!("{KEY_PREFIX}{}", generate_token());
+    let scopes: Vec<&str> = scopes.iter().map(Scope::as_str).collect();
+
+    let model = api_key::ActiveModel {
+        name: Set(name.to_string()),
+        prefix: Set(key[..DISPLAY_PREFIX_LEN].to_string()),
+        key_hash: Set(hash_token(&key)),
+        scopes: Set(scopes.join(" ")),
+        created_by: Set(created_by.to_string()),
+        created_at: Set(Utc::now()),
+        expires_at: Set(expires_at),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok((model, key))
+}
+
+/// Looks up a presented API key and records its use
+///
+/// Returns `None` if the key is unknown, revoked, or expired.
+pub async fn authenticate<C: ConnectionTrait>(
+    db: &C,
+    presented: &str,
+) -> Result<Option<api_key::Model>, DbErr> {
+    let now = Utc::now();
+
+    let key = api_key::Entity::find()
+        .filter(api_key::Column::KeyHash.eq(hash_token(presented)))
+        .one(db)
+        .await?
+        .filter(|key| key.revoked_at.is_none())
+        .filter(|key| key.expires_at.is_none_or(|expires_at| expires_at > now));
+
+    if let Some(key) = &key {
+        if key
+            .last_used_at
+            .is_none_or(|last_used_at| now - last_used_at >= LAST_USED_RESOLUTION)
+        {
+            api_key::Entity::update_many()
+                .col_expr(api_key::Column::LastUsedAt, Expr::value(now))
+                .filter(api_key::Column::Id.eq(key.id))
+                .exec(db)
+                .await?;
+        }
+    }
+
+    Ok(key)
+}
+
+/// Builds the claims that stand in for a bearer token on API key requests
+///
+/// The `read_only` role grants nothing further, since `rbac::authorize` checks the key's
+/// scopes instead.
+pub fn claims(key: &api_key::Model) -> TokenClaims {
+    TokenClaims {
+        jti: Uuid::nil(),
+        sub: format!("{PRINCIPAL_PREFIX}{}", key.id),
+        role: Role::ReadOnly,
+        iat: revocation::epoch_seconds(key.created_at),
+        exp: key
+            .expires_at
+            .map_or(usize::MAX, |expires_at| expires_at.timestamp() as usize),
+    }
+}
+
+/// Revokes an API key, returning `false` if it doesn't exist
+pub async fn revoke_api_key<C: ConnectionTrait>(db: &C, id: i32) -> Result<bool, DbErr> {
+    let result = api_key::Entity::update_many()
+        .col_expr(api_key::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(api_key::Column::Id.eq(id))
+        .filter(api_key::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+
+    if result.rows_affected > 0 {
+        return Ok(true);
+    }
+
+    // Revoking a revoked key again succeeds
+    Ok(api_key::Entity::find_by_id(id).one(db).await?.is_some())
+}
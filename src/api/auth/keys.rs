@@ -0,0 +1,330 @@
+use crate::settings::{Settings, SigningAlgorithm, SigningKey};
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::errors::{ErrorKind, Result as JwtResult};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+
+/// The keys used to sign and verify access tokens
+///
+/// Built once at startup from `Settings::token_signing`, falling back to HS256 with
+/// `token_secret` when no key files are configured.
+pub struct SigningKeys {
+    /// The header for new tokens, naming the algorithm and `kid` of the active key
+    header: Header,
+    encoding_key: EncodingKey,
+    /// Verification keys by `kid`; the HS256 secret is stored under `None`
+    decoding_keys: HashMap<Option<String>, (Algorithm, DecodingKey)>,
+    /// The public half of every key, which is empty for HS256
+    jwks: JwkSet,
+}
+
+impl SigningKeys {
+    pub fn new(settings: &Settings) -> anyhow::Result<Self> {
+        let signing = &settings.token_signing;
+
+        if signing.keys.is_empty() {
+            let secret = settings.token_secret.as_bytes();
+            return Ok(Self {
+                header: Header::new(Algorithm::HS256),
+                encoding_key: EncodingKey::from_secret(secret),
+                decoding_keys: HashMap::from([(
+                    None,
+                    (Algorithm::HS256, DecodingKey::from_secret(secret)),
+                )]),
+                jwks: JwkSet { keys: Vec::new() },
+            });
+        }
+
+        let active_kid = signing
+            .active_kid
+            .clone()
+            .unwrap_or_else(|| signing.keys[0].kid.clone());
+
+        let mut active = None;
+        let mut decoding_keys = HashMap::new();
+        let mut jwks = JwkSet { keys: Vec::new() };
+
+        for key in &signing.keys {
+            let (encoding_key, jwk) = load_key(key)
+                .with_context(|| format!("Failed to load signing key \"{}\"", key.kid))?;
+            let algorithm = key.algorithm.into();
+            let decoding_key = DecodingKey::from_jwk(&jwk)?;
+
+            if decoding_keys
+                .insert(Some(key.kid.clone()), (algorithm, decoding_key))
+                .is_some()
+            {
+                return Err(anyhow!("Duplicate signing key ID \"{}\"", key.kid));
+            }
+            if key.kid == active_kid {
+                let mut header = Header::new(algorithm);
+                header.kid = Some(key.kid.clone());
+                active = Some((header, encoding_key));
+            }
+            jwks.keys.push(jwk);
+        }
+
+        let (header, encoding_key) = active
+            .ok_or_else(|| anyhow!("Active signing key \"{active_kid}\" is not configured"))?;
+
+        Ok(Self {
+            header,
+            encoding_key,
+            decoding_keys,
+            jwks,
+        })
+    }
+
+    /// Signs the claims with the active key
+    pub fn sign<T: Serialize>(&self, claims: &T) -> JwtResult<String> {
+        encode(&self.header, claims, &self.encoding_key)
+    }
+
+    /// Verifies a token against the key named by its `kid` header
+    ///
+    /// Only the algorithm configured for that key is accepted.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> JwtResult<T> {
+        let header = decode_header(token)?;
+        let (algorithm, key) = self
+            .decoding_keys
+            .get(&header.kid)
+            .ok_or_else(|| jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken))?;
+
+        Ok(decode::<T>(token, key, &Validation::new(*algorithm))?.claims)
+    }
+
+    /// The public keys for the JWKS endpoint
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+impl From<SigningAlgorithm> for Algorithm {
+    fn from(algorithm: SigningAlgorithm) -> Self {
+        match algorithm {
+            SigningAlgorithm::RS256 => Algorithm::RS256,
+            SigningAlgorithm::ES256 => Algorithm::ES256,
+            SigningAlgorithm::EdDSA => Algorithm::EdDSA,
+        }
+    }
+}
+
+/// Reads a private key file and derives the JWK of its public key
+fn load_key(key: &SigningKey) -> anyhow::Result<(EncodingKey, Jwk)> {
+    let pem_bytes = std::fs::read(&key.private_key_path)
+        .with_context(|| format!("Failed to read {}", key.private_key_path))?;
+    let pem = pem::parse(&pem_bytes)?;
+    let der = pem.contents.as_slice();
+
+    let (encoding_key, parameters) = match key.algorithm {
+        SigningAlgorithm::RS256 => {
+            let pair = match pem.tag.as_str() {
+                "RSA PRIVATE KEY" => RsaKeyPair::from_der(der),
+                _ => RsaKeyPair::from_pkcs8(der),
+            }
+            .map_err(|err| anyhow!("Invalid RSA private key: {err}"))?;
+            let public = pair.public_key();
+            let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: encode_component(public.modulus().big_endian_without_leading_zero()),
+                e: encode_component(public.exponent().big_endian_without_leading_zero()),
+            });
+            (EncodingKey::from_rsa_pem(&pem_bytes)?, parameters)
+        }
+        SigningAlgorithm::ES256 => {
+            let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der)
+                .map_err(|err| anyhow!("Invalid P-256 private key: {err}"))?;
+            // An uncompressed point: 0x04 followed by the x and y coordinates
+            let point = pair.public_key().as_ref();
+            let parameters = AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: EllipticCurve::P256,
+                x: encode_component(&point[1..33]),
+                y: encode_component(&point[33..65]),
+            });
+            (EncodingKey::from_ec_pem(&pem_bytes)?, parameters)
+        }
+        SigningAlgorithm::EdDSA => {
+            // OpenSSL writes v1 PKCS#8 documents, which omit the public key
+            let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+                .map_err(|err| anyhow!("Invalid Ed25519 private key: {err}"))?;
+            let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: encode_component(pair.public_key().as_ref()),
+            });
+            (EncodingKey::from_ed_pem(&pem_bytes)?, parameters)
+        }
+    };
+
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(key.algorithm.into()),
+            key_id: Some(key.kid.clone()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    };
+
+    Ok((encoding_key, jwk))
+}
+
+fn encode_component(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::response::TokenClaims;
+    use crate::entities::user::Role;
+    use crate::settings::TokenSigning;
+    use chrono::Utc;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    fn key(kid: &str, algorithm: SigningAlgorithm) -> SigningKey {
+        let file = match algorithm {
+            SigningAlgorithm::RS256 => "rs256.pem",
+            SigningAlgorithm::ES256 => "es256.pem",
+            SigningAlgorithm::EdDSA => "eddsa.pem",
+        };
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/keys")
+            .join(file);
+        SigningKey {
+            kid: kid.to_string(),
+            algorithm,
+            private_key_path: path.to_string_lossy().into_owned(),
+        }
+    }
+
+    fn keys(active_kid: Option<&str>, keys: Vec<SigningKey>) -> SigningKeys {
+        SigningKeys::new(&Settings {
+            token_signing: TokenSigning {
+                active_kid: active_kid.map(str::to_string),
+                keys,
+            },
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn claims() -> TokenClaims {
+        let now = Utc::now().timestamp() as usize;
+        TokenClaims {
+            jti: Uuid::new_v4(),
+            sub: "jdoe".to_string(),
+            role: Role::Clinician,
+            exp: now + 60,
+            iat: now as f64,
+        }
+    }
+
+    #[test]
+    fn signs_and_verifies_with_each_algorithm() {
+        for (kid, algorithm) in [
+            ("rs", SigningAlgorithm::RS256),
+            ("es", SigningAlgorithm::ES256),
+            ("ed", SigningAlgorithm::EdDSA),
+        ] {
+            let keys = keys(None, vec![key(kid, algorithm)]);
+            let token = keys.sign(&claims()).unwrap();
+
+            let header = decode_header(&token).unwrap();
+            assert_eq!(header.alg, algorithm.into());
+            assert_eq!(header.kid.as_deref(), Some(kid));
+            let verified: TokenClaims = keys.verify(&token).unwrap();
+            assert_eq!(verified.sub, "jdoe");
+        }
+    }
+
+    #[test]
+    fn verifies_with_the_key_named_by_kid() {
+        let configured = || {
+            vec![
+                key("old", SigningAlgorithm::RS256),
+                key("new", SigningAlgorithm::ES256),
+            ]
+        };
+        let old = keys(Some("old"), configured());
+        let new = keys(Some("new"), configured());
+
+        // Tokens signed before a rotation still verify after it
+        let token = old.sign(&claims()).unwrap();
+        assert!(new.verify::<TokenClaims>(&token).is_ok());
+        let token = new.sign(&claims()).unwrap();
+        assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("new"));
+        assert!(old.verify::<TokenClaims>(&token).is_ok());
+
+        // A key that isn't configured is rejected rather than guessed at
+        let unknown = keys(None, vec![key("other", SigningAlgorithm::ES256)]);
+        let token = unknown.sign(&claims()).unwrap();
+        assert!(new.verify::<TokenClaims>(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_an_algorithm_other_than_the_keys() {
+        let keys = keys(None, vec![key("rs", SigningAlgorithm::RS256)]);
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("rs".to_string());
+        let forged = encode(&header, &claims(), &EncodingKey::from_secret(b"secret")).unwrap();
+        assert!(keys.verify::<TokenClaims>(&forged).is_err());
+    }
+
+    #[test]
+    fn publishes_every_public_key() {
+        let keys = keys(
+            Some("es"),
+            vec![
+                key("rs", SigningAlgorithm::RS256),
+                key("es", SigningAlgorithm::ES256),
+                key("ed", SigningAlgorithm::EdDSA),
+            ],
+        );
+        let jwks = serde_json::to_value(keys.jwks()).unwrap();
+        let published = jwks["keys"].as_array().unwrap();
+        assert_eq!(published.len(), 3);
+
+        let expected = [
+            ("rs", "RS256", "RSA"),
+            ("es", "ES256", "EC"),
+            ("ed", "EdDSA", "OKP"),
+        ];
+        for (jwk, (kid, alg, kty)) in published.iter().zip(expected) {
+            assert_eq!(jwk["kid"], kid);
+            assert_eq!(jwk["alg"], alg);
+            assert_eq!(jwk["kty"], kty);
+            assert_eq!(jwk["use"], "sig");
+            // Only the public half is published
+            assert!(jwk.get("d").is_none());
+        }
+    }
+
+    #[test]
+    fn falls_back_to_the_shared_secret() {
+        let keys = SigningKeys::new(&Settings {
+            token_secret: "secret".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let token = keys.sign(&claims()).unwrap();
+        assert_eq!(decode_header(&token).unwrap().alg, Algorithm::HS256);
+        assert!(keys.verify::<TokenClaims>(&token).is_ok());
+        assert!(keys.jwks().keys.is_empty());
+    }
+}
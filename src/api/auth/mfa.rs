@@ -0,0 +1,392 @@
+use crate::api::auth::keys::SigningKeys;
+use crate::api::auth::revocation;
+use crate::api::auth::tokens::hash_token;
+use crate::entities::{recovery_code, user};
+use crate::settings::Settings;
+
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{Rng, RngCore};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, ConnectionTrait,
+    DatabaseConnection, DbErr, EntityTrait, QueryFilter, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use uuid::Uuid;
+
+/// The RFC 6238 defaults, which every authenticator app supports
+const TOTP_PERIOD_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+
+/// Codes from the time steps either side of the current one are accepted to allow for clock drift
+const TOTP_SKEW_STEPS: i64 = 1;
+
+/// Marks challenge tokens so that no other token can stand in for one
+const CHALLENGE_PURPOSE: &str = "mfa_challenge";
+
+/// Unambiguous characters for recovery codes
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Claims of the token that proves the password step of a login succeeded
+///
+/// It has no role, so it is rejected as a bearer token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub jti: Uuid,
+    pub sub: String,
+    pub purpose: String,
+    pub iat: f64,
+    pub exp: usize,
+}
+
+/// Signs a challenge token to exchange, together with a code, for an access token
+pub fn issue_challenge(
+    settings: &Settings,
+    keys: &SigningKeys,
+    user: &user::Model,
+) -> anyhow::Result<String> {
+    let now = Utc::now();
+    let claims = ChallengeClaims {
+        jti: Uuid::new_v4(),
+        sub: user.username.clone(),
+        purpose: CHALLENGE_PURPOSE.to_string(),
+        iat: revocation::epoch_seconds(now),
+        exp: (now + chrono::Duration::seconds(settings.mfa.challenge_timeout_seconds)).timestamp()
+            as usize,
+    };
+
+    Ok(keys.sign(&claims)?)
+}
+
+/// Verifies a challenge token, returning `None` if it is invalid or expired
+pub fn verify_challenge(keys: &SigningKeys, token: &str) -> Option<ChallengeClaims> {
+    keys.verify::<ChallengeClaims>(token)
+        .ok()
+        .filter(|claims| claims.purpose == CHALLENGE_PURPOSE)
+}
+
+/// Generates a 160-bit secret, as RFC 4226 recommends, encoded in base32
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// Builds the URI that authenticator apps read from a QR code
+pub fn otpauth_uri(issuer: &str, username: &str, secret: &str) -> String {
+    let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC);
+    let username = utf8_percent_encode(username, NON_ALPHANUMERIC);
+    format!(
+        "otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}\
+         &algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_PERIOD_SECONDS}"
+    )
+}
+
+/// Returns the time step the code is valid for, if any
+fn verify_totp(secret: &str, code: &str, now: i64) -> Option<i64> {
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let step = now / TOTP_PERIOD_SECONDS;
+    (step - TOTP_SKEW_STEPS..=step + TOTP_SKEW_STEPS)
+        .find(|step| hotp(&secret, *step as u64) == code)
+}
+
+/// Computes an RFC 4226 one-time password
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset],
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Generates a recovery code formatted as two groups of five characters
+fn generate_recovery_code() -> String {
+    let mut rng = rand::rngs::OsRng;
+    let chars: String = (0..10)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+/// Digests a recovery code, ignoring case and separators
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+/// Stores a new pending secret for the user, replacing any unconfirmed one
+pub async fn start_enrolment<C: ConnectionTrait>(
+    db: &C,
+    user: user::Model,
+) -> Result<String, DbErr> {
+    let secret = generate_secret();
+
+    let mut active: user::ActiveModel = user.into();
+    active.totp_secret = Set(Some(secret.clone()));
+    active.totp_enabled_at = Set(None);
+    active.totp_last_step = Set(None);
+    active.update(db).await?;
+
+    Ok(secret)
+}
+
+/// Enables MFA if the code matches the pending secret, returning fresh recovery codes
+pub async fn confirm_enrolment(
+    db: &DatabaseConnection,
+    settings: &Settings,
+    user: user::Model,
+    code: &str,
+) -> Result<Option<Vec<String>>, DbErr> {
+    let Some(step) = user
+        .totp_secret
+        .as_deref()
+        .and_then(|secret| verify_totp(secret, code.trim(), Utc::now().timestamp()))
+    else {
+        return Ok(None);
+    };
+
+    let txn = db.begin().await?;
+
+    let user_id = user.id;
+    let mut active: user::ActiveModel = user.into();
+    active.totp_enabled_at = Set(Some(Utc::now()));
+    active.totp_last_step = Set(Some(step));
+    active.update(&txn).await?;
+
+    let codes = replace_recovery_codes(&txn, user_id, settings.mfa.recovery_codes).await?;
+
+    txn.commit().await?;
+    Ok(Some(codes))
+}
+
+/// Turns MFA off and deletes the user's secret and recovery codes
+pub async fn disable<C: ConnectionTrait>(db: &C, user: user::Model) -> Result<(), DbErr> {
+    let user_id = user.id;
+    let mut active: user::ActiveModel = user.into();
+    active.totp_secret = Set(None);
+    active.totp_enabled_at = Set(None);
+    active.totp_last_step = Set(None);
+    active.update(db).await?;
+
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Checks a TOTP or recovery code for a user with MFA enabled
+///
+/// Accepted codes are consumed, so each TOTP time step and recovery code works only once.
+pub async fn verify_second_factor<C: ConnectionTrait>(
+    db: &C,
+    user: &user::Model,
+    code: &str,
+) -> Result<bool, DbErr> {
+    let code = code.trim();
+
+    if let Some(step) = user
+        .totp_secret
+        .as_deref()
+        .filter(|_| user.totp_enabled_at.is_some())
+        .and_then(|secret| verify_totp(secret, code, Utc::now().timestamp()))
+    {
+        let claimed = user::Entity::update_many()
+            .col_expr(user::Column::TotpLastStep, Expr::value(step))
+            .filter(user::Column::Id.eq(user.id))
+            .filter(
+                Condition::any()
+                    .add(user::Column::TotpLastStep.is_null())
+                    .add(user::Column::TotpLastStep.lt(step)),
+            )
+            .exec(db)
+            .await?;
+        return Ok(claimed.rows_affected > 0);
+    }
+
+    let claimed = recovery_code::Entity::update_many()
+        .col_expr(recovery_code::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(recovery_code::Column::UserId.eq(user.id))
+        .filter(recovery_code::Column::CodeHash.eq(hash_recovery_code(code)))
+        .filter(recovery_code::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(claimed.rows_affected > 0)
+}
+
+async fn replace_recovery_codes<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    count: usize,
+) -> Result<Vec<String>, DbErr> {
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    let codes: Vec<String> = (0..count).map(|_| generate_recovery_code()).collect();
+    if codes.is_empty() {
+        return Ok(codes);
+    }
+
+    let now = Utc::now();
+    recovery_code::Entity::insert_many(codes.iter().map(|code| recovery_code::ActiveModel {
+        user_id: Set(user_id),
+        code_hash: Set(hash_recovery_code(code)),
+        created_at: Set(now),
+        ..Default::default()
+    }))
+    .exec(db)
+    .await?;
+
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    /// The SHA-1 secret of the RFC 6238 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn user(secret: &str) -> user::Model {
+        user::Model {
+            id: 1,
+            username: "jdoe".to_string(),
+            password: String::new(),
+            role: user::Role::Clinician,
+            active_flag: true,
+            created_at: Utc::now(),
+            tokens_revoked_at: None,
+            totp_secret: Some(secret.to_string()),
+            totp_enabled_at: Some(Utc::now()),
+            totp_last_step: None,
+        }
+    }
+
+    fn code_at(secret: &str, now: i64) -> String {
+        let secret = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+        format!("{:06}", hotp(&secret, (now / TOTP_PERIOD_SECONDS) as u64))
+    }
+
+    fn updated(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    #[test]
+    fn matches_the_rfc_6238_vectors() {
+        // The last six digits of the eight-digit SHA-1 codes in RFC 6238 appendix B
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        for (time, code) in [
+            (59, "287082"),
+            (1_111_111_109, "081804"),
+            (1_111_111_111, "050471"),
+            (1_234_567_890, "005924"),
+            (2_000_000_000, "279037"),
+            (20_000_000_000, "353130"),
+        ] {
+            assert_eq!(
+                verify_totp(&secret, code, time),
+                Some(time / TOTP_PERIOD_SECONDS),
+                "{time}"
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_clock_drift() {
+        let secret = generate_secret();
+        let now = 1_700_000_000;
+        let code = code_at(&secret, now);
+        let step = now / TOTP_PERIOD_SECONDS;
+
+        assert_eq!(verify_totp(&secret, &code, now - 30), Some(step));
+        assert_eq!(verify_totp(&secret, &code, now + 30), Some(step));
+        assert_eq!(verify_totp(&secret, &code, now - 60), None);
+        assert_eq!(verify_totp(&secret, &code, now + 60), None);
+        assert_eq!(verify_totp(&secret, &format!(" {code}"), now), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_reused_code() {
+        let secret = generate_secret();
+        let code = code_at(&secret, Utc::now().timestamp());
+        // The second claim finds the step already used
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([updated(1), updated(0)])
+            .into_connection();
+
+        assert!(verify_second_factor(&db, &user(&secret), &code)
+            .await
+            .unwrap());
+        assert!(!verify_second_factor(&db, &user(&secret), &code)
+            .await
+            .unwrap());
+
+        let log = db.into_transaction_log();
+        let sql = &log[0].statements()[0].sql;
+        assert!(
+            sql.starts_with("UPDATE \"user\" SET \"totp_last_step\""),
+            "{sql}"
+        );
+        assert!(sql.contains("\"totp_last_step\" < $"), "{sql}");
+    }
+
+    #[tokio::test]
+    async fn recovery_codes_work_once() {
+        let code = generate_recovery_code();
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([updated(1), updated(0)])
+            .into_connection();
+        let user = user(&generate_secret());
+
+        assert!(verify_second_factor(&db, &user, &code).await.unwrap());
+        assert!(!verify_second_factor(&db, &user, &code).await.unwrap());
+
+        let log = db.into_transaction_log();
+        let statement = &log[0].statements()[0];
+        assert!(
+            statement.sql.contains("\"used_at\" IS NULL"),
+            "{}",
+            statement.sql
+        );
+        assert!(statement
+            .values
+            .as_ref()
+            .unwrap()
+            .0
+            .contains(&hash_recovery_code(&code).into()));
+        // Case and separators are ignored
+        assert_eq!(
+            hash_recovery_code(&code.to_uppercase().replace('-', " ")),
+            hash_recovery_code(&code)
+        );
+    }
+}
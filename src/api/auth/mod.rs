@@ -0,0 +1,15 @@
+pub mod api_keys;
+pub mod keys;
+pub mod mfa;
+pub mod oidc;
+pub mod password;
+pub mod revocation;
+pub mod throttle;
+pub mod tokens;
+
+/// The prefix of the username that is reserved for principals other than local users, if any
+pub fn reserved_prefix(username: &str) -> Option<&'static str> {
+    [api_keys::PRINCIPAL_PREFIX, oidc::PRINCIPAL_PREFIX]
+        .into_iter()
+        .find(|prefix| username.starts_with(prefix))
+}
@@ -0,0 +1,369 @@
+use crate::api::response::TokenClaims;
+use crate::entities::user::Role;
+use crate::settings::Oidc;
+
+use anyhow::{anyhow, bail, Context};
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use sea_orm::Iterable;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// The shortest interval between fetches prompted by an unknown `kid` or a failed fetch
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Starts the `sub` of provider identities, which are named `oidc:<issuer>:<username>` so that
+/// they can never be mistaken for a local user of the same name
+pub const PRINCIPAL_PREFIX: &str = "oidc:";
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct RawJwkSet {
+    keys: Vec<Value>,
+}
+
+struct CachedKeys {
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Validates access tokens issued by an external OpenID Connect provider
+///
+/// The provider's signing keys are located through its discovery document and cached for
+/// `jwks_cache_seconds`, or until a token names a key the cache doesn't hold.
+pub struct OidcVerifier {
+    settings: Oidc,
+    role_mapping: HashMap<String, Role>,
+    default_role: Option<Role>,
+    client: reqwest::Client,
+    keys: RwLock<Option<Arc<CachedKeys>>>,
+    /// The time of the last fetch, locked so that concurrent requests share a single refresh
+    refresh: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl OidcVerifier {
+    pub fn new(settings: &Oidc) -> anyhow::Result<Self> {
+        let role_mapping = settings
+            .role_mapping
+            .iter()
+            .map(|(name, role)| Ok((name.clone(), role.parse()?)))
+            .collect::<anyhow::Result<_>>()?;
+        let default_role = settings
+            .default_role
+            .as_deref()
+            .map(str::parse)
+            .transpose()?;
+
+        Ok(Self {
+            settings: settings.clone(),
+            role_mapping,
+            default_role,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            keys: RwLock::new(None),
+            refresh: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Validates a provider token and maps it to local claims
+    pub async fn verify(&self, token: &str) -> anyhow::Result<TokenClaims> {
+        let header = decode_header(token)?;
+        let cached = self.keys(header.kid.as_deref()).await?;
+        let jwk = match &header.kid {
+            Some(kid) => cached.jwks.find(kid),
+            None if cached.jwks.keys.len() == 1 => cached.jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| anyhow!("No provider key matches the token"))?;
+
+        // A shared secret in the key set would let anyone who can read it mint tokens
+        if matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) {
+            bail!("Provider keys must be asymmetric");
+        }
+
+        let mut validation = Validation::new(jwk.common.algorithm.unwrap_or(header.alg));
+        validation.set_issuer(&[&self.settings.issuer]);
+        validation.set_audience(&[&self.settings.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+        let claims =
+            decode::<HashMap<String, Value>>(token, &DecodingKey::from_jwk(jwk)?, &validation)?
+                .claims;
+
+        self.map_claims(token, &claims)
+    }
+
+    fn map_claims(
+        &self,
+        token: &str,
+        claims: &HashMap<String, Value>,
+    ) -> anyhow::Result<TokenClaims> {
+        let username = claims
+            .get(&self.settings.username_claim)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Missing \"{}\" claim", self.settings.username_claim))?;
+        let role = self
+            .role(claims)
+            .ok_or_else(|| anyhow!("No local role is mapped for \"{username}\""))?;
+        let sub = format!("{PRINCIPAL_PREFIX}{}:{username}", self.settings.issuer);
+        let exp = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow!("Missing \"exp\" claim"))?;
+        // Without an issue time, any revocation of the user applies to the token
+        let iat = claims.get("iat").and_then(Value::as_f64).unwrap_or(0.0);
+
+        // Provider token IDs need not be UUIDs, so others are replaced with a
+        // digest of the token, which still lets it be revoked at logout
+        let jti = claims
+            .get("jti")
+            .and_then(Value::as_str)
+            .and_then(|jti| Uuid::parse_str(jti).ok())
+            .unwrap_or_else(|| {
+                let mut bytes = [0u8; 16];
+                bytes.copy_from_slice(&Sha256::digest(token.as_bytes())[..16]);
+                Uuid::from_bytes(bytes)
+            });
+
+        Ok(TokenClaims {
+            jti,
+            sub,
+            role,
+            iat,
+            exp: exp as usize,
+        })
+    }
+
+    /// Picks the most privileged local role mapped from the role claim
+    fn role(&self, claims: &HashMap<String, Value>) -> Option<Role> {
+        let mut path = self.settings.role_claim.split('.');
+        let mut value = path.next().and_then(|name| claims.get(name));
+        for segment in path {
+            value = value.and_then(|value| value.get(segment));
+        }
+
+        let names: Vec<&str> = match value {
+            Some(Value::String(name)) => vec![name],
+            Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let mapped: Vec<Role> = names
+            .into_iter()
+            .filter_map(|name| self.role_mapping.get(name).copied())
+            .collect();
+
+        // Roles are declared from most to least privileged
+        Role::iter()
+            .find(|role| mapped.contains(role))
+            .or(self.default_role)
+    }
+
+    /// Returns the provider's keys, fetching them if the cache is stale or lacks the `kid`
+    async fn keys(&self, kid: Option<&str>) -> anyhow::Result<Arc<CachedKeys>> {
+        if let Some(cached) = self.cached_keys(kid) {
+            return Ok(cached);
+        }
+
+        let mut last_attempt = self.refresh.lock().await;
+
+        // Another request may have refreshed the keys while this one waited
+        if let Some(cached) = self.cached_keys(kid) {
+            return Ok(cached);
+        }
+        let stale = self.keys.read().unwrap().clone();
+        if let (Some(stale), Some(attempt)) = (&stale, *last_attempt) {
+            if attempt.elapsed() < MIN_REFRESH_INTERVAL {
+                return Ok(stale.clone());
+            }
+        }
+
+        *last_attempt = Some(Instant::now());
+        match self.fetch().await {
+            Ok(jwks) => {
+                let cached = Arc::new(CachedKeys {
+                    jwks,
+                    fetched_at: Instant::now(),
+                });
+                *self.keys.write().unwrap() = Some(cached.clone());
+                Ok(cached)
+            }
+            // Keeps verifying with the old keys while the provider is unreachable
+            Err(err) => match stale {
+                Some(stale) => {
+                    tracing::warn!("Failed to refresh OIDC provider keys: {err:#}");
+                    Ok(stale)
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    fn cached_keys(&self, kid: Option<&str>) -> Option<Arc<CachedKeys>> {
+        let cached = self.keys.read().unwrap().clone()?;
+        let fresh =
+            cached.fetched_at.elapsed() < Duration::from_secs(self.settings.jwks_cache_seconds);
+        let known = kid.is_none_or(|kid| cached.jwks.find(kid).is_some());
+        (fresh && known).then_some(cached)
+    }
+
+    async fn fetch(&self) -> anyhow::Result<JwkSet> {
+        let issuer = self.settings.issuer.trim_end_matches('/');
+        let discovery: Discovery = self
+            .get_json(&format!("{issuer}/.well-known/openid-configuration"))
+            .await?;
+        if discovery.issuer != self.settings.issuer {
+            bail!("Discovery document is for issuer \"{}\"", discovery.issuer);
+        }
+
+        // Skips keys this crate cannot use, such as encryption keys
+        let raw: RawJwkSet = self.get_json(&discovery.jwks_uri).await?;
+        let keys = raw
+            .keys
+            .into_iter()
+            .filter_map(|key| serde_json::from_value::<Jwk>(key).ok())
+            .collect();
+
+        Ok(JwkSet { keys })
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        serde_json::from_str(&body).with_context(|| format!("Invalid JSON from {url}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use chrono::Utc;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/oidc")
+            .join(name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    /// Serves the fixture discovery and JWKS documents and returns the issuer URL
+    fn stub_issuer() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let discovery = fixture("openid-configuration.json").replace("{issuer}", &issuer);
+        let jwks = fixture("jwks.json");
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { discovery }),
+            )
+            .route("/jwks.json", get(move || async move { jwks }));
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        issuer
+    }
+
+    fn verifier(issuer: &str) -> OidcVerifier {
+        OidcVerifier::new(&Oidc {
+            issuer: issuer.to_string(),
+            audience: "api-doc".to_string(),
+            username_claim: "preferred_username".to_string(),
+            role_claim: "realm_access.roles".to_string(),
+            role_mapping: HashMap::from([
+                ("doctor".to_string(), "clinician".to_string()),
+                ("reception".to_string(), "front_desk".to_string()),
+            ]),
+            default_role: None,
+            jwks_cache_seconds: 3_600,
+        })
+        .unwrap()
+    }
+
+    fn sign(claims: Value) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some("stub-key".to_string());
+        let key = EncodingKey::from_ec_pem(fixture("signing-key.pem").as_bytes()).unwrap();
+        encode(&header, &claims, &key).unwrap()
+    }
+
+    fn claims(issuer: &str, audience: &str, roles: &[&str]) -> Value {
+        let now = Utc::now().timestamp();
+        json!({
+            "iss": issuer,
+            "aud": audience,
+            "sub": "8c1d5a40",
+            "preferred_username": "jdoe",
+            "iat": now,
+            "exp": now + 300,
+            "realm_access": { "roles": roles },
+        })
+    }
+
+    #[tokio::test]
+    async fn maps_provider_token_to_most_privileged_role() {
+        let issuer = stub_issuer();
+        let verifier = verifier(&issuer);
+
+        let token = sign(claims(
+            &issuer,
+            "api-doc",
+            &["reception", "doctor", "other"],
+        ));
+        let claims = verifier.verify(&token).await.unwrap();
+
+        assert_eq!(claims.sub, format!("oidc:{issuer}:jdoe"));
+        assert_eq!(claims.role, Role::Clinician);
+    }
+
+    #[tokio::test]
+    async fn provider_users_never_share_a_local_username() {
+        let issuer = stub_issuer();
+        let verifier = verifier(&issuer);
+
+        let mut admin = claims(&issuer, "api-doc", &["doctor"]);
+        admin["preferred_username"] = json!("admin");
+        let claims = verifier.verify(&sign(admin)).await.unwrap();
+
+        assert_ne!(claims.sub, "admin");
+        assert!(claims.is_federated());
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_issuer_audience_or_unmapped_role() {
+        let issuer = stub_issuer();
+        let verifier = verifier(&issuer);
+
+        let wrong_issuer = sign(claims("http://elsewhere", "api-doc", &["doctor"]));
+        let wrong_audience = sign(claims(&issuer, "another-api", &["doctor"]));
+        let unmapped = sign(claims(&issuer, "api-doc", &["other"]));
+
+        assert!(verifier.verify(&wrong_issuer).await.is_err());
+        assert!(verifier.verify(&wrong_audience).await.is_err());
+        assert!(verifier.verify(&unmapped).await.is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::api::response::error::AppError;
+use crate::entities::{password_history, user};
+use crate::settings::PasswordPolicy;
+
+use anyhow::anyhow;
+use argon2::Argon2;
+use axum::http::StatusCode;
+use password_hash::rand_core::OsRng;
+use password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DbErr, EntityTrait,
+    QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use std::sync::OnceLock;
+
+/// Commonly used passwords that are always rejected, compared case-insensitively
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456789012",
+    "1q2w3e4r5t6y",
+    "abc123abc123",
+    "admin123456",
+    "apidocpass",
+    "changeme123",
+    "iloveyou1234",
+    "letmein12345",
+    "password",
+    "password1",
+    "password12",
+    "password123",
+    "password1234",
+    "passw0rd1234",
+    "qwerty123456",
+    "qwertyuiop12",
+    "welcome12345",
+];
+
+/// Why a new password was rejected
+#[derive(Debug)]
+pub enum PasswordError {
+    /// The password breaks one or more policy rules
+    Policy(Vec<String>),
+    /// The password matches the current or a recent password
+    Reused,
+    Db(DbErr),
+    Hash(anyhow::Error),
+}
+
+impl From<DbErr> for PasswordError {
+    fn from(err: DbErr) -> Self {
+        Self::Db(err)
+    }
+}
+
+impl PasswordError {
+    pub fn into_app_error(self) -> AppError {
+        match self {
+            PasswordError::Policy(violations) => AppError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                anyhow!("Password does not meet the password policy: {}", violations.join("; ")),
+            ),
+            PasswordError::Reused => AppError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                anyhow!("Password matches a recently used password"),
+            ),
+            PasswordError::Db(err) => AppError(StatusCode::INTERNAL_SERVER_ERROR, err.into()),
+            PasswordError::Hash(err) => AppError(StatusCode::INTERNAL_SERVER_ERROR, err),
+        }
+    }
+}
+
+/// Hashes a password with Argon2 and a random salt
+pub fn encrypt_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let argon2 = Argon2::default();
+
+    if let Ok(hash) = argon2.hash_password(password.as_bytes(), &salt) {
+        Ok(hash.to_string())
+    } else {
+        Err(anyhow!("Failed to hash password"))
+    }
+}
+
+/// A hash of an unguessable password to verify against when the user doesn't exist
+///
+/// Verifying against it takes as long as verifying a real password, so response times don't
+/// reveal which usernames exist.
+pub fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        encrypt_password(&crate::api::auth::tokens::generate_token())
+            .expect("Failed to hash dummy password")
+    })
+}
+
+/// Verifies a password against a stored Argon2 hash
+pub fn validate_password(password: &str, hash: &str) -> anyhow::Result<()> {
+    let argon2 = Argon2::default();
+    let parsed_hash = PasswordHash::new(hash).map_err(|e| anyhow!(e.to_string()))?;
+
+    argon2
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .map_err(|_e| anyhow!("Failed to verify password"))?;
+
+    Ok(())
+}
+
+/// Checks a password against the policy, returning every rule it breaks
+pub fn check_policy(policy: &PasswordPolicy, password: &str) -> Result<(), PasswordError> {
+    let mut violations = Vec::new();
+
+    if password.chars().count() < policy.min_length {
+        violations.push(format!("must be at least {} characters", policy.min_length));
+    }
+    if policy.require_uppercase && !password.chars().any(char::is_uppercase) {
+        violations.push("must contain an uppercase letter".to_string());
+    }
+    if policy.require_lowercase && !password.chars().any(char::is_lowercase) {
+        violations.push("must contain a lowercase letter".to_string());
+    }
+    if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        violations.push("must contain a digit".to_string());
+    }
+    if policy.require_symbol && password.chars().all(char::is_alphanumeric) {
+        violations.push("must contain a symbol".to_string());
+    }
+
+    let lowered = password.to_lowercase();
+    let denied = COMMON_PASSWORDS.iter().any(|common| *common == lowered)
+        || policy
+            .deny_list
+            .iter()
+            .any(|denied| denied.to_lowercase() == lowered);
+    if denied {
+        violations.push("must not be a commonly used password".to_string());
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(PasswordError::Policy(violations))
+    }
+}
+
+/// Replaces a user's password after checking it against the policy and recent passwords
+///
+/// The replaced hash moves into the password history, which is trimmed to the policy size, in the
+/// same transaction as the new hash is stored.
+pub async fn change_password<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    policy: &PasswordPolicy,
+    user: user::Model,
+    new_password: &str,
+) -> Result<user::Model, PasswordError> {
+    check_policy(policy, new_password)?;
+
+    // The current password counts toward the history size
+    let kept = policy.history_size.saturating_sub(1) as u64;
+    if policy.history_size > 0 {
+        let previous = password_history::Entity::find()
+            .filter(password_history::Column::UserId.eq(user.id))
+            .order_by_desc(password_history::Column::Id)
+            .limit(kept)
+            .all(db)
+            .await?;
+
+        let reused = std::iter::once(user.password.as_str())
+            .chain(previous.iter().map(|entry| entry.password.as_str()))
+            .any(|hash| validate_password(new_password, hash).is_ok());
+        if reused {
+            return Err(PasswordError::Reused);
+        }
+    }
+
+    let user_id = user.id;
+    let old_hash = user.password.clone();
+    let mut active: user::ActiveModel = user.into();
+    active.password = Set(encrypt_password(new_password).map_err(PasswordError::Hash)?);
+    let txn = db.begin().await?;
+    let user = active.update(&txn).await?;
+
+    if kept > 0 {
+        password_history::ActiveModel {
+            user_id: Set(user_id),
+            password: Set(old_hash),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+    }
+
+    // Drops entries that have aged out of the history
+    let expired: Vec<i32> = password_history::Entity::find()
+        .select_only()
+        .column(password_history::Column::Id)
+        .filter(password_history::Column::UserId.eq(user_id))
+        .order_by_desc(password_history::Column::Id)
+        .offset(kept)
+        .into_tuple()
+        .all(&txn)
+        .await?;
+    if !expired.is_empty() {
+        password_history::Entity::delete_many()
+            .filter(password_history::Column::Id.is_in(expired))
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await?;
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn documented_example_meets_the_default_policy() {
+        let policy = PasswordPolicy::default();
+        assert!(check_policy(&policy, "Correct-Horse-Battery-9").is_ok());
+        assert!(check_policy(&policy, "correct horse battery staple").is_err());
+    }
+
+    #[tokio::test]
+    async fn stores_the_hash_and_history_in_one_transaction() {
+        let user = user::Model {
+            id: 1,
+            username: "jdoe".to_string(),
+            password: encrypt_password("Old-Password-1").unwrap(),
+            role: user::Role::Clinician,
+            active_flag: true,
+            created_at: Utc::now(),
+            tokens_revoked_at: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+        };
+        let policy = PasswordPolicy {
+            history_size: 2,
+            ..Default::default()
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<password_history::Model>::new()])
+            .append_query_results([[user.clone()]])
+            .append_query_results([[password_history::Model {
+                id: 1,
+                user_id: 1,
+                password: user.password.clone(),
+                created_at: Utc::now(),
+            }]])
+            .append_query_results([Vec::<BTreeMap<&str, sea_orm::Value>>::new()])
+            .into_connection();
+
+        change_password(&db, &policy, user, "New-Password-2")
+            .await
+            .unwrap();
+
+        let log = db.into_transaction_log();
+        let statements: Vec<&str> = log
+            .last()
+            .unwrap()
+            .statements()
+            .iter()
+            .map(|statement| statement.sql.as_str())
+            .collect();
+        assert_eq!(statements.first(), Some(&"BEGIN"));
+        assert!(statements[1].starts_with("UPDATE \"user\""));
+        assert!(statements[2].starts_with("INSERT INTO \"password_history\""));
+        assert_eq!(statements.last(), Some(&"COMMIT"));
+    }
+}
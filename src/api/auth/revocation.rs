@@ -0,0 +1,298 @@
+use crate::api::response::TokenClaims;
+use crate::entities::{refresh_token, revoked_token, revoked_user, user};
+use crate::state::ApplicationState;
+
+use chrono::{DateTime, SubsecRound, TimeZone, Utc};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DbErr, EntityTrait,
+    QueryFilter, TransactionTrait,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often the cache is pruned and reloaded from the database
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// In-memory mirror of the revoked access tokens persisted in Postgres
+///
+/// Local revocations take effect immediately; revocations made by other instances are picked
+/// up on the next sync.
+#[derive(Default)]
+pub struct RevocationList {
+    /// Revoked token IDs mapped to the expiry of the token
+    tokens: RwLock<HashMap<Uuid, DateTime<Utc>>>,
+    /// Usernames mapped to the instant before which all their tokens are revoked
+    users: RwLock<HashMap<String, DateTime<Utc>>>,
+}
+
+impl RevocationList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_revoked(&self, claims: &TokenClaims) -> bool {
+        if self.tokens.read().unwrap().contains_key(&claims.jti) {
+            return true;
+        }
+
+        match self.users.read().unwrap().get(&claims.sub) {
+            Some(cutoff) => claims.iat <= epoch_seconds(*cutoff),
+            None => false,
+        }
+    }
+
+    /// Revokes a single access token until it expires
+    pub async fn revoke_token<C: ConnectionTrait>(
+        &self,
+        db: &C,
+        claims: &TokenClaims,
+    ) -> Result<(), DbErr> {
+        let expires_at = timestamp(claims.exp);
+
+        revoked_token::Entity::insert(revoked_token::ActiveModel {
+            jti: Set(claims.jti),
+            username: Set(claims.sub.clone()),
+            expires_at: Set(expires_at),
+            revoked_at: Set(Utc::now()),
+        })
+        .on_conflict(
+            OnConflict::column(revoked_token::Column::Jti)
+                .do_nothing()
+                .to_owned(),
+        )
+        .do_nothing()
+        .exec(db)
+        .await?;
+
+        self.tokens.write().unwrap().insert(claims.jti, expires_at);
+        Ok(())
+    }
+
+    /// Revokes every access and refresh token issued to the user so far
+    ///
+    /// The cutoff is kept by username, apart from the user, so it still applies on other
+    /// instances once the user is deleted.
+    pub async fn revoke_user<C: ConnectionTrait + TransactionTrait>(
+        &self,
+        db: &C,
+        user: user::Model,
+    ) -> Result<(), DbErr> {
+        // Postgres keeps microseconds, so the cached cutoff matches the stored one
+        let now = Utc::now().trunc_subsecs(6);
+
+        let username = user.username.clone();
+        let user_id = user.id;
+        let txn = db.begin().await?;
+
+        let mut active: user::ActiveModel = user.into();
+        active.tokens_revoked_at = Set(Some(now));
+        active.update(&txn).await?;
+
+        revoked_user::Entity::insert(revoked_user::ActiveModel {
+            username: Set(username.clone()),
+            revoked_at: Set(now),
+        })
+        .on_conflict(
+            OnConflict::column(revoked_user::Column::Username)
+                .update_column(revoked_user::Column::RevokedAt)
+                .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await?;
+
+        refresh_token::Entity::update_many()
+            .col_expr(refresh_token::Column::RevokedAt, Expr::value(now))
+            .filter(refresh_token::Column::UserId.eq(user_id))
+            .filter(refresh_token::Column::RevokedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+
+        self.users.write().unwrap().insert(username, now);
+        Ok(())
+    }
+
+    /// Deletes expired revocations and reloads the cache from the database
+    pub async fn sync<C: ConnectionTrait>(
+        &self,
+        db: &C,
+        token_timeout_seconds: i64,
+    ) -> Result<(), DbErr> {
+        let now = Utc::now();
+
+        // A revoked token that has expired fails validation on its own
+        revoked_token::Entity::delete_many()
+            .filter(revoked_token::Column::ExpiresAt.lte(now))
+            .exec(db)
+            .await?;
+
+        let tokens = revoked_token::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|revoked| (revoked.jti, revoked.expires_at))
+            .collect();
+
+        // Every token issued before an older cutoff has expired
+        let oldest_live_token = now - chrono::Duration::seconds(token_timeout_seconds);
+        revoked_user::Entity::delete_many()
+            .filter(revoked_user::Column::RevokedAt.lte(oldest_live_token))
+            .exec(db)
+            .await?;
+
+        let users = revoked_user::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|revoked| (revoked.username, revoked.revoked_at))
+            .collect();
+
+        *self.tokens.write().unwrap() = tokens;
+        *self.users.write().unwrap() = users;
+        Ok(())
+    }
+}
+
+/// Periodically prunes and reloads the revocation list for the lifetime of the server
+pub fn spawn_sync(state: Arc<ApplicationState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SYNC_INTERVAL);
+        loop {
+            interval.tick().await;
+            let timeout = state.settings.load().token_timeout_seconds;
+            if let Err(err) = state
+                .revocations
+                .sync(state.db_conn.load().as_ref(), timeout)
+                .await
+            {
+                tracing::error!("Failed to sync token revocation list: {err}");
+            }
+        }
+    });
+}
+
+/// Seconds since the epoch, to the microsecond, as used for the `iat` claim
+///
+/// Whole seconds can't tell apart the tokens issued just before and just after a user's tokens
+/// are revoked.
+pub fn epoch_seconds(instant: DateTime<Utc>) -> f64 {
+    instant.timestamp_micros() as f64 / 1_000_000.0
+}
+
+fn timestamp(secs: usize) -> DateTime<Utc> {
+    Utc.timestamp_opt(secs as i64, 0)
+        .single()
+        .unwrap_or_else(Utc::now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::user::Role;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Transaction};
+
+    fn user() -> user::Model {
+        user::Model {
+            id: 1,
+            username: "jdoe".to_string(),
+            password: String::new(),
+            role: Role::Clinician,
+            active_flag: true,
+            created_at: Utc::now(),
+            tokens_revoked_at: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+        }
+    }
+
+    fn claims(sub: &str, issued_at: DateTime<Utc>) -> TokenClaims {
+        TokenClaims {
+            jti: Uuid::new_v4(),
+            sub: sub.to_string(),
+            role: Role::Clinician,
+            iat: epoch_seconds(issued_at),
+            exp: usize::MAX,
+        }
+    }
+
+    fn executed(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    fn statements(log: &[Transaction]) -> Vec<String> {
+        log.iter()
+            .flat_map(|transaction| transaction.statements())
+            .map(|statement| statement.sql.clone())
+            .collect()
+    }
+
+    #[test]
+    fn accepts_tokens_issued_in_the_same_second_after_the_cutoff() {
+        let cutoff = Utc.timestamp_opt(1_750_000_000, 500_000_000).unwrap();
+        let list = RevocationList::new();
+        list.users
+            .write()
+            .unwrap()
+            .insert("jdoe".to_string(), cutoff);
+
+        let before = cutoff - chrono::Duration::milliseconds(100);
+        let after = cutoff + chrono::Duration::milliseconds(100);
+        assert!(list.is_revoked(&claims("jdoe", before)));
+        assert!(list.is_revoked(&claims("jdoe", cutoff)));
+        assert!(!list.is_revoked(&claims("jdoe", after)));
+        assert!(!list.is_revoked(&claims("asmith", before)));
+    }
+
+    #[tokio::test]
+    async fn revokes_a_user_in_one_transaction() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[user::Model {
+                tokens_revoked_at: Some(Utc::now()),
+                ..user()
+            }]])
+            .append_exec_results([executed(1), executed(2)])
+            .into_connection();
+
+        let list = RevocationList::new();
+        list.revoke_user(&db, user()).await.unwrap();
+        assert!(list.is_revoked(&claims("jdoe", Utc::now() - chrono::Duration::seconds(1))));
+
+        let statements = statements(&db.into_transaction_log());
+        assert_eq!(statements.first().unwrap(), "BEGIN");
+        assert!(statements[1].starts_with(r#"UPDATE "user""#));
+        assert!(statements[2].starts_with(r#"INSERT INTO "revoked_user""#));
+        assert!(statements[3].starts_with(r#"UPDATE "refresh_token""#));
+        assert_eq!(statements.last().unwrap(), "COMMIT");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_cutoff_of_a_deleted_user() {
+        let cutoff = Utc::now().trunc_subsecs(6) - chrono::Duration::minutes(5);
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([executed(0)])
+            .append_query_results([Vec::<revoked_token::Model>::new()])
+            .append_exec_results([executed(1)])
+            .append_query_results([[revoked_user::Model {
+                username: "deleted".to_string(),
+                revoked_at: cutoff,
+            }]])
+            .into_connection();
+
+        let list = RevocationList::new();
+        list.sync(&db, 3600).await.unwrap();
+        assert!(list.is_revoked(&claims("deleted", cutoff - chrono::Duration::seconds(1))));
+        assert!(!list.is_revoked(&claims("deleted", cutoff + chrono::Duration::seconds(1))));
+
+        let statements = statements(&db.into_transaction_log());
+        assert!(statements[2].starts_with(r#"DELETE FROM "revoked_user""#));
+        assert!(statements[3].starts_with(r#"SELECT "revoked_user""#));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::settings::LoginThrottle;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tables larger than this are pruned of stale entries on the next failure
+const PRUNE_THRESHOLD: usize = 1024;
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    /// Whether the failures no longer count toward a lockout
+    fn is_stale(&self, throttle: &LoginThrottle, now: Instant) -> bool {
+        match self.locked_until {
+            Some(locked_until) => now >= locked_until,
+            None => now.duration_since(self.last) >= Duration::from_secs(throttle.failure_window_seconds),
+        }
+    }
+}
+
+/// In-memory counters of failed logins per username and per client IP
+#[derive(Default)]
+pub struct FailedLogins {
+    usernames: Mutex<HashMap<String, Failures>>,
+    ips: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl FailedLogins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how long the client must wait before it may attempt to log in again
+    pub fn retry_after(
+        &self,
+        throttle: &LoginThrottle,
+        username: &str,
+        ip: Option<IpAddr>,
+    ) -> Option<Duration> {
+        self.retry_after_at(throttle, username, ip, Instant::now())
+    }
+
+    fn retry_after_at(
+        &self,
+        throttle: &LoginThrottle,
+        username: &str,
+        ip: Option<IpAddr>,
+        now: Instant,
+    ) -> Option<Duration> {
+        let username_wait = self
+            .usernames
+            .lock()
+            .unwrap()
+            .get(username)
+            .filter(|failures| !failures.is_stale(throttle, now))
+            .and_then(|failures| match failures.locked_until {
+                Some(locked_until) => Some(locked_until - now),
+                None => {
+                    // Doubles the required delay with each failure
+                    let exponent = failures.count.saturating_sub(1).min(16);
+                    let backoff = throttle
+                        .backoff_base_milliseconds
+                        .saturating_mul(1 << exponent)
+                        .min(throttle.backoff_max_milliseconds);
+                    (failures.last + Duration::from_millis(backoff))
+                        .checked_duration_since(now)
+                        .filter(|wait| !wait.is_zero())
+                }
+            });
+
+        let ip_wait = ip.and_then(|ip| {
+            self.ips
+                .lock()
+                .unwrap()
+                .get(&ip)
+                .filter(|failures| !failures.is_stale(throttle, now))
+                .and_then(|failures| failures.locked_until)
+                .map(|locked_until| locked_until - now)
+        });
+
+        username_wait.max(ip_wait)
+    }
+
+    pub fn record_failure(&self, throttle: &LoginThrottle, username: &str, ip: Option<IpAddr>) {
+        self.record_failure_at(throttle, username, ip, Instant::now());
+    }
+
+    fn record_failure_at(
+        &self,
+        throttle: &LoginThrottle,
+        username: &str,
+        ip: Option<IpAddr>,
+        now: Instant,
+    ) {
+        let lockout = Duration::from_secs(throttle.lockout_seconds);
+
+        record(
+            &mut self.usernames.lock().unwrap(),
+            username.to_string(),
+            throttle,
+            throttle.max_failures,
+            lockout,
+            now,
+        );
+        if let Some(ip) = ip {
+            record(
+                &mut self.ips.lock().unwrap(),
+                ip,
+                throttle,
+                throttle.ip_max_failures,
+                lockout,
+                now,
+            );
+        }
+    }
+
+    /// Clears the username's failures; IP failures only decay, so that a
+    /// client cannot reset them by logging in to an account it controls
+    pub fn record_success(&self, username: &str) {
+        self.usernames.lock().unwrap().remove(username);
+    }
+}
+
+fn record<K: Eq + Hash>(
+    table: &mut HashMap<K, Failures>,
+    key: K,
+    throttle: &LoginThrottle,
+    max_failures: u32,
+    lockout: Duration,
+    now: Instant,
+) {
+    if table.len() > PRUNE_THRESHOLD {
+        table.retain(|_, failures| !failures.is_stale(throttle, now));
+    }
+
+    let failures = table.entry(key).or_insert(Failures {
+        count: 0,
+        last: now,
+        locked_until: None,
+    });
+    if failures.is_stale(throttle, now) {
+        failures.count = 0;
+        failures.locked_until = None;
+    }
+
+    failures.count += 1;
+    failures.last = now;
+    if failures.count >= max_failures {
+        failures.locked_until = Some(now + lockout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 9)));
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            max_failures: 5,
+            ip_max_failures: 8,
+            lockout_seconds: 900,
+            failure_window_seconds: 900,
+            backoff_base_milliseconds: 500,
+            backoff_max_milliseconds: 3_000,
+        }
+    }
+
+    /// Fails `count` logins for the username from the IP, all at `now`
+    fn fail(logins: &FailedLogins, username: &str, count: u32, now: Instant) {
+        for _ in 0..count {
+            logins.record_failure_at(&throttle(), username, IP, now);
+        }
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_cap() {
+        let logins = FailedLogins::new();
+        let now = Instant::now();
+
+        for (failures, backoff) in [(1, 500), (2, 1_000), (3, 2_000), (4, 3_000)] {
+            fail(&logins, "jdoe", 1, now);
+            assert_eq!(
+                logins.retry_after_at(&throttle(), "jdoe", None, now),
+                Some(Duration::from_millis(backoff)),
+                "after {failures} failures"
+            );
+        }
+
+        let later = now + Duration::from_millis(3_000);
+        assert_eq!(
+            logins.retry_after_at(&throttle(), "jdoe", None, later),
+            None
+        );
+    }
+
+    #[test]
+    fn locks_out_a_username_after_max_failures() {
+        let logins = FailedLogins::new();
+        let now = Instant::now();
+
+        fail(&logins, "jdoe", 5, now);
+        assert_eq!(
+            logins.retry_after_at(&throttle(), "jdoe", None, now),
+            Some(Duration::from_secs(900))
+        );
+        assert_eq!(
+            logins.retry_after_at(&throttle(), "asmith", None, now),
+            None
+        );
+    }
+
+    #[test]
+    fn locks_out_an_ip_after_ip_max_failures() {
+        let logins = FailedLogins::new();
+        let now = Instant::now();
+
+        // Spread over usernames so that none of them is locked out itself
+        for username in ["a", "b", "c", "d"] {
+            fail(&logins, username, 2, now);
+        }
+        assert_eq!(
+            logins.retry_after_at(&throttle(), "e", IP, now),
+            Some(Duration::from_secs(900))
+        );
+        assert_eq!(logins.retry_after_at(&throttle(), "e", None, now), None);
+    }
+
+    #[test]
+    fn forgets_the_username_failures_after_a_success() {
+        let logins = FailedLogins::new();
+        let now = Instant::now();
+
+        fail(&logins, "jdoe", 4, now);
+        logins.record_success("jdoe");
+        assert_eq!(logins.retry_after_at(&throttle(), "jdoe", None, now), None);
+
+        // The count starts over, so one more failure doesn't lock the username out
+        fail(&logins, "jdoe", 1, now);
+        assert_eq!(
+            logins.retry_after_at(&throttle(), "jdoe", None, now),
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn ends_the_lockout_once_it_passes() {
+        let logins = FailedLogins::new();
+        let now = Instant::now();
+
+        fail(&logins, "jdoe", 8, now);
+        let almost = now + Duration::from_secs(899);
+        assert_eq!(
+            logins.retry_after_at(&throttle(), "jdoe", IP, almost),
+            Some(Duration::from_secs(1))
+        );
+
+        let after = now + Duration::from_secs(900);
+        assert_eq!(logins.retry_after_at(&throttle(), "jdoe", IP, after), None);
+
+        // Failures after the lockout count from zero again
+        fail(&logins, "jdoe", 1, after);
+        assert_eq!(
+            logins.retry_after_at(&throttle(), "jdoe", IP, after),
+            Some(Duration::from_millis(500))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use crate::api::auth::keys::SigningKeys;
+use crate::api::auth::revocation;
+use crate::api::response::TokenClaims;
+use crate::entities::{refresh_token, user};
+use crate::settings::Settings;
+
+use chrono::Utc;
+use rand::RngCore;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Why a refresh token could not be exchanged
+#[derive(Debug)]
+pub enum RefreshError {
+    /// The token is unknown, expired, or its family was revoked, or the user is disabled
+    Invalid,
+    /// The token was already exchanged, so its whole family has been revoked
+    Reused,
+    Db(DbErr),
+}
+
+impl From<DbErr> for RefreshError {
+    fn from(err: DbErr) -> Self {
+        Self::Db(err)
+    }
+}
+
+/// Signs a short-lived access token for the given user
+pub fn issue_access_token(
+    settings: &Settings,
+    keys: &SigningKeys,
+    user: &user::Model,
+) -> anyhow::Result<String> {
+    let now = Utc::now();
+    let iat = revocation::epoch_seconds(now);
+    let exp = (now + chrono::Duration::seconds(settings.token_timeout_seconds)).timestamp() as usize;
+    let claims = TokenClaims {
+        jti: Uuid::new_v4(),
+        sub: user.username.clone(),
+        role: user.role,
+        exp,
+        iat,
+    };
+
+    let token = keys.sign(&claims)?;
+
+    Ok(token)
+}
+
+/// Stores a new refresh token for the user and returns its plaintext value
+///
+/// Pass the family of the token being rotated, or `None` to start a new family at login
+pub async fn issue_refresh_token<C: ConnectionTrait>(
+    db: &C,
+    settings: &Settings,
+    user_id: i32,
+    family_id: Option<Uuid>,
+) -> Result<String, DbErr> {
+    let token = generate_token();
+    let now = Utc::now();
+
+    refresh_token::ActiveModel {
+        user_id: Set(user_id),
+        family_id: Set(family_id.unwrap_or_else(Uuid::new_v4)),
+        token_hash: Set(hash_token(&token)),
+        created_at: Set(now),
+        expires_at: Set(now + chrono::Duration::seconds(settings.refresh_token_timeout_seconds)),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Exchanges a refresh token for a new one in the same family
+///
+/// Presenting a token that was already exchanged revokes every token in its family, since
+/// either the client or an attacker is holding a stolen copy. The old token is only used up if
+/// its replacement is stored, so a failed exchange leaves the session intact.
+pub async fn rotate_refresh_token(
+    db: &DatabaseConnection,
+    settings: &Settings,
+    presented: &str,
+) -> Result<(user::Model, String), RefreshError> {
+    let now = Utc::now();
+
+    let token = find_refresh_token(db, presented)
+        .await?
+        .ok_or(RefreshError::Invalid)?;
+
+    if token.revoked_at.is_some() || token.expires_at <= now {
+        return Err(RefreshError::Invalid);
+    }
+    if token.replaced_at.is_some() {
+        revoke_family(db, token.family_id).await?;
+        return Err(RefreshError::Reused);
+    }
+
+    // Claims the token atomically so two concurrent exchanges
+    // cannot both succeed, and issues its replacement in the same transaction
+    let txn = db.begin().await?;
+    let claimed = refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::ReplacedAt, Expr::value(now))
+        .filter(refresh_token::Column::Id.eq(token.id))
+        .filter(refresh_token::Column::ReplacedAt.is_null())
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(&txn)
+        .await?;
+    if claimed.rows_affected == 0 {
+        txn.rollback().await?;
+        revoke_family(db, token.family_id).await?;
+        return Err(RefreshError::Reused);
+    }
+
+    let user = user::Entity::find_by_id(token.user_id)
+        .one(&txn)
+        .await?
+        .filter(|user| user.active_flag)
+        .ok_or(RefreshError::Invalid)?;
+
+    let new_token = issue_refresh_token(&txn, settings, user.id, Some(token.family_id)).await?;
+    txn.commit().await?;
+
+    Ok((user, new_token))
+}
+
+/// Looks up a stored refresh token by its plaintext value
+pub async fn find_refresh_token<C: ConnectionTrait>(
+    db: &C,
+    presented: &str,
+) -> Result<Option<refresh_token::Model>, DbErr> {
+    refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(hash_token(presented)))
+        .one(db)
+        .await
+}
+
+/// Revokes every refresh token descended from the same login
+pub async fn revoke_family<C: ConnectionTrait>(db: &C, family_id: Uuid) -> Result<(), DbErr> {
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::FamilyId.eq(family_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Generates 256 bits of randomness encoded as hex
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Digests a token for storage and lookup
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::user::Role;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Transaction};
+
+    fn token(replaced: bool) -> refresh_token::Model {
+        let now = Utc::now();
+        refresh_token::Model {
+            id: 7,
+            user_id: 1,
+            family_id: Uuid::nil(),
+            token_hash: hash_token("presented"),
+            created_at: now,
+            expires_at: now + chrono::Duration::hours(1),
+            replaced_at: replaced.then_some(now),
+            revoked_at: None,
+        }
+    }
+
+    fn user() -> user::Model {
+        user::Model {
+            id: 1,
+            username: "jdoe".to_string(),
+            password: String::new(),
+            role: Role::Clinician,
+            active_flag: true,
+            created_at: Utc::now(),
+            tokens_revoked_at: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+        }
+    }
+
+    fn statements(log: &[Transaction]) -> Vec<String> {
+        log.iter()
+            .flat_map(|transaction| transaction.statements())
+            .map(|statement| statement.sql.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rotates_within_one_transaction() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[token(false)]])
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 1,
+            }])
+            .append_query_results([[user()]])
+            .append_query_results([[refresh_token::Model {
+                id: 8,
+                ..token(false)
+            }]])
+            .into_connection();
+
+        let (user, new_token) = rotate_refresh_token(&db, &Settings::default(), "presented")
+            .await
+            .unwrap();
+        assert_eq!(user.username, "jdoe");
+        assert_ne!(new_token, "presented");
+
+        let statements = statements(&db.into_transaction_log());
+        let begin = statements.iter().position(|sql| sql == "BEGIN").unwrap();
+        let claim = statements
+            .iter()
+            .position(|sql| sql.starts_with("UPDATE"))
+            .unwrap();
+        let insert = statements
+            .iter()
+            .position(|sql| sql.starts_with("INSERT"))
+            .unwrap();
+        let commit = statements.iter().position(|sql| sql == "COMMIT").unwrap();
+        assert!(begin < claim && claim < insert && insert < commit);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_old_token_when_the_new_one_fails_to_store() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[token(false)]])
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 1,
+            }])
+            .append_query_results([[user()]])
+            .append_query_errors([DbErr::Custom("injected failure".to_string())])
+            .into_connection();
+
+        let result = rotate_refresh_token(&db, &Settings::default(), "presented").await;
+        assert!(matches!(result, Err(RefreshError::Db(_))));
+
+        let statements = statements(&db.into_transaction_log());
+        assert!(statements.iter().any(|sql| sql == "ROLLBACK"));
+        assert!(!statements.iter().any(|sql| sql == "COMMIT"));
+    }
+
+    #[tokio::test]
+    async fn reusing_an_exchanged_token_revokes_its_family() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[token(true)]])
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 2,
+            }])
+            .into_connection();
+
+        let result = rotate_refresh_token(&db, &Settings::default(), "presented").await;
+        assert!(matches!(result, Err(RefreshError::Reused)));
+
+        let statements = statements(&db.into_transaction_log());
+        assert_eq!(statements.len(), 2);
+        assert!(statements[1].starts_with("UPDATE") && statements[1].contains("\"revoked_at\""));
+        assert!(statements[1].contains("\"family_id\""));
+        assert!(!statements.iter().any(|sql| sql.starts_with("INSERT")));
+    }
+}
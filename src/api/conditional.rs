@@ -0,0 +1,163 @@
+use crate::api::response::error::AppError;
+
+use anyhow::anyhow;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use opentelemetry::{Key, Value};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+/// Formats a patient version as a strong entity tag, such as `"3"`
+pub fn etag(version: i32) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{version}\""))
+        .expect("a quoted integer is a valid header value")
+}
+
+/// Checks `If-Match` against the current version before a change
+///
+/// Fails with 412 when the header names other versions, so a client can't overwrite a change it
+/// hasn't seen. When `required` is set, a request without the header fails with 428. Either
+/// failure is recorded as the status of the handler's span.
+pub fn check_if_match(
+    span: &Span,
+    headers: &HeaderMap,
+    version: i32,
+    required: bool,
+) -> Result<(), AppError> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        if required {
+            let code = StatusCode::PRECONDITION_REQUIRED;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            return Err(AppError(
+                code,
+                anyhow!("Send the patient's ETag in an If-Match header to change it"),
+            ));
+        }
+        return Ok(());
+    };
+
+    // If-Match uses the strong comparison, so weak tags never match
+    let current = format!("\"{version}\"");
+    let matches = value
+        .to_str()
+        .map(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag == current)
+        })
+        .unwrap_or(false);
+    if matches {
+        Ok(())
+    } else {
+        Err(stale(span))
+    }
+}
+
+/// The 412 for a change based on a version that is no longer current, recorded on the span
+pub fn stale(span: &Span) -> AppError {
+    let code = StatusCode::PRECONDITION_FAILED;
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(code.as_u16() as i64),
+    );
+    AppError(
+        code,
+        anyhow!("The patient has changed since it was read; fetch it again and retry"),
+    )
+}
+
+/// The 410 for a patient that was deleted, as opposed to the 404 for one that never existed
+///
+/// An admin can restore the patient, which makes it readable and changeable again.
+pub fn gone(patient_id: Uuid) -> AppError {
+    AppError(
+        StatusCode::GONE,
+        anyhow!("Patient {patient_id} was deleted"),
+    )
+}
+
+/// The 410 for a patient that was purged, which can't be restored
+pub fn purged(patient_id: Uuid) -> AppError {
+    AppError(
+        StatusCode::GONE,
+        anyhow!("Patient {patient_id} was permanently purged"),
+    )
+}
+
+/// Whether `If-None-Match` names the current version, so a GET can answer 304 Not Modified
+pub fn if_none_match(headers: &HeaderMap, version: i32) -> bool {
+    let Some(Ok(tags)) = headers.get(header::IF_NONE_MATCH).map(HeaderValue::to_str) else {
+        return false;
+    };
+
+    // If-None-Match uses the weak comparison, which ignores the W/ prefix
+    let current = format!("\"{version}\"");
+    tags.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(name, HeaderValue::from_static(value))])
+    }
+
+    fn if_match(value: &'static str, version: i32) -> Option<StatusCode> {
+        check_if_match(
+            &Span::none(),
+            &headers(header::IF_MATCH, value),
+            version,
+            true,
+        )
+        .err()
+        .map(|AppError(code, _)| code)
+    }
+
+    #[test]
+    fn if_match_accepts_the_current_version_or_any() {
+        assert_eq!(if_match("\"3\"", 3), None);
+        assert_eq!(if_match("*", 3), None);
+        assert_eq!(if_match("\"1\", \"3\"", 3), None);
+    }
+
+    #[test]
+    fn if_match_rejects_other_versions_and_weak_tags() {
+        assert_eq!(if_match("\"2\"", 3), Some(StatusCode::PRECONDITION_FAILED));
+        assert_eq!(
+            if_match("\"1\", \"2\"", 3),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(
+            if_match("W/\"3\"", 3),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+        assert_eq!(if_match("3", 3), Some(StatusCode::PRECONDITION_FAILED));
+    }
+
+    #[test]
+    fn if_match_is_required_only_when_configured() {
+        let none = HeaderMap::new();
+        assert!(check_if_match(&Span::none(), &none, 3, false).is_ok());
+        assert!(matches!(
+            check_if_match(&Span::none(), &none, 3, true),
+            Err(AppError(StatusCode::PRECONDITION_REQUIRED, _))
+        ));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let matches = |value| if_none_match(&headers(header::IF_NONE_MATCH, value), 3);
+        assert!(matches("\"3\""));
+        assert!(matches("W/\"3\""));
+        assert!(matches("\"1\", W/\"3\""));
+        assert!(matches("*"));
+        assert!(!matches("\"2\""));
+        assert!(!if_none_match(&HeaderMap::new(), 3));
+    }
+}
@@ -0,0 +1,469 @@
+use crate::api::encryption::{FieldEncryption, SearchField};
+use crate::entities::patient::{self, address, birthdate, PatientRecord, StoredRecord};
+
+use sea_orm::sea_query::{Condition, Expr, SimpleExpr};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, EntityTrait, JoinType, QueryFilter, QueryOrder, QuerySelect,
+    RelationTrait,
+};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use uuid::Uuid;
+
+/// The share of the score from how alike the first names and surnames are
+const NAME_WEIGHT: f32 = 0.5;
+
+/// The share of the score from the same birth date
+const BIRTH_DATE_WEIGHT: f32 = 0.35;
+
+/// The share of the score from the same postal code
+const POSTAL_CODE_WEIGHT: f32 = 0.15;
+
+/// Patients decrypted per query while building the report
+const BATCH_SIZE: u64 = 500;
+
+/// How alike two patients are, from 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Score {
+    pub total: f32,
+    /// The mean trigram similarity of the first names and of the surnames
+    pub name_similarity: f32,
+    pub same_birth_date: bool,
+    pub same_postal_code: bool,
+}
+
+/// An existing patient that a new one may duplicate
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub patient_id: Uuid,
+    pub score: Score,
+}
+
+/// Two existing patients that may be the same person
+#[derive(Clone, Debug)]
+pub struct DuplicatePair {
+    pub patient_id: Uuid,
+    pub duplicate_id: Uuid,
+    pub score: Score,
+}
+
+/// Scores how likely two patients are to be the same person
+///
+/// Names count for half, compared by trigram similarity so that misspellings still score; an
+/// exact birth date and postal code make up the rest.
+pub fn score(a: &PatientRecord, b: &PatientRecord) -> Score {
+    let name_similarity =
+        (similarity(&a.first, &b.first) + similarity(&a.surname, &b.surname)) / 2.0;
+    let same_birth_date = (a.year, a.month, a.day) == (b.year, b.month, b.day);
+    let same_postal_code = postal_code(a).is_some() && postal_code(a) == postal_code(b);
+
+    let mut total = name_similarity * NAME_WEIGHT;
+    if same_birth_date {
+        total += BIRTH_DATE_WEIGHT;
+    }
+    if same_postal_code {
+        total += POSTAL_CODE_WEIGHT;
+    }
+    Score {
+        total,
+        name_similarity,
+        same_birth_date,
+        same_postal_code,
+    }
+}
+
+/// The active patients that a new patient likely duplicates, best match first
+///
+/// Only patients born on the same date, or born the same year with the same postal code, can
+/// score over any useful threshold, so only those are loaded. Encrypted patients are found by
+/// their blind indexes and scored once decrypted.
+pub async fn candidates<C: ConnectionTrait>(
+    db: &C,
+    encryption: &FieldEncryption,
+    record: &PatientRecord,
+    threshold: f32,
+) -> anyhow::Result<Vec<Candidate>> {
+    let birth_date = format!("{:04}-{:02}-{:02}", record.year, record.month, record.day);
+    let mut blocking = Condition::any().add(
+        Expr::col((birthdate::Entity, birthdate::Column::BirthDateIndex))
+            .is_in(encryption.blind_indexes(SearchField::BirthDate, &birth_date))
+            .or(plaintext().and(
+                Expr::tuple([
+                    Expr::col((birthdate::Entity, birthdate::Column::Year)).into(),
+                    Expr::col((birthdate::Entity, birthdate::Column::Month)).into(),
+                    Expr::col((birthdate::Entity, birthdate::Column::Day)).into(),
+                ])
+                .eq(Expr::tuple([
+                    Expr::value(record.year),
+                    Expr::value(record.month),
+                    Expr::value(record.day),
+                ])),
+            )),
+    );
+    if let Some(postal_code) = postal_code(record) {
+        blocking = blocking.add(
+            Expr::col((birthdate::Entity, birthdate::Column::Year))
+                .eq(record.year)
+                .and(
+                    Expr::col((address::Entity, address::Column::PostalCodeIndex))
+                        .is_in(encryption.blind_indexes(SearchField::PostalCode, &postal_code))
+                        .or(plaintext().and(Expr::cust_with_exprs(
+                            "lower(trim($1)) = $2",
+                            [
+                                Expr::col((address::Entity, address::Column::PostalCode)).into(),
+                                Expr::value(postal_code),
+                            ],
+                        ))),
+                ),
+        );
+    }
+
+    let stored = patient::Entity::find_records()
+        .filter(patient::Column::ActiveFlag.eq(true))
+        .filter(patient::Column::PatientId.ne(record.patient_id))
+        .filter(blocking)
+        .into_model::<StoredRecord>()
+        .all(db)
+        .await?;
+
+    let mut candidates = Vec::new();
+    for stored in stored {
+        let existing = encryption.open(stored)?;
+        let score = score(record, &existing);
+        if score.total >= threshold {
+            candidates.push(Candidate {
+                patient_id: existing.patient_id,
+                score,
+            });
+        }
+    }
+    candidates.sort_by(|a, b| b.score.total.total_cmp(&a.score.total));
+    Ok(candidates)
+}
+
+/// The `limit` pairs of active patients that are most likely the same person, best match first,
+/// along with how many pairs there are in all
+///
+/// Patients are paired up within the same groups that `candidates` searches: the same birth date,
+/// or the same birth year and postal code. Both fall within one birth year, which is never
+/// encrypted, so only one year's patients are decrypted and held at a time.
+pub async fn report<C: ConnectionTrait>(
+    db: &C,
+    encryption: &FieldEncryption,
+    threshold: f32,
+    limit: usize,
+) -> anyhow::Result<(u64, Vec<DuplicatePair>)> {
+    let years: Vec<i32> = patient::Entity::find()
+        .select_only()
+        .column(birthdate::Column::Year)
+        .distinct()
+        .join(JoinType::InnerJoin, patient::Relation::Birthdate.def())
+        .filter(patient::Column::ActiveFlag.eq(true))
+        .order_by_asc(birthdate::Column::Year)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut total = 0;
+    let mut best = Vec::new();
+    for year in years {
+        let pairs = pairs(&born_in(db, encryption, year).await?, threshold);
+        total += pairs.len() as u64;
+        best.extend(pairs);
+        best.sort_by(rank);
+        best.truncate(limit);
+    }
+    Ok((total, best))
+}
+
+/// The active patients born in `year`, decrypted a batch at a time
+async fn born_in<C: ConnectionTrait>(
+    db: &C,
+    encryption: &FieldEncryption,
+    year: i32,
+) -> anyhow::Result<Vec<PatientRecord>> {
+    let mut records = Vec::new();
+    let mut after = 0;
+    loop {
+        let batch = patient::Entity::find_records()
+            .filter(patient::Column::ActiveFlag.eq(true))
+            .filter(birthdate::Column::Year.eq(year))
+            .filter(patient::Column::Id.gt(after))
+            .order_by_asc(patient::Column::Id)
+            .limit(BATCH_SIZE)
+            .into_model::<StoredRecord>()
+            .all(db)
+            .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = last.id;
+        for stored in batch {
+            records.push(encryption.open(stored)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Every pair of the patients that scores at least `threshold`, among those born on the same
+/// date or with the same postal code
+fn pairs(records: &[PatientRecord], threshold: f32) -> Vec<DuplicatePair> {
+    let mut by_birth_date: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+    let mut by_postal_code: HashMap<(i32, String), Vec<usize>> = HashMap::new();
+    for (i, record) in records.iter().enumerate() {
+        by_birth_date
+            .entry((record.year, record.month, record.day))
+            .or_default()
+            .push(i);
+        if let Some(postal_code) = postal_code(record) {
+            by_postal_code
+                .entry((record.year, postal_code))
+                .or_default()
+                .push(i);
+        }
+    }
+
+    // A pair can share both groups, so each is scored once
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::new();
+    for group in by_birth_date.values().chain(by_postal_code.values()) {
+        for (n, &i) in group.iter().enumerate() {
+            for &j in &group[n + 1..] {
+                if !seen.insert((i, j)) {
+                    continue;
+                }
+                let score = score(&records[i], &records[j]);
+                if score.total >= threshold {
+                    pairs.push(DuplicatePair {
+                        patient_id: records[i].patient_id,
+                        duplicate_id: records[j].patient_id,
+                        score,
+                    });
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Orders pairs best match first, then by patient IDs so the order is stable
+fn rank(a: &DuplicatePair, b: &DuplicatePair) -> Ordering {
+    b.score
+        .total
+        .total_cmp(&a.score.total)
+        .then(a.patient_id.cmp(&b.patient_id))
+        .then(a.duplicate_id.cmp(&b.duplicate_id))
+}
+
+/// Patients whose fields aren't encrypted yet
+fn plaintext() -> SimpleExpr {
+    Expr::col((patient::Entity, patient::Column::KeyVersion)).is_null()
+}
+
+/// The postal code as compared, ignoring case and surrounding whitespace, unless it's empty
+fn postal_code(record: &PatientRecord) -> Option<String> {
+    Some(record.postal_code.trim().to_lowercase()).filter(|code| !code.is_empty())
+}
+
+/// The trigram similarity of two names, from 0 to 1, ignoring case
+///
+/// Matches `pg_trgm`: each word is padded with two spaces in front and one behind, and the
+/// similarity is the share of distinct trigrams the names have in common.
+fn similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    let union = a.union(&b).count();
+    match union {
+        0 => 0.0,
+        _ => a.intersection(&b).count() as f32 / union as f32,
+    }
+}
+
+fn trigrams(value: &str) -> BTreeSet<[char; 3]> {
+    let mut trigrams = BTreeSet::new();
+    for word in value
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let padded: Vec<char> = format!("  {word} ").chars().collect();
+        for window in padded.windows(3) {
+            trigrams.insert([window[0], window[1], window[2]]);
+        }
+    }
+    trigrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+    use std::collections::BTreeMap;
+
+    fn patient(
+        first: &str,
+        surname: &str,
+        (year, month, day): (i32, i32, i32),
+        postal_code: &str,
+    ) -> PatientRecord {
+        PatientRecord {
+            id: 0,
+            active_flag: true,
+            patient_id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            version: 1,
+            deleted_at: None,
+            deleted_by: None,
+            first: first.to_string(),
+            middle: String::new(),
+            surname: surname.to_string(),
+            address_lines: vec![],
+            sublocality: String::new(),
+            locality: String::new(),
+            administrative_area: String::new(),
+            postal_code: postal_code.to_string(),
+            country_region: "US".to_string(),
+            day,
+            month,
+            year,
+        }
+    }
+
+    /// A stored plaintext patient, as the report reads it
+    fn stored(
+        id: i32,
+        first: &str,
+        surname: &str,
+        (year, month, day): (i32, i32, i32),
+    ) -> BTreeMap<&'static str, sea_orm::Value> {
+        let none = Option::<String>::None;
+        BTreeMap::from([
+            ("id", id.into()),
+            ("active_flag", true.into()),
+            ("patient_id", Uuid::new_v4().into()),
+            ("created_at", Utc::now().into()),
+            ("version", 1.into()),
+            ("key_version", Option::<i32>::None.into()),
+            ("deleted_at", Option::<chrono::DateTime<Utc>>::None.into()),
+            ("deleted_by", none.clone().into()),
+            ("name_id", id.into()),
+            ("address_id", id.into()),
+            ("birthdate_id", id.into()),
+            ("first", first.into()),
+            ("middle", "".into()),
+            ("surname", surname.into()),
+            ("address_lines", Vec::<String>::new().into()),
+            ("sublocality", "".into()),
+            ("locality", "".into()),
+            ("administrative_area", "".into()),
+            ("postal_code", "97211".into()),
+            ("country_region", "US".into()),
+            ("day", Some(day).into()),
+            ("month", Some(month).into()),
+            ("year", year.into()),
+            ("sealed_date", none.into()),
+        ])
+    }
+
+    #[tokio::test]
+    async fn reports_the_best_pairs_a_birth_year_at_a_time() {
+        let year = |year: i32| BTreeMap::from([("year", sea_orm::Value::from(year))]);
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[year(1997), year(1999)]])
+            .append_query_results([
+                vec![
+                    stored(1, "Jon", "Doe", (1997, 8, 6)),
+                    stored(2, "John", "Doe", (1997, 8, 6)),
+                ],
+                vec![],
+                vec![
+                    stored(3, "Mary", "Roe", (1999, 2, 1)),
+                    stored(4, "mary", "ROE", (1999, 2, 1)),
+                ],
+                vec![],
+            ])
+            .into_connection();
+
+        let (total, pairs) = report(&db, &FieldEncryption::disabled(), 0.8, 1)
+            .await
+            .unwrap();
+
+        // Both pairs are counted, but only the exact match is kept
+        assert_eq!(total, 2);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].score.total, 1.0);
+
+        let log = db.into_transaction_log();
+        assert_eq!(log.len(), 5);
+        assert!(log[0].statements()[0].sql.starts_with("SELECT DISTINCT"));
+        for (statement, year) in [(&log[1], 1997), (&log[3], 1999)] {
+            let statement = &statement.statements()[0];
+            assert!(statement.sql.contains("\"birthdate\".\"year\" = $2"));
+            assert_eq!(statement.values.as_ref().unwrap().0[1], year.into());
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_candidates_by_a_padded_postal_code() {
+        // Born a day apart, so only the postal code can bring the existing patient in
+        let mut existing = stored(1, "John", "Doe", (1997, 8, 5));
+        existing.insert("postal_code", " 97211".into());
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[existing]])
+            .into_connection();
+
+        let record = patient("John", "Doe", (1997, 8, 6), "97211 ");
+        let found = candidates(&db, &FieldEncryption::disabled(), &record, 0.5)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].score.same_postal_code);
+
+        // Both sides are trimmed and lowercased before they're compared
+        let log = db.into_transaction_log();
+        let statement = &log[0].statements()[0];
+        assert!(statement
+            .sql
+            .contains("lower(trim(\"address\".\"postal_code\")) = $"));
+        assert!(statement
+            .values
+            .as_ref()
+            .unwrap()
+            .0
+            .contains(&"97211".into()));
+    }
+
+    #[test]
+    fn similarity_matches_pg_trgm() {
+        assert_eq!(similarity("Smith", "smith"), 1.0);
+        // "  j", " jo" in common out of "  j", " jo", "jon", "on ", "joh", "ohn", "hn "
+        assert_eq!(similarity("Jon", "John"), 2.0 / 7.0);
+        assert_eq!(similarity("Ann", "Bob"), 0.0);
+        assert_eq!(similarity("", ""), 0.0);
+    }
+
+    #[test]
+    fn same_name_and_birth_date_is_a_duplicate() {
+        let existing = patient("Jane", "Doe", (1997, 8, 6), "97211");
+        let moved = patient("jane", "DOE", (1997, 8, 6), "10001");
+        let score = score(&moved, &existing);
+        assert!(score.same_birth_date && !score.same_postal_code);
+        assert!(score.total >= 0.8, "{score:?}");
+    }
+
+    #[test]
+    fn misspelled_name_with_the_same_details_is_a_duplicate() {
+        let existing = patient("John", "Doe", (1997, 8, 6), "97211");
+        let misspelled = patient("Jon", "Doe", (1997, 8, 6), " 97211 ");
+        assert!(score(&misspelled, &existing).total >= 0.8);
+    }
+
+    #[test]
+    fn siblings_at_the_same_address_are_not_duplicates() {
+        let twin = patient("Jane", "Doe", (1997, 8, 6), "97211");
+        let other_twin = patient("Mary", "Doe", (1997, 8, 6), "97211");
+        let sibling = patient("Jane", "Doe", (1999, 2, 1), "97211");
+        assert!(score(&other_twin, &twin).total < 0.8);
+        assert!(score(&sibling, &twin).total < 0.8);
+    }
+}
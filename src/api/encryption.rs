@@ -0,0 +1,796 @@
+use crate::entities::data_key;
+use crate::entities::patient::{address, birthdate, name, PatientRecord, StoredRecord};
+use crate::entities::patient_history;
+use crate::settings::Settings;
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Datelike, NaiveDate};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::sqlx::{self, PgConnection};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, QueryOrder,
+};
+use std::collections::BTreeMap;
+
+/// The length of master and data keys, for AES-256
+const KEY_LEN: usize = 32;
+
+/// Associated data for wrapped data keys, so they can't pass for field values
+const DATA_KEY_AAD: &str = "data_key";
+
+/// The fields that have blind indexes, for exact matches on their encrypted values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchField {
+    First,
+    Surname,
+    Locality,
+    PostalCode,
+    BirthDate,
+}
+
+impl SearchField {
+    fn as_str(self) -> &'static str {
+        match self {
+            SearchField::First => "first",
+            SearchField::Surname => "surname",
+            SearchField::Locality => "locality",
+            SearchField::PostalCode => "postal_code",
+            SearchField::BirthDate => "birth_date",
+        }
+    }
+}
+
+/// The key that wraps data keys, from `encryption.master_key` or `encryption.master_key_file`
+pub struct MasterKey {
+    key: LessSafeKey,
+    /// A fingerprint stored with the data keys it wraps
+    id: String,
+}
+
+impl MasterKey {
+    /// The configured master key, or `None` when patient fields aren't encrypted
+    pub fn from_settings(settings: &Settings) -> anyhow::Result<Option<Self>> {
+        let encryption = &settings.encryption;
+        match (&encryption.master_key, &encryption.master_key_file) {
+            (Some(encoded), _) => Self::from_base64(encoded).map(Some),
+            (None, Some(path)) => Self::from_file(path).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Reads a base64-encoded key from a file
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let encoded = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read master key file {path}"))?;
+        Self::from_base64(&encoded).with_context(|| format!("Invalid master key in {path}"))
+    }
+
+    fn from_base64(encoded: &str) -> anyhow::Result<Self> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .context("The master key must be base64-encoded")?;
+        if bytes.len() != KEY_LEN {
+            return Err(anyhow!("The master key must be {KEY_LEN} bytes"));
+        }
+        Ok(Self {
+            key: aead_key(&bytes)?,
+            id: hex::encode(&digest(&SHA256, &bytes).as_ref()[..8]),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn wrap(&self, data_key: &[u8]) -> anyhow::Result<String> {
+        seal(&self.key, DATA_KEY_AAD, data_key)
+    }
+
+    fn unwrap(&self, wrapped: &str) -> anyhow::Result<Vec<u8>> {
+        open(&self.key, DATA_KEY_AAD, wrapped)
+    }
+}
+
+/// A data key, split into a key for encrypting fields and one for their blind indexes
+pub struct DataKey {
+    cipher: LessSafeKey,
+    index: hmac::Key,
+}
+
+impl DataKey {
+    fn new(bytes: &[u8]) -> anyhow::Result<Self> {
+        // Each use gets its own subkey, so the blind indexes reveal nothing about the cipher key
+        let root = hmac::Key::new(hmac::HMAC_SHA256, bytes);
+        let cipher = hmac::sign(&root, b"field encryption");
+        let index = hmac::sign(&root, b"blind index");
+        Ok(Self {
+            cipher: aead_key(cipher.as_ref())?,
+            index: hmac::Key::new(hmac::HMAC_SHA256, index.as_ref()),
+        })
+    }
+
+    /// Generates a key, stores it wrapped by the master key, and returns it with its version
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        master: &MasterKey,
+    ) -> anyhow::Result<(i32, Self)> {
+        let bytes = generate()?;
+        let stored = data_key::ActiveModel {
+            wrapped_key: Set(master.wrap(&bytes)?),
+            master_key_id: Set(master.id.clone()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+        Ok((stored.id, Self::new(&bytes)?))
+    }
+
+    /// Stores the first key for the master key, unless another server already has
+    ///
+    /// The unique index on initial keys lets only one of several servers starting together
+    /// create it, so that every one of them encrypts with the same key.
+    pub async fn create_initial<C: ConnectionTrait>(
+        db: &C,
+        master: &MasterKey,
+    ) -> anyhow::Result<()> {
+        let bytes = generate()?;
+        data_key::Entity::insert(data_key::ActiveModel {
+            wrapped_key: Set(master.wrap(&bytes)?),
+            master_key_id: Set(master.id.clone()),
+            initial: Set(true),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::column(data_key::Column::MasterKeyId)
+                .target_and_where(Expr::col(data_key::Column::Initial).into())
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Loads every stored key that `master` wrapped, by version
+    pub async fn load_all<C: ConnectionTrait>(
+        db: &C,
+        master: &MasterKey,
+    ) -> anyhow::Result<BTreeMap<i32, Self>> {
+        let stored = data_key::Entity::find()
+            .filter(data_key::Column::MasterKeyId.eq(&master.id))
+            .order_by_asc(data_key::Column::Id)
+            .all(db)
+            .await?;
+
+        let mut keys = BTreeMap::new();
+        for key in stored {
+            let bytes = master
+                .unwrap(&key.wrapped_key)
+                .with_context(|| format!("Failed to unwrap data key {}", key.id))?;
+            keys.insert(key.id, Self::new(&bytes)?);
+        }
+        Ok(keys)
+    }
+
+    fn seal(&self, field: &str, value: &str) -> anyhow::Result<String> {
+        seal(&self.cipher, field, value.as_bytes())
+    }
+
+    fn open(&self, field: &str, value: &str) -> anyhow::Result<String> {
+        String::from_utf8(open(&self.cipher, field, value)?)
+            .with_context(|| format!("Decrypted {field} isn't UTF-8"))
+    }
+
+    /// The blind index of a value, which ignores case and surrounding whitespace
+    fn blind_index(&self, field: SearchField, value: &str) -> String {
+        let normalized = format!("{}:{}", field.as_str(), value.trim().to_lowercase());
+        hex::encode(hmac::sign(&self.index, normalized.as_bytes()))
+    }
+}
+
+/// A patient's name, address and birth date rows, ready to insert or update
+pub struct Sealed {
+    /// The data key version to store on the patient
+    pub key_version: Option<i32>,
+    pub name: name::ActiveModel,
+    pub address: address::ActiveModel,
+    pub birthdate: birthdate::ActiveModel,
+}
+
+/// Encrypts and decrypts the identifying fields of patients
+///
+/// Names, address lines, sublocalities, localities, postal codes and birth dates are encrypted
+/// with AES-256-GCM under a data key, which is stored wrapped by the master key. The
+/// administrative area, country and birth year stay in the clear. With no master key
+/// configured, new patients are stored in plaintext.
+pub struct FieldEncryption {
+    /// The version new values are encrypted with, unset when encryption is off
+    active: Option<i32>,
+    keys: BTreeMap<i32, DataKey>,
+}
+
+impl FieldEncryption {
+    /// Stores patients in plaintext, and can only read plaintext patients
+    pub fn disabled() -> Self {
+        Self {
+            active: None,
+            keys: BTreeMap::new(),
+        }
+    }
+
+    /// Encrypts with the `active` key, and decrypts with any of `keys`
+    pub fn new(active: i32, keys: BTreeMap<i32, DataKey>) -> Self {
+        Self {
+            active: Some(active),
+            keys,
+        }
+    }
+
+    /// Loads the data keys wrapped by the configured master key, creating the first one if there
+    /// are none; the newest key encrypts new values
+    pub async fn load<C: ConnectionTrait>(db: &C, settings: &Settings) -> anyhow::Result<Self> {
+        let Some(master) = MasterKey::from_settings(settings)? else {
+            return Ok(Self::disabled());
+        };
+
+        let mut keys = DataKey::load_all(db, &master).await?;
+        if keys.is_empty() {
+            // Whichever server stores the first key, every one of them loads it
+            DataKey::create_initial(db, &master).await?;
+            keys = DataKey::load_all(db, &master).await?;
+        }
+        let Some(&active) = keys.keys().last() else {
+            return Err(anyhow!("The first data key wasn't stored"));
+        };
+        Ok(Self::new(active, keys))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// The version of the key that encrypts new values
+    pub fn key_version(&self) -> Option<i32> {
+        self.active
+    }
+
+    /// The blind indexes of a value under every loaded key, any of which a match may carry
+    pub fn blind_indexes(&self, field: SearchField, value: &str) -> Vec<String> {
+        self.keys
+            .values()
+            .map(|key| key.blind_index(field, value))
+            .collect()
+    }
+
+    /// Prepares the rows for a patient's fields, encrypted with the active key if there is one
+    pub fn seal(&self, record: &PatientRecord) -> anyhow::Result<Sealed> {
+        let index = |field, value: &str| {
+            self.active
+                .and_then(|version| self.keys.get(&version))
+                .map(|key| key.blind_index(field, value))
+        };
+        let birth_date =
+            NaiveDate::from_ymd_opt(record.year, record.month as u32, record.day as u32)
+                .ok_or_else(|| anyhow!("Invalid birth date"))?
+                .to_string();
+        let indexes = name::ActiveModel {
+            first_index: Set(index(SearchField::First, &record.first)),
+            surname_index: Set(index(SearchField::Surname, &record.surname)),
+            ..Default::default()
+        };
+        let address_indexes = address::ActiveModel {
+            locality_index: Set(index(SearchField::Locality, &record.locality)),
+            postal_code_index: Set(index(SearchField::PostalCode, &record.postal_code)),
+            ..Default::default()
+        };
+        let birth_date_index = Set(index(SearchField::BirthDate, &birth_date));
+
+        let fields = self.seal_fields(Fields {
+            key_version: None,
+            first: record.first.clone(),
+            middle: record.middle.clone(),
+            surname: record.surname.clone(),
+            address_lines: record.address_lines.clone(),
+            sublocality: record.sublocality.clone(),
+            locality: record.locality.clone(),
+            postal_code: record.postal_code.clone(),
+            day: Some(record.day),
+            month: Some(record.month),
+            year: record.year,
+            sealed_date: None,
+        })?;
+
+        Ok(Sealed {
+            key_version: fields.key_version,
+            name: name::ActiveModel {
+                first: Set(fields.first),
+                middle: Set(fields.middle),
+                surname: Set(fields.surname),
+                ..indexes
+            },
+            address: address::ActiveModel {
+                address_lines: Set(fields.address_lines),
+                sublocality: Set(fields.sublocality),
+                locality: Set(fields.locality),
+                administrative_area: Set(record.administrative_area.clone()),
+                postal_code: Set(fields.postal_code),
+                country_region: Set(record.country_region.clone()),
+                ..address_indexes
+            },
+            birthdate: birthdate::ActiveModel {
+                day: Set(fields.day),
+                month: Set(fields.month),
+                year: Set(record.year),
+                sealed_date: Set(fields.sealed_date),
+                birth_date_index,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Encrypts a history entry in the clear with the active key, if there is one
+    pub fn seal_history(
+        &self,
+        entry: patient_history::Model,
+    ) -> anyhow::Result<patient_history::Model> {
+        let fields = self.seal_fields(Fields::from_history(&entry))?;
+        Ok(fields.into_history(entry))
+    }
+
+    /// Decrypts a stored patient
+    pub fn open(&self, stored: StoredRecord) -> anyhow::Result<PatientRecord> {
+        let fields = self.open_fields(Fields {
+            key_version: stored.key_version,
+            first: stored.first,
+            middle: stored.middle,
+            surname: stored.surname,
+            address_lines: stored.address_lines,
+            sublocality: stored.sublocality,
+            locality: stored.locality,
+            postal_code: stored.postal_code,
+            day: stored.day,
+            month: stored.month,
+            year: stored.year,
+            sealed_date: stored.sealed_date,
+        })?;
+
+        Ok(PatientRecord {
+            id: stored.id,
+            active_flag: stored.active_flag,
+            patient_id: stored.patient_id,
+            created_at: stored.created_at,
+            version: stored.version,
+            deleted_at: stored.deleted_at,
+            deleted_by: stored.deleted_by,
+            first: fields.first,
+            middle: fields.middle,
+            surname: fields.surname,
+            address_lines: fields.address_lines,
+            sublocality: fields.sublocality,
+            locality: fields.locality,
+            administrative_area: stored.administrative_area,
+            postal_code: fields.postal_code,
+            country_region: stored.country_region,
+            day: fields.day.unwrap_or_default(),
+            month: fields.month.unwrap_or_default(),
+            year: stored.year,
+        })
+    }
+
+    /// Decrypts a history entry, which afterwards has its birth day and month set
+    pub fn open_history(
+        &self,
+        entry: patient_history::Model,
+    ) -> anyhow::Result<patient_history::Model> {
+        let fields = self.open_fields(Fields::from_history(&entry))?;
+        Ok(fields.into_history(entry))
+    }
+
+    fn seal_fields(&self, fields: Fields) -> anyhow::Result<Fields> {
+        let Some(version) = self.active else {
+            return Ok(fields);
+        };
+        let key = self.key(version)?;
+
+        let (Some(day), Some(month)) = (fields.day, fields.month) else {
+            return Err(anyhow!(
+                "A birth date to encrypt is missing its day or month"
+            ));
+        };
+        let date = NaiveDate::from_ymd_opt(fields.year, month as u32, day as u32)
+            .ok_or_else(|| anyhow!("Invalid birth date"))?;
+        Ok(Fields {
+            key_version: Some(version),
+            first: key.seal("first", &fields.first)?,
+            middle: key.seal("middle", &fields.middle)?,
+            surname: key.seal("surname", &fields.surname)?,
+            address_lines: fields
+                .address_lines
+                .iter()
+                .map(|line| key.seal("address_lines", line))
+                .collect::<anyhow::Result<_>>()?,
+            sublocality: key.seal("sublocality", &fields.sublocality)?,
+            locality: key.seal("locality", &fields.locality)?,
+            postal_code: key.seal("postal_code", &fields.postal_code)?,
+            day: None,
+            month: None,
+            year: fields.year,
+            sealed_date: Some(key.seal("birth_date", &date.to_string())?),
+        })
+    }
+
+    fn open_fields(&self, fields: Fields) -> anyhow::Result<Fields> {
+        let Some(version) = fields.key_version else {
+            if fields.day.is_none() || fields.month.is_none() {
+                return Err(anyhow!(
+                    "A plaintext birth date is missing its day or month"
+                ));
+            }
+            return Ok(fields);
+        };
+        let key = self.key(version)?;
+
+        let sealed_date = fields
+            .sealed_date
+            .ok_or_else(|| anyhow!("An encrypted patient is missing its birth date"))?;
+        let date: NaiveDate = key
+            .open("birth_date", &sealed_date)?
+            .parse()
+            .context("Decrypted birth date isn't a date")?;
+        Ok(Fields {
+            key_version: None,
+            first: key.open("first", &fields.first)?,
+            middle: key.open("middle", &fields.middle)?,
+            surname: key.open("surname", &fields.surname)?,
+            address_lines: fields
+                .address_lines
+                .iter()
+                .map(|line| key.open("address_lines", line))
+                .collect::<anyhow::Result<_>>()?,
+            sublocality: key.open("sublocality", &fields.sublocality)?,
+            locality: key.open("locality", &fields.locality)?,
+            postal_code: key.open("postal_code", &fields.postal_code)?,
+            day: Some(date.day() as i32),
+            month: Some(date.month() as i32),
+            year: date.year(),
+            sealed_date: None,
+        })
+    }
+
+    fn key(&self, version: i32) -> anyhow::Result<&DataKey> {
+        self.keys
+            .get(&version)
+            .ok_or_else(|| anyhow!("Data key {version} isn't wrapped by the configured master key"))
+    }
+}
+
+#[cfg(test)]
+impl FieldEncryption {
+    /// Encryption with fixed keys, each a version and the byte its key repeats; the last one
+    /// encrypts new values
+    pub fn mock(keys: &[(i32, u8)]) -> Self {
+        let keys: BTreeMap<i32, DataKey> = keys
+            .iter()
+            .map(|&(version, byte)| (version, DataKey::new(&[byte; KEY_LEN]).unwrap()))
+            .collect();
+        let active = *keys.keys().last().expect("at least one key");
+        Self::new(active, keys)
+    }
+}
+
+/// The fields that may be encrypted, shared by patients and their history entries
+struct Fields {
+    key_version: Option<i32>,
+    first: String,
+    middle: String,
+    surname: String,
+    address_lines: Vec<String>,
+    sublocality: String,
+    locality: String,
+    postal_code: String,
+    day: Option<i32>,
+    month: Option<i32>,
+    year: i32,
+    sealed_date: Option<String>,
+}
+
+impl Fields {
+    fn from_history(entry: &patient_history::Model) -> Self {
+        Self {
+            key_version: entry.key_version,
+            first: entry.first.clone(),
+            middle: entry.middle.clone(),
+            surname: entry.surname.clone(),
+            address_lines: entry.address_lines.clone(),
+            sublocality: entry.sublocality.clone(),
+            locality: entry.locality.clone(),
+            postal_code: entry.postal_code.clone(),
+            day: entry.day,
+            month: entry.month,
+            year: entry.year,
+            sealed_date: entry.sealed_date.clone(),
+        }
+    }
+
+    fn into_history(self, entry: patient_history::Model) -> patient_history::Model {
+        patient_history::Model {
+            key_version: self.key_version,
+            first: self.first,
+            middle: self.middle,
+            surname: self.surname,
+            address_lines: self.address_lines,
+            sublocality: self.sublocality,
+            locality: self.locality,
+            postal_code: self.postal_code,
+            day: self.day,
+            month: self.month,
+            sealed_date: self.sealed_date,
+            ..entry
+        }
+    }
+}
+
+/// Keeps `rekey` from running alongside servers, which load the data keys only when they start
+///
+/// Every server holds the lock shared while it runs, and `rekey` holds it exclusively. It's a
+/// session lock on a connection taken out of the pool, so it's released once dropped.
+pub struct KeyLock {
+    _session: PgConnection,
+}
+
+impl KeyLock {
+    /// Takes the lock for a server, unless `rekey` is running
+    pub async fn shared(db: &DatabaseConnection) -> anyhow::Result<Option<Self>> {
+        Self::try_lock(
+            db,
+            "SELECT pg_try_advisory_lock_shared(hashtext('data_key'))",
+        )
+        .await
+    }
+
+    /// Takes the lock for `rekey`, unless a server is running
+    pub async fn exclusive(db: &DatabaseConnection) -> anyhow::Result<Option<Self>> {
+        Self::try_lock(db, "SELECT pg_try_advisory_lock(hashtext('data_key'))").await
+    }
+
+    async fn try_lock(db: &DatabaseConnection, sql: &str) -> anyhow::Result<Option<Self>> {
+        let mut session = db.get_postgres_connection_pool().acquire().await?.detach();
+        let locked: bool = sqlx::query_scalar(sql).fetch_one(&mut session).await?;
+        Ok(locked.then_some(Self { _session: session }))
+    }
+}
+
+/// Data keys that `rekey` may drop, because no patient or history entry uses them
+pub async fn unused_keys<C: ConnectionTrait>(db: &C) -> Result<Vec<i32>, DbErr> {
+    let keys = data_key::Entity::find()
+        .filter(Expr::cust(
+            "NOT EXISTS (SELECT 1 FROM patient WHERE patient.key_version = data_key.id) \
+             AND NOT EXISTS (SELECT 1 FROM patient_history \
+             WHERE patient_history.key_version = data_key.id)",
+        ))
+        .all(db)
+        .await?;
+    Ok(keys.into_iter().map(|key| key.id).collect())
+}
+
+fn generate() -> anyhow::Result<[u8; KEY_LEN]> {
+    let mut bytes = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow!("Failed to generate a data key"))?;
+    Ok(bytes)
+}
+
+fn aead_key(bytes: &[u8]) -> anyhow::Result<LessSafeKey> {
+    let key = UnboundKey::new(&AES_256_GCM, bytes).map_err(|_| anyhow!("Invalid AES-256 key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Encrypts a value with a random nonce, bound to `aad` so it can't be moved to another field,
+/// and encodes the nonce and ciphertext as base64
+fn seal(key: &LessSafeKey, aad: &str, value: &[u8]) -> anyhow::Result<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow!("Failed to generate a nonce"))?;
+
+    let mut sealed = value.to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(aad.as_bytes()),
+        &mut sealed,
+    )
+    .map_err(|_| anyhow!("Failed to encrypt {aad}"))?;
+
+    let mut encoded = nonce.to_vec();
+    encoded.extend_from_slice(&sealed);
+    Ok(STANDARD.encode(encoded))
+}
+
+fn open(key: &LessSafeKey, aad: &str, value: &str) -> anyhow::Result<Vec<u8>> {
+    let failed = || anyhow!("Failed to decrypt {aad}");
+    let mut sealed = STANDARD.decode(value).map_err(|_| failed())?;
+    if sealed.len() < NONCE_LEN {
+        return Err(failed());
+    }
+    let mut ciphertext = sealed.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&sealed).map_err(|_| failed())?;
+
+    let plaintext = key
+        .open_in_place(nonce, Aad::from(aad.as_bytes()), &mut ciphertext)
+        .map_err(|_| failed())?;
+    Ok(plaintext.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::patient_history::Action;
+    use chrono::Utc;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+    use uuid::Uuid;
+
+    fn entry() -> patient_history::Model {
+        let now = Utc::now();
+        patient_history::Model {
+            id: 1,
+            patient_id: Uuid::new_v4(),
+            version: 1,
+            action: Action::Created,
+            changed_by: "admin".to_string(),
+            changed_at: now,
+            active_flag: true,
+            created_at: now,
+            key_version: None,
+            first: "Jane".to_string(),
+            middle: "Q.".to_string(),
+            surname: "Doe".to_string(),
+            address_lines: vec!["123 Fake St.".to_string(), "Apt 4".to_string()],
+            sublocality: "".to_string(),
+            locality: "Portland".to_string(),
+            administrative_area: "OR".to_string(),
+            postal_code: "97211".to_string(),
+            country_region: "US".to_string(),
+            day: Some(6),
+            month: Some(8),
+            year: 1997,
+            sealed_date: None,
+        }
+    }
+
+    #[test]
+    fn opens_what_it_sealed() {
+        let encryption = FieldEncryption::mock(&[(1, 7)]);
+        let plaintext = entry();
+
+        let sealed = encryption.seal_history(plaintext.clone()).unwrap();
+        assert_eq!(sealed.key_version, Some(1));
+        assert_ne!(sealed.first, plaintext.first);
+        assert_ne!(sealed.address_lines, plaintext.address_lines);
+        assert_eq!((sealed.day, sealed.month), (None, None));
+        assert_eq!(sealed.year, 1997);
+        assert_eq!(sealed.administrative_area, "OR");
+
+        assert_eq!(encryption.open_history(sealed).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn seals_the_same_value_differently_each_time() {
+        let encryption = FieldEncryption::mock(&[(1, 7)]);
+        let first = encryption.seal_history(entry()).unwrap();
+        let second = encryption.seal_history(entry()).unwrap();
+        assert_ne!(first.surname, second.surname);
+    }
+
+    #[test]
+    fn fails_to_open_with_another_data_key() {
+        let sealed = FieldEncryption::mock(&[(1, 7)])
+            .seal_history(entry())
+            .unwrap();
+        assert!(FieldEncryption::mock(&[(1, 8)])
+            .open_history(sealed.clone())
+            .is_err());
+        // A key version that isn't loaded can't be opened either
+        assert!(FieldEncryption::mock(&[(2, 7)])
+            .open_history(sealed)
+            .is_err());
+    }
+
+    #[test]
+    fn fails_to_open_a_value_moved_to_another_field() {
+        let encryption = FieldEncryption::mock(&[(1, 7)]);
+        let sealed = encryption.seal_history(entry()).unwrap();
+        let swapped = patient_history::Model {
+            first: sealed.surname.clone(),
+            surname: sealed.first.clone(),
+            ..sealed
+        };
+        assert!(encryption.open_history(swapped).is_err());
+    }
+
+    #[test]
+    fn blind_indexes_stay_the_same_across_key_versions() {
+        let old = FieldEncryption::mock(&[(1, 7)]);
+        let rotated = FieldEncryption::mock(&[(1, 7), (2, 9)]);
+
+        // An index stored under the old key still matches once a newer key is active
+        let stored = old.blind_indexes(SearchField::Surname, "Doe");
+        let searched = rotated.blind_indexes(SearchField::Surname, " doe ");
+        assert_eq!(searched.len(), 2);
+        assert_eq!(searched[0], stored[0]);
+        assert_ne!(searched[0], searched[1]);
+
+        // The field is part of the index, so equal values in different fields don't match
+        assert_ne!(
+            old.blind_indexes(SearchField::First, "Doe"),
+            old.blind_indexes(SearchField::Surname, "Doe")
+        );
+    }
+
+    #[test]
+    fn leaves_values_in_plaintext_when_disabled() {
+        let disabled = FieldEncryption::disabled();
+        let plaintext = entry();
+        assert_eq!(disabled.seal_history(plaintext.clone()).unwrap(), plaintext);
+
+        let sealed = FieldEncryption::mock(&[(1, 7)])
+            .seal_history(plaintext)
+            .unwrap();
+        assert!(disabled.open_history(sealed).is_err());
+    }
+
+    #[test]
+    fn unwraps_data_keys_only_with_their_master_key() {
+        let master = MasterKey::from_base64(&STANDARD.encode([1u8; KEY_LEN])).unwrap();
+        let other = MasterKey::from_base64(&STANDARD.encode([2u8; KEY_LEN])).unwrap();
+        let wrapped = master.wrap(&[7u8; KEY_LEN]).unwrap();
+
+        assert_eq!(master.unwrap(&wrapped).unwrap(), [7u8; KEY_LEN]);
+        assert!(other.unwrap(&wrapped).is_err());
+        assert_ne!(master.id(), other.id());
+        assert!(MasterKey::from_base64(&STANDARD.encode([1u8; 16])).is_err());
+    }
+
+    #[tokio::test]
+    async fn loads_the_first_key_whichever_server_stored_it() {
+        let encoded = STANDARD.encode([1u8; KEY_LEN]);
+        let master = MasterKey::from_base64(&encoded).unwrap();
+        let mut settings = Settings::default();
+        settings.encryption.master_key = Some(encoded);
+
+        // Another server stored the first key between the lookup and the insert
+        let stored = data_key::Model {
+            id: 3,
+            wrapped_key: master.wrap(&[7u8; KEY_LEN]).unwrap(),
+            master_key_id: master.id().to_string(),
+            initial: true,
+            created_at: Utc::now(),
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<data_key::Model>::new(), vec![stored]])
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 0,
+            }])
+            .into_connection();
+
+        let loaded = FieldEncryption::load(&db, &settings).await.unwrap();
+        assert_eq!(loaded.key_version(), Some(3));
+        let plaintext = entry();
+        let sealed = FieldEncryption::mock(&[(3, 7)])
+            .seal_history(plaintext.clone())
+            .unwrap();
+        assert_eq!(loaded.open_history(sealed).unwrap(), plaintext);
+
+        let log = db.into_transaction_log();
+        assert!(log[1].statements()[0]
+            .sql
+            .ends_with("ON CONFLICT (\"master_key_id\") WHERE \"initial\" DO NOTHING"));
+    }
+}
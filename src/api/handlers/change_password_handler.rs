@@ -0,0 +1,93 @@
+use crate::api::auth::password::{change_password, validate_password};
+use crate::api::middleware::json::CustomJson;
+use crate::api::request::change_password_request::ChangePasswordRequest;
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{debug_handler, extract::State, http::StatusCode, Extension};
+use opentelemetry::{Key, Value};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Change your password
+///
+/// Change the password of the user the bearer token was issued to. The new password must meet
+/// the password policy and must not match a recently used password. Every token issued to you
+/// so far is revoked, so you must log in again with the new password.
+#[utoipa::path(
+    put,
+    path = "/me/password",
+    tag = "Auth",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 204, description = "Success"),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The current password is incorrect", body = ErrorResponse),
+        (status = 422, description = "The new password breaks the password policy or was used recently", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = [])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "change_password", skip_all)]
+pub async fn change(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    CustomJson(payload): CustomJson<ChangePasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("PUT"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let model = user::Entity::find()
+        .filter(user::Column::Username.eq(&claims.sub))
+        .one(db)
+        .await?
+        .ok_or_else(|| {
+            let code = StatusCode::UNAUTHORIZED;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            AppError(code, anyhow!("User doesn't exist"))
+        })?;
+
+    if validate_password(&payload.current_password, &model.password).is_err() {
+        let code = StatusCode::FORBIDDEN;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!("Current password is incorrect")));
+    }
+
+    let policy = &state.settings.load().password_policy;
+    let model = change_password(db, policy, model, &payload.new_password)
+        .await
+        .map_err(|err| {
+            let err = err.into_app_error();
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(err.0.as_u16() as i64),
+            );
+            err
+        })?;
+
+    state.revocations.revoke_user(db, model).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::NO_CONTENT.as_u16() as i64),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,93 @@
+use crate::api::auth::api_keys::create_api_key;
+use crate::api::middleware::json::CustomJson;
+use crate::api::request::create_api_key_request::CreateApiKeyRequest;
+use crate::api::response::api_key_response::CreateApiKeyResponse;
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{debug_handler, extract::State, http::StatusCode, Extension, Json};
+use chrono::{DateTime, Utc};
+use opentelemetry::{Key, Value};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Create an API key
+///
+/// Create a key that machine clients send in the `X-Api-Key` header instead of logging in. The
+/// key can only perform the operations in its scopes. The response contains the only copy of
+/// the key.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    post,
+    path = "/api-keys",
+    tag = "API keys",
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 200, description = "Success", body = CreateApiKeyResponse),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 422, description = "The scopes are empty or the expiry time is invalid", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "create_api_key", skip_all)]
+pub async fn create(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    CustomJson(payload): CustomJson<CreateApiKeyRequest>,
+) -> Result<Json<CreateApiKeyResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let expires_at = if payload.scopes.is_empty() {
+        Err(anyhow!("An API key needs at least one scope"))
+    } else {
+        payload.expires_at.as_deref().map(parse_expiry).transpose()
+    };
+    let expires_at = expires_at.map_err(|err| {
+        let code = StatusCode::UNPROCESSABLE_ENTITY;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        AppError(code, err)
+    })?;
+
+    let (model, key) = create_api_key(
+        state.db_conn.load().as_ref(),
+        &payload.name,
+        &payload.scopes,
+        expires_at,
+        &claims.sub,
+    )
+    .await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(CreateApiKeyResponse {
+        key,
+        data: model.into(),
+    }))
+}
+
+/// Parses an RFC3339 expiry time, which must be in the future
+pub fn parse_expiry(expires_at: &str) -> anyhow::Result<DateTime<Utc>> {
+    let expires_at = DateTime::parse_from_rfc3339(expires_at)
+        .map_err(|err| anyhow!("Invalid expiry time \"{expires_at}\": {err}"))?
+        .with_timezone(&Utc);
+    if expires_at <= Utc::now() {
+        return Err(anyhow!("The expiry time must be in the future"));
+    }
+    Ok(expires_at)
+}
@@ -1,167 +1,709 @@
 use crate::api::request::create_patient_request::CreatePatientRequest;
-use crate::api::response::create_patient_response::{
-    AddressData, 
-    BirthdateData, 
-    CreatePatientResponse, 
-    NameData, 
-    Patient,
-};
+use crate::api::response::create_patient_response::{CreatePatientResponse, Patient};
+use crate::api::response::duplicates::DuplicatePatientResponse;
 //use chrono::NaiveDate;
+use crate::api::audit::AuditPatients;
+use crate::api::auth::api_keys::ApiKeyScopes;
+use crate::api::conditional;
+use crate::api::duplicates;
+use crate::api::encryption::FieldEncryption;
+use crate::api::idempotency::{
+    self, Claim, IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER, MAX_KEY_LENGTH,
+};
 use crate::api::response::error::AppError;
 use crate::api::response::TokenClaims;
-use crate::entities::patient;
+use crate::entities::patient::{self, PatientRecord};
+use crate::entities::patient_history::{self, Action};
 use crate::state::ApplicationState;
-use axum::{debug_handler, extract::State, http::StatusCode, Extension, Json};
-use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
 use std::sync::Arc;
 use uuid::Uuid;
 //use crate::api::response::error::ErrorResponse;
-use crate::api::middleware::json::CustomJson;
+use crate::api::middleware::json::ValidJson;
+use crate::api::middleware::rbac;
 use opentelemetry::{Key, Value};
 use tracing::instrument;
 use tracing::Span;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+#[derive(Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+pub struct CreatePatientParams {
+    /// Create the patient even if it looks like an existing one
+    #[param(example = false)]
+    pub force: Option<bool>,
+}
+
 /// Create a patient record
 ///
 /// Create a patient record by supplying patient information. The system generates and returns 
 /// a patient ID as UUID to use with subsequent patient record operations.
+///
+/// The first name and surname must not be empty, the birth date must be a real date within the
+/// last 150 years, and `country_region` must be an ISO 3166-1 alpha-2 code. Invalid requests
+/// get a 422 listing every failing field.
+///
+/// Send an `Idempotency-Key` header to retry safely after a timeout. A retry with the same key
+/// and body gets the original response, with `Idempotent-Replayed: true`, instead of creating
+/// another patient. Keys are remembered per caller for a configurable window (a day by default),
+/// and reusing one with a different body is rejected with a 422.
+///
+/// A patient that likely duplicates an existing active one gets a 409 with the number of
+/// candidates, and isn't created. Callers who may read patients also get the candidates, best
+/// match first. Each candidate is scored from 0 to 1: names count for half, compared by trigram
+/// similarity so that misspellings still match, and the same birth date and postal code make up
+/// the rest. Set `force` to create the patient anyway once the candidates are ruled out.
+///
+/// Requires one of the `admin`, `clinician`, or `front_desk` roles, or an API key with the
+/// `patients:write` scope.
 #[utoipa::path(
     post,
     path = "/patient",
     tag = "Patient Records",
     request_body = CreatePatientRequest,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "A unique value, such as a UUID, that makes retries of this request safe"),
+        CreatePatientParams,
+    ),
     responses(
-        (status = 200, description = "Success", body = CreatePatientResponse),
+        (status = 200, description = "Success", body = CreatePatientResponse,
+            headers(("ETag" = String, description = "The version of the new patient record"))),
         (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 409, description = "The patient likely duplicates existing ones, which are counted and, for callers who may read patients, listed as candidates; or a request with the same idempotency key is still being processed, which gets the plain error", body = CreateConflictResponse),
+        (status = 422, description = "One or more fields are invalid, the body doesn't match the schema, or the idempotency key was used with a different body", body = UnprocessableResponse),
     ),
     security(
-        ("api_jwt_token" = [])
+        ("api_jwt_token" = ["admin", "clinician", "front_desk"]),
+        ("api_key" = ["patients:write"])
     )
 )]
 #[debug_handler]
 #[instrument(level = "info", name = "create_patient", skip_all)]
 pub async fn create(
     Extension(claims): Extension<TokenClaims>,
+    api_key: Option<Extension<ApiKeyScopes>>,
     State(state): State<Arc<ApplicationState>>,
-    CustomJson(payload): CustomJson<CreatePatientRequest>,
-) -> Result<Json<CreatePatientResponse>, AppError> {
+    Query(params): Query<CreatePatientParams>,
+    headers: HeaderMap,
+    ValidJson(payload): ValidJson<CreatePatientRequest>,
+) -> Result<Response, AppError> {
     // Start a tracing span
     let span = Span::current();
     span.set_attribute(Key::from("http.method"), Value::from("POST"));
     let name = &claims.sub;
     span.set_attribute(Key::from("user"), Value::from(name.to_string()));
 
-    // Validations
-    let payload_ref = &payload;
-    validate(payload_ref);
-
     // Open a DB connection
     let db_conn = state.db_conn.load();
     let db = db_conn.as_ref();
 
-    // Convert request payload to `ActiveModel`
-    let name_active_model = patient::name::ActiveModel {
-        first: Set(payload.name.first),
-        middle: Set(payload.name.middle.unwrap_or("".to_string())),
-        surname: Set(payload.name.surname),
-        ..Default::default()
+    // A retried request replays the response to the first one instead of creating a duplicate
+    let idempotency_key = idempotency_key(&headers)?;
+    if let Some(key) = &idempotency_key {
+        let request_hash = idempotency::hash_request(&payload)?;
+        let settings = state.settings.load();
+        match idempotency::claim(db, &settings, name, key, &request_hash).await? {
+            Claim::New => {}
+            Claim::Replay {
+                status,
+                patient_id,
+                version,
+            } => {
+                // The original response is rebuilt from the patient as it was created, which its
+                // history keeps encrypted
+                let Some(entry) = patient_history::Entity::find()
+                    .filter(patient_history::Column::PatientId.eq(patient_id))
+                    .filter(patient_history::Column::Version.eq(version))
+                    .one(db)
+                    .await?
+                else {
+                    let err = conditional::purged(patient_id);
+                    span.set_attribute(
+                        Key::from("http.status_code"),
+                        Value::from(err.0.as_u16() as i64),
+                    );
+                    return Err(err);
+                };
+                let entry = state.field_encryption.open_history(entry)?;
+                let body = serde_json::to_string(&CreatePatientResponse {
+                    data: Patient {
+                        created_at: entry.created_at.to_string(),
+                        ..Patient::from(entry)
+                    },
+                })?;
+
+                span.set_attribute(
+                    Key::from("http.status_code"),
+                    Value::from(status.as_u16() as i64),
+                );
+                // Sends the ETag the original response had, for the patient it created
+                return Ok((
+                    status,
+                    Extension(AuditPatients(vec![patient_id])),
+                    [
+                        (header::CONTENT_TYPE.as_str(), "application/json"),
+                        (IDEMPOTENT_REPLAYED_HEADER, "true"),
+                    ],
+                    [(header::ETAG, conditional::etag(version))],
+                    body,
+                )
+                    .into_response());
+            }
+            Claim::Mismatch => {
+                let code = StatusCode::UNPROCESSABLE_ENTITY;
+                span.set_attribute(
+                    Key::from("http.status_code"),
+                    Value::from(code.as_u16() as i64),
+                );
+                return Err(AppError(
+                    code,
+                    anyhow!("The idempotency key was already used with a different request body"),
+                ));
+            }
+            Claim::InProgress => {
+                let code = StatusCode::CONFLICT;
+                span.set_attribute(
+                    Key::from("http.status_code"),
+                    Value::from(code.as_u16() as i64),
+                );
+                return Err(AppError(
+                    code,
+                    anyhow!("A request with this idempotency key is still being processed"),
+                ));
+            }
+        }
+    }
+
+    // A likely duplicate is turned away unless the caller has ruled the candidates out
+    let record = new_record(payload);
+    let candidates = match params.force {
+        Some(true) => Ok(Vec::new()),
+        _ => {
+            let threshold = state.settings.load().duplicates.match_threshold;
+            duplicates::candidates(db, &state.field_encryption, &record, threshold).await
+        }
     };
-    let address_active_model = patient::address::ActiveModel {
-        address_lines: Set(payload.address.address_lines),
-        sublocality: Set(payload.address.sublocality.unwrap_or("".to_string())),
-        locality: Set(payload.address.locality.unwrap_or("".to_string())),
-        administrative_area: Set(payload
-            .address
-            .administrative_area
-            .unwrap_or("".to_string())),
-        postal_code: Set(payload.address.postal_code.unwrap_or("".to_string())),
-        country_region: Set(payload.address.country_region),
-        ..Default::default()
+    let candidates = match candidates {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            if let Some(key) = &idempotency_key {
+                idempotency::release(db, name, key).await?;
+            }
+            return Err(err.into());
+        }
     };
-    let birthdate_active_model = patient::birthdate::ActiveModel {
-        day: Set(payload.birth_date.day),
-        month: Set(payload.birth_date.month),
-        year: Set(payload.birth_date.year),
-        ..Default::default()
+    if !candidates.is_empty() {
+        // Lets the client retry with the same key and force=true
+        if let Some(key) = &idempotency_key {
+            idempotency::release(db, name, key).await?;
+        }
+        let code = StatusCode::CONFLICT;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        // Only callers who may read patients learn which ones matched
+        let readable = rbac::PATIENT_READ.permits(&claims, api_key.as_ref().map(|key| &key.0));
+        let disclosed = if readable {
+            candidates
+                .iter()
+                .map(|candidate| candidate.patient_id)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        return Ok((
+            code,
+            Extension(AuditPatients(disclosed)),
+            Json(DuplicatePatientResponse::new(candidates, readable)),
+        )
+            .into_response());
+    }
+
+    // Stores the patient and its related rows together, completing the idempotency key with them
+    let idempotency = idempotency_key.as_deref().map(|key| (name.as_str(), key));
+    let (patient_model, body) =
+        match insert_patient(db, &state.field_encryption, record, name, idempotency).await {
+            Ok(inserted) => inserted,
+            Err(err) => {
+                // Lets the client retry with the same key
+                if let Some(key) = &idempotency_key {
+                    idempotency::release(db, name, key).await?;
+                }
+                return Err(err.into());
+            }
+        };
+    let uuid = patient_model.patient_id;
+    let etag = conditional::etag(patient_model.version);
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok((
+        StatusCode::OK,
+        Extension(AuditPatients(vec![uuid])),
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/json")),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Reads the optional `Idempotency-Key` header
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
     };
+    match value.to_str() {
+        Ok(key) if !key.trim().is_empty() && key.len() <= MAX_KEY_LENGTH => {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Idempotency-Key must be 1 to {MAX_KEY_LENGTH} visible ASCII characters"),
+        )),
+    }
+}
+
+/// Converts the request payload to a record, whose generated fields are filled in once stored
+fn new_record(payload: CreatePatientRequest) -> PatientRecord {
+    PatientRecord {
+        id: 0,
+        active_flag: true,
+        patient_id: Uuid::new_v4(), // Creates the patient_record_id
+        created_at: Utc::now(),
+        version: 1,
+        deleted_at: None,
+        deleted_by: None,
+        first: payload.name.first,
+        middle: payload.name.middle.unwrap_or("".to_string()),
+        surname: payload.name.surname,
+        address_lines: payload.address.address_lines,
+        sublocality: payload.address.sublocality.unwrap_or("".to_string()),
+        locality: payload.address.locality.unwrap_or("".to_string()),
+        administrative_area: payload
+            .address
+            .administrative_area
+            .unwrap_or("".to_string()),
+        postal_code: payload.address.postal_code.unwrap_or("".to_string()),
+        country_region: payload.address.country_region,
+        day: payload.birth_date.day,
+        month: payload.birth_date.month,
+        year: payload.birth_date.year,
+    }
+}
+
+/// Inserts the patient's name, address, birth date and patient rows in one transaction, along with
+/// the first entry in its history, and returns the stored patient with the response body
+///
+/// The name, address and birth date are encrypted first when encryption is on. If any insert
+/// fails, the transaction rolls back when dropped, so no orphaned rows remain. The caller's
+/// idempotency key, if any, is completed with the patient in the same transaction, so that a retry
+/// either replays the response or finds the key free to create the patient again.
+async fn insert_patient(
+    db: &DatabaseConnection,
+    encryption: &FieldEncryption,
+    mut record: PatientRecord,
+    created_by: &str,
+    idempotency: Option<(&str, &str)>,
+) -> anyhow::Result<(patient::Model, String)> {
+    let sealed = encryption.seal(&record)?;
 
     // Stores Models
-    let name_model: patient::name::Model = name_active_model.insert(db).await?;
-    let address_model: patient::address::Model = address_active_model.insert(db).await?;
-    let birthdate_model: patient::birthdate::Model = birthdate_active_model.insert(db).await?;
-    let uuid = Uuid::new_v4(); // Creates the patient_record_id
+    let txn = db.begin().await?;
+    let name_model: patient::name::Model = sealed.name.insert(&txn).await?;
+    let address_model: patient::address::Model = sealed.address.insert(&txn).await?;
+    let birthdate_model: patient::birthdate::Model = sealed.birthdate.insert(&txn).await?;
 
     // Create and store the full patient record
     let patient_active_model = patient::ActiveModel {
         name_id: Set(name_model.id),
         address_id: Set(address_model.id),
         birthdate_id: Set(birthdate_model.id),
-        patient_id: Set(uuid),
+        patient_id: Set(record.patient_id),
         active_flag: Set(true),
+        key_version: Set(sealed.key_version),
         ..Default::default()
     };
 
-    let patient_model: patient::Model = patient_active_model.insert(db).await?;
+    let patient_model: patient::Model = patient_active_model.insert(&txn).await?;
+    patient_history::record(&txn, patient_model.id, Action::Created, created_by).await?;
 
-    // Constructs response from generated models
+    // Constructs response from the stored patient and the fields as sent
+    record.id = patient_model.id;
+    record.created_at = patient_model.created_at;
+    record.version = patient_model.version;
     let response_data = Patient {
         created_at: patient_model.created_at.to_string(),
-        patient_id: uuid.into(),
-        name: NameData {
-            first: name_model.first,
-            middle: name_model.middle,
-            surname: name_model.surname,
-        },
-        address: AddressData {
-            address_lines: address_model.address_lines,
-            sublocality: address_model.sublocality,
-            locality: address_model.locality,
-            administrative_area: address_model.administrative_area,
-            postal_code: address_model.postal_code,
-            country_region: address_model.country_region,
-        },
-        birthdate: BirthdateData {
-            day: birthdate_model.day,
-            month: birthdate_model.month,
-            year: birthdate_model.year,
-        },
+        ..Patient::from(record)
     };
-
-    span.set_attribute(
-        Key::from("http.status_code"),
-        Value::from(StatusCode::OK.as_u16() as i64),
-    );
-    Ok(Json(CreatePatientResponse {
+    let body = serde_json::to_string(&CreatePatientResponse {
         //status: 200,
         data: response_data,
-    }))
+    })?;
+    if let Some((principal, key)) = idempotency {
+        let patient = (patient_model.patient_id, patient_model.version);
+        idempotency::complete(&txn, principal, key, StatusCode::OK, patient).await?;
+    }
+    txn.commit().await?;
+
+    Ok((patient_model, body))
 }
 
-fn validate(payload: &CreatePatientRequest) {
-    // 1) Prints some test output to server
-    if payload.name.first == "Peter".to_string() {
-        println!("We got one!!!")
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::request::create_patient_request::{AddressCreate, BirthDateCreate, NameCreate};
+    use crate::entities::api_key::Scope;
+    use sea_orm::{DatabaseBackend, DbErr, MockDatabase, MockExecResult};
+    use std::collections::BTreeMap;
 
-    // 2) Calculates patient's age
-    use chrono::{Datelike, NaiveDate, Utc};
+    fn request() -> CreatePatientRequest {
+        CreatePatientRequest {
+            name: NameCreate {
+                first: "Jane".to_string(),
+                middle: None,
+                surname: "Doe".to_string(),
+            },
+            address: AddressCreate {
+                address_lines: vec!["123 Fake St.".to_string()],
+                sublocality: None,
+                locality: Some("Portland".to_string()),
+                administrative_area: Some("OR".to_string()),
+                postal_code: Some("97211".to_string()),
+                country_region: "US".to_string(),
+            },
+            birth_date: BirthDateCreate {
+                day: 6,
+                month: 8,
+                year: 1997,
+            },
+        }
+    }
 
-    let today = Utc::now().date_naive();
-    let _timestamp = Utc::now().format("%Y/%m/%d %H:%M:%S");
-    let birth_date = NaiveDate::from_ymd_opt(
-        payload.birth_date.year,
-        payload.birth_date.month as u32,
-        payload.birth_date.day as u32,
-    )
-    .expect("Invalid birth date");
+    /// A row with every column of the patient tables and history, which can stand in for the
+    /// result of any insert or lookup while creating a patient
+    fn row(patient_id: Uuid) -> BTreeMap<&'static str, sea_orm::Value> {
+        let none = Option::<String>::None;
+        BTreeMap::from([
+            ("id", 1.into()),
+            ("active_flag", true.into()),
+            ("patient_id", patient_id.into()),
+            ("created_at", Utc::now().into()),
+            ("version", 1.into()),
+            ("key_version", Option::<i32>::None.into()),
+            ("deleted_at", Option::<chrono::DateTime<Utc>>::None.into()),
+            ("deleted_by", none.clone().into()),
+            ("name_id", 1.into()),
+            ("address_id", 1.into()),
+            ("birthdate_id", 1.into()),
+            ("first", "Jane".into()),
+            ("middle", "".into()),
+            ("surname", "Doe".into()),
+            ("first_index", none.clone().into()),
+            ("surname_index", none.clone().into()),
+            ("address_lines", vec!["123 Fake St.".to_string()].into()),
+            ("sublocality", "".into()),
+            ("locality", "Portland".into()),
+            ("administrative_area", "OR".into()),
+            ("postal_code", "97211".into()),
+            ("country_region", "US".into()),
+            ("locality_index", none.clone().into()),
+            ("postal_code_index", none.clone().into()),
+            ("day", Some(6).into()),
+            ("month", Some(8).into()),
+            ("year", 1997.into()),
+            ("sealed_date", none.clone().into()),
+            ("birth_date_index", none.into()),
+            ("action", "created".into()),
+            ("changed_by", "admin".into()),
+            ("changed_at", Utc::now().into()),
+        ])
+    }
+
+    /// Results for the name, address, birth date, patient and history inserts, and the lookup
+    /// that the history entry is copied from
+    fn inserted(db: MockDatabase, patient_id: Uuid) -> MockDatabase {
+        db.append_query_results((0..6).map(|_| [row(patient_id)]))
+    }
 
-    let mut age = today.year() - birth_date.year();
+    #[tokio::test]
+    async fn completes_the_idempotency_key_with_the_patient() {
+        let record = new_record(request());
+        let db = inserted(MockDatabase::new(DatabaseBackend::Postgres), record.patient_id)
+            .append_exec_results([affected(1)])
+            .into_connection();
 
-    // Adjust if birthday hasn't occurred yet this year
-    if today < birth_date.with_year(today.year()).unwrap() {
-        age -= 1;
+        let (patient, body) = insert_patient(
+            &db,
+            &FieldEncryption::disabled(),
+            record,
+            "admin",
+            Some(("admin", "key-1")),
+        )
+        .await
+        .unwrap();
+        assert!(body.contains(&patient.patient_id.to_string()));
+
+        // The response is stored before the patient is committed, in the same transaction
+        let log = db.into_transaction_log();
+        assert_eq!(log.len(), 1);
+        let statements = log[0].statements();
+        let n = statements.len();
+        assert_eq!(statements[0].sql, "BEGIN");
+        assert!(statements[n - 2]
+            .sql
+            .starts_with("UPDATE \"idempotency_key\""));
+        assert_eq!(statements[n - 1].sql, "COMMIT");
+    }
+
+    fn claims() -> TokenClaims {
+        TokenClaims {
+            jti: Uuid::new_v4(),
+            sub: "admin".to_string(),
+            role: crate::entities::user::Role::Admin,
+            iat: 0.0,
+            exp: usize::MAX,
+        }
+    }
+
+    fn keyed(key: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(
+            header::HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+            HeaderValue::from_static(key),
+        )])
     }
 
-    println!("Patient is {} years old", age);
+    fn affected(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_the_original_response_with_its_etag() {
+        let patient_id = Uuid::new_v4();
+        let now = Utc::now();
+        let stored = crate::entities::idempotency_key::Model {
+            id: 1,
+            principal: "admin".to_string(),
+            key: "key-1".to_string(),
+            request_hash: idempotency::hash_request(&request()).unwrap(),
+            response_status: Some(200),
+            patient_id: Some(patient_id),
+            version: Some(1),
+            created_at: now,
+            expires_at: now + chrono::Duration::days(1),
+            claimed_at: now,
+        };
+        // The expired keys are cleared, the key is taken and its claim is live, the earlier
+        // request's row is read, and then the patient's history entry from when it was created
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_exec_results([affected(0), affected(0), affected(0)])
+                .append_query_results([[stored]])
+                .append_query_results([[row(patient_id)]])
+                .into_connection(),
+        );
+
+        let response = create(
+            Extension(claims()),
+            None,
+            State(state.clone()),
+            Query(CreatePatientParams::default()),
+            keyed("key-1"),
+            ValidJson(request()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+        assert_eq!(response.headers()[header::ETAG], "\"1\"");
+        assert_eq!(
+            response.extensions().get::<AuditPatients>().unwrap().0,
+            [patient_id]
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["patient_id"], patient_id.to_string());
+        assert_eq!(body["data"]["name"]["surname"], "Doe");
+
+        // Nothing was inserted
+        let log = state.into_transaction_log();
+        assert_eq!(log.len(), 5);
+        assert!(log[4].statements()[0]
+            .sql
+            .contains("\"patient_history\".\"version\" = $2"));
+    }
+
+    async fn body(response: Response) -> serde_json::Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn turns_away_a_likely_duplicate_and_frees_its_key() {
+        let existing = Uuid::new_v4();
+        // The key is claimed, the candidates are read, and then the key is released
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_exec_results([affected(0), affected(1)])
+                .append_query_results([[row(existing)]])
+                .append_exec_results([affected(1)])
+                .into_connection(),
+        );
+
+        let response = create(
+            Extension(claims()),
+            None,
+            State(state.clone()),
+            Query(CreatePatientParams::default()),
+            keyed("key-1"),
+            ValidJson(request()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            response.extensions().get::<AuditPatients>().unwrap().0,
+            [existing]
+        );
+        let body = body(response).await;
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["candidates"][0]["patient_id"], existing.to_string());
+        assert_eq!(body["candidates"][0]["same_birth_date"], true);
+
+        // Nothing was inserted, and a retry with force=true may use the same key
+        let log = state.into_transaction_log();
+        assert_eq!(log.len(), 4);
+        assert!(log[3].statements()[0]
+            .sql
+            .starts_with("DELETE FROM \"idempotency_key\""));
+    }
+
+    #[tokio::test]
+    async fn only_counts_candidates_for_keys_that_cant_read_patients() {
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([[row(Uuid::new_v4())]])
+                .into_connection(),
+        );
+
+        let response = create(
+            Extension(claims()),
+            Some(Extension(ApiKeyScopes(vec![Scope::PatientsWrite]))),
+            State(state),
+            Query(CreatePatientParams::default()),
+            HeaderMap::new(),
+            ValidJson(request()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(response
+            .extensions()
+            .get::<AuditPatients>()
+            .unwrap()
+            .0
+            .is_empty());
+        let body = body(response).await;
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["candidates"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn creates_a_likely_duplicate_when_forced() {
+        let state = ApplicationState::mock(
+            inserted(MockDatabase::new(DatabaseBackend::Postgres), Uuid::new_v4())
+                .into_connection(),
+        );
+
+        let response = create(
+            Extension(claims()),
+            None,
+            State(state.clone()),
+            Query(CreatePatientParams { force: Some(true) }),
+            HeaderMap::new(),
+            ValidJson(request()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        // No candidates were looked up before the patient was inserted
+        let log = state.into_transaction_log();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].statements()[0].sql, "BEGIN");
+    }
+
+    #[tokio::test]
+    async fn rolls_back_every_insert_when_one_fails() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[patient::name::Model {
+                id: 1,
+                first: "Jane".to_string(),
+                middle: String::new(),
+                surname: "Doe".to_string(),
+                first_index: None,
+                surname_index: None,
+            }]])
+            .append_query_results([[patient::address::Model {
+                id: 1,
+                address_lines: vec!["123 Fake St.".to_string()],
+                sublocality: String::new(),
+                locality: "Portland".to_string(),
+                administrative_area: "OR".to_string(),
+                postal_code: "97211".to_string(),
+                country_region: "US".to_string(),
+                locality_index: None,
+                postal_code_index: None,
+            }]])
+            // Fails the third insert, after the name and address are written
+            .append_query_errors([DbErr::Custom("injected failure".to_string())])
+            .into_connection();
+
+        let result = insert_patient(
+            &db,
+            &FieldEncryption::disabled(),
+            new_record(request()),
+            "admin",
+            None,
+        ).await;
+        assert!(result.is_err());
+
+        // The two inserts that succeeded ran inside a transaction that was rolled back
+        let log = db.into_transaction_log();
+        assert_eq!(log.len(), 1);
+        let statements: Vec<String> = log[0]
+            .statements()
+            .iter()
+            .map(|statement| statement.sql.clone())
+            .collect();
+        assert_eq!(statements.first().map(String::as_str), Some("BEGIN"));
+        assert_eq!(statements.last().map(String::as_str), Some("ROLLBACK"));
+        assert!(!statements.iter().any(|sql| sql == "COMMIT"));
+        assert_eq!(
+            statements.iter().filter(|sql| sql.starts_with("INSERT")).count(),
+            3
+        );
+    }
 }
@@ -0,0 +1,142 @@
+use crate::api::auth::password::{check_policy, encrypt_password};
+use crate::api::auth::reserved_prefix;
+use crate::api::middleware::json::CustomJson;
+use crate::api::request::create_user_request::CreateUserRequest;
+use crate::api::response::error::AppError;
+use crate::api::response::user_response::UserResponse;
+use crate::api::response::TokenClaims;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{debug_handler, extract::State, http::StatusCode, Extension, Json};
+use opentelemetry::{Key, Value};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Create a user
+///
+/// Create a user that can log in to the service with the supplied password and role. The
+/// password must meet the password policy.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "Users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 200, description = "Success", body = UserResponse),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 409, description = "The username is already taken", body = ErrorResponse),
+        (status = 422, description = "The password breaks the password policy, or the username starts with a reserved prefix", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "create_user", skip_all)]
+pub async fn create(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    CustomJson(payload): CustomJson<CreateUserRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    // API keys and provider identities are named with these prefixes
+    if let Some(prefix) = reserved_prefix(&payload.username) {
+        let code = StatusCode::UNPROCESSABLE_ENTITY;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("Usernames starting with \"{prefix}\" are reserved"),
+        ));
+    }
+
+    let policy = &state.settings.load().password_policy;
+    check_policy(policy, &payload.password).map_err(|err| {
+        let err = err.into_app_error();
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(err.0.as_u16() as i64),
+        );
+        err
+    })?;
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let existing = user::Entity::find()
+        .filter(user::Column::Username.eq(&payload.username))
+        .one(db)
+        .await?;
+    if existing.is_some() {
+        let code = StatusCode::CONFLICT;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("User {} already exists", payload.username),
+        ));
+    }
+
+    let model = user::ActiveModel {
+        username: Set(payload.username),
+        password: Set(encrypt_password(&payload.password)?),
+        role: Set(payload.role),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(UserResponse { data: model.into() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::user::Role;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn refuses_usernames_that_could_pass_for_an_api_key() {
+        let claims = TokenClaims {
+            jti: Uuid::new_v4(),
+            sub: "admin".to_string(),
+            role: Role::Admin,
+            iat: 0.0,
+            exp: usize::MAX,
+        };
+        let state =
+            ApplicationState::mock(MockDatabase::new(DatabaseBackend::Postgres).into_connection());
+        let payload = CreateUserRequest {
+            username: "api-key:3".to_string(),
+            password: "Correct-Horse-Battery-9".to_string(),
+            role: Role::Admin,
+        };
+
+        let Err(err) = create(Extension(claims), State(state.clone()), CustomJson(payload)).await
+        else {
+            panic!("expected the reserved username to be refused");
+        };
+        assert_eq!(err.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(state.into_transaction_log().is_empty());
+    }
+}
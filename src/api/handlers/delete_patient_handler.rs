@@ -1,24 +1,27 @@
-use crate::api::response::create_patient_response::{
-    AddressData, BirthdateData, CreatePatientResponse, NameData, Patient,
-};
+use crate::api::conditional;
+use crate::api::purge;
+use crate::api::response::create_patient_response::{CreatePatientResponse, Patient};
 use crate::api::response::error::AppError;
 use crate::api::response::TokenClaims;
-use crate::entities::patient::{self, address, birthdate, name};
+use crate::entities::patient::{self, StoredRecord};
+use crate::entities::patient_history::{self, Action};
 use crate::state::ApplicationState;
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use axum::{
     debug_handler, 
     extract::{Path, State}, 
-    http::StatusCode, 
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Extension, 
     Json
 };
+use sea_orm::sea_query::Expr;
 use sea_orm::{
-    ActiveModelTrait, 
-    ActiveValue::Set, 
     ColumnTrait, 
     EntityTrait, 
-    QueryFilter
+    QueryFilter,
+    TransactionTrait,
 };
 use std::sync::Arc;
 use uuid::Uuid;
@@ -31,6 +34,15 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
 ///
 /// Delete a patient record by ID. The operation returns the deleted patient record as
 /// confirmation.
+///
+/// The record is kept, along with who deleted it and when, so an admin can restore it. Until then,
+/// reading, changing or deleting it again gets a 410.
+///
+/// Send the `ETag` from an earlier read in `If-Match` to make sure the record hasn't changed since
+/// it was read. A stale ETag gets a 412. The header is optional unless the server is configured to
+/// require it, in which case leaving it out gets a 428.
+///
+/// Requires the `admin` role, or an API key with the `patients:delete` scope.
 #[utoipa::path(
     delete,
     path = "/patient/{patient_id}",
@@ -38,15 +50,23 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
         // utoipa doesn't support uuid directly, so the path param
         // has to be a String instead
         ("patient_id" = String, Path, description = "Patient ID as UUID v4", example = "3973ebb8-11e5-4725-93b7-3b752caad60f
-")
+"),
+        ("If-Match" = Option<String>, Header, description = "The ETag of the version being deleted"),
     ),
     tag = "Patient Records",
     responses(
-        (status = 200, description = "Success", body = CreatePatientResponse),
+        (status = 200, description = "Success", body = CreatePatientResponse,
+            headers(("ETag" = String, description = "The version of the deleted patient record"))),
         (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "No patient has this ID", body = ErrorResponse),
+        (status = 410, description = "The patient was already deleted", body = ErrorResponse),
+        (status = 412, description = "The patient has changed since the ETag in If-Match was issued", body = ErrorResponse),
+        (status = 428, description = "If-Match is required but was not sent", body = ErrorResponse),
     ),
     security(
-        ("api_jwt_token" = [])
+        ("api_jwt_token" = ["admin"]),
+        ("api_key" = ["patients:delete"])
     )
 )]
 #[debug_handler]
@@ -55,7 +75,8 @@ pub async fn delete(
     Extension(claims): Extension<TokenClaims>,
     State(state): State<Arc<ApplicationState>>,
     Path(patient_id): Path<Uuid>,
-) -> Result<Json<CreatePatientResponse>, AppError> {
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     // Start a tracing span
     let span = Span::current();
     span.set_attribute(Key::from("http.method"), Value::from("DELETE"));
@@ -66,108 +87,70 @@ pub async fn delete(
     let db_conn = state.db_conn.load();
     let db = db_conn.as_ref();
 
-    // Query the patient by UUID
-    match patient::Entity::find()
+    // Query the patient and its related records by UUID
+    match patient::Entity::find_records()
         .filter(patient::Column::PatientId.eq(patient_id))
-        .one(state.db_conn.load().as_ref())
+        .into_model::<StoredRecord>()
+        .one(db)
         .await
     {
         // await returns Result<Option<Model>, DbErr>
         // so you have to safely unwrap all its thorny layers
         Ok(conn) => {
             // If the search returns a hit, set the
-            // "deleted" flag to true, fetch the patient 
-            // record data, assemble the JSON, 
-            // and return it
-            if let Some(model) = conn {
-
-                // Set the "deleted" flag
-                let mut active: patient::ActiveModel = model.clone().into();
-                active.active_flag = Set(false);
-                let _ = active.update(db).await?;
-
-                // Fetch related name
-                let name = name::Entity::find_by_id(model.name_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &patient_id)),
-                        );
-                        AppError(code, anyhow!("Birthdate record not found"))
-                    })?;
+            // "deleted" flag to true and return the
+            // patient record
+            if let Some(stored) = conn {
+                if !stored.active_flag {
+                    let code = StatusCode::GONE;
+                    span.set_attribute(
+                        Key::from("http.status_code"),
+                        Value::from(code.as_u16() as i64),
+                    );
+                    return Err(conditional::gone(patient_id));
+                }
+                let record = state.field_encryption.open(stored)?;
+                let require_if_match = state.settings.load().require_if_match;
+                conditional::check_if_match(
+                    &span,
+                    &headers,
+                    record.version,
+                    require_if_match,
+                )?;
 
-                // Fetch related address
-                let address = address::Entity::find_by_id(model.address_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &patient_id)),
-                        );
-                        AppError(code, anyhow!("Birthdate record not found"))
-                    })?;
+                // Set the "deleted" flag, unless the patient changed since it was read
+                let txn = db.begin().await?;
+                let deleted = patient::Entity::update_many()
+                    .col_expr(patient::Column::ActiveFlag, Expr::value(false))
+                    .col_expr(patient::Column::DeletedAt, Expr::value(Utc::now()))
+                    .col_expr(patient::Column::DeletedBy, Expr::value(name.to_string()))
+                    .col_expr(
+                        patient::Column::Version,
+                        Expr::col(patient::Column::Version).add(1),
+                    )
+                    .filter(patient::Column::Id.eq(record.id))
+                    .filter(patient::Column::Version.eq(record.version))
+                    .exec(&txn)
+                    .await?;
+                if deleted.rows_affected == 0 {
+                    return Err(conditional::stale(&span));
+                }
+                patient_history::record(&txn, record.id, Action::Deleted, name).await?;
+                txn.commit().await?;
+                let etag = conditional::etag(record.version + 1);
 
-                // Fetch related birthdate
-                let birthdate = birthdate::Entity::find_by_id(model.birthdate_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &patient_id)),
-                        );
-                        AppError(code, anyhow!("Birthdate record not found"))
-                    })?;
-
-                // Construct the response
-                let response_data = Patient {
-                    patient_id: model.patient_id.into(),
-                    created_at: model.created_at.to_rfc3339(),
-                    name: NameData {
-                        first: name.first,
-                        middle: name.middle,
-                        surname: name.surname,
-                    },
-                    address: AddressData {
-                        address_lines: address.address_lines,
-                        sublocality: address.sublocality,
-                        locality: address.locality,
-                        administrative_area: address.administrative_area,
-                        postal_code: address.postal_code,
-                        country_region: address.country_region,
-                    },
-                    birthdate: BirthdateData {
-                        day: birthdate.day,
-                        month: birthdate.month,
-                        year: birthdate.year,
-                    },
-                };
                 // Happy path
                 span.set_attribute(
                     Key::from("http.status_code"),
                     Value::from(StatusCode::OK.as_u16() as i64),
                 );
-                return Ok(Json(CreatePatientResponse {
-                    data: response_data,
-                }));
+                return Ok((
+                    [(header::ETAG, etag)],
+                    Json(CreatePatientResponse {
+                        data: Patient::from(record),
+                    }),
+                )
+                    .into_response());
             // If the search is Ok, but there is no hit,
             // return a 404 NOT_FOUND error
             } else {
@@ -199,3 +182,125 @@ pub async fn delete(
         }
     }
 }
+
+/// Restore a deleted patient record
+///
+/// Restore a patient record deleted by `DELETE /patient/{patient_id}`, so that it can be read and
+/// changed again. The operation returns the restored patient record. Admins can find deleted
+/// records with `GET /patient?status=inactive`.
+///
+/// Send the `ETag` from the delete in `If-Match` to make sure the record hasn't changed since. A
+/// record that isn't deleted gets a 409.
+///
+/// Requires the `admin` role, or an API key with the `patients:delete` scope.
+#[utoipa::path(
+    post,
+    path = "/patient/{patient_id}/restore",
+    params(
+        ("patient_id" = String, Path, description = "Patient ID as UUID v4", example = "3973ebb8-11e5-4725-93b7-3b752caad60f"),
+        ("If-Match" = Option<String>, Header, description = "The ETag of the deleted version"),
+    ),
+    tag = "Patient Records",
+    responses(
+        (status = 200, description = "Success", body = CreatePatientResponse,
+            headers(("ETag" = String, description = "The version of the restored patient record"))),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "No patient has this ID", body = ErrorResponse),
+        (status = 409, description = "The patient isn't deleted", body = ErrorResponse),
+        (status = 410, description = "The patient was purged", body = ErrorResponse),
+        (status = 412, description = "The patient has changed since the ETag in If-Match was issued", body = ErrorResponse),
+        (status = 428, description = "If-Match is required but was not sent", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"]),
+        ("api_key" = ["patients:delete"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "restore_patient", skip_all)]
+pub async fn restore(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(patient_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    let name = &claims.sub;
+    span.set_attribute(Key::from("user"), Value::from(name.to_string()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let stored = patient::Entity::find_records()
+        .filter(patient::Column::PatientId.eq(patient_id))
+        .into_model::<StoredRecord>()
+        .one(db)
+        .await?;
+    let Some(stored) = stored else {
+        if purge::was_purged(db, patient_id).await? {
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(StatusCode::GONE.as_u16() as i64),
+            );
+            return Err(conditional::purged(patient_id));
+        }
+        let code = StatusCode::NOT_FOUND;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!("Patient {patient_id} not found")));
+    };
+    if stored.active_flag {
+        let code = StatusCode::CONFLICT;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!("Patient {patient_id} isn't deleted")));
+    }
+    let mut record = state.field_encryption.open(stored)?;
+    let require_if_match = state.settings.load().require_if_match;
+    conditional::check_if_match(
+        &span,
+        &headers,
+        record.version,
+        require_if_match,
+    )?;
+
+    // Clear the "deleted" flag, unless the patient changed since it was read
+    let txn = db.begin().await?;
+    let restored = patient::Entity::update_many()
+        .col_expr(patient::Column::ActiveFlag, Expr::value(true))
+        .col_expr(patient::Column::DeletedAt, Expr::value(Option::<DateTime<Utc>>::None))
+        .col_expr(patient::Column::DeletedBy, Expr::value(Option::<String>::None))
+        .col_expr(
+            patient::Column::Version,
+            Expr::col(patient::Column::Version).add(1),
+        )
+        .filter(patient::Column::Id.eq(record.id))
+        .filter(patient::Column::Version.eq(record.version))
+        .exec(&txn)
+        .await?;
+    if restored.rows_affected == 0 {
+        return Err(conditional::stale(&span));
+    }
+    patient_history::record(&txn, record.id, Action::Restored, name).await?;
+    txn.commit().await?;
+    record.version += 1;
+    let etag = conditional::etag(record.version);
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok((
+        [(header::ETAG, etag)],
+        Json(CreatePatientResponse {
+            data: Patient::from(record),
+        }),
+    )
+        .into_response())
+}
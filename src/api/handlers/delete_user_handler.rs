@@ -0,0 +1,68 @@
+use crate::api::handlers::get_user_handler::{find_user, reject_self};
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::state::ApplicationState;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use opentelemetry::{Key, Value};
+use sea_orm::ModelTrait;
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Delete a user
+///
+/// Permanently delete a user and revoke every token issued to them.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    delete,
+    path = "/users/{username}",
+    tag = "Users",
+    params(
+        ("username" = String, Path, description = "The username of the user", example = "jdoe")
+    ),
+    responses(
+        (status = 204, description = "Success"),
+        (status = 400, description = "You cannot delete your own account", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The user doesn't exist", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "delete_user", skip_all)]
+pub async fn delete(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(username): Path<String>,
+) -> Result<StatusCode, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("DELETE"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    reject_self(&span, &claims, &username)?;
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    // Revokes outstanding tokens before the row, and its refresh
+    // tokens, are gone
+    let model = find_user(db, &span, &username).await?;
+    state.revocations.revoke_user(db, model.clone()).await?;
+    model.delete(db).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::NO_CONTENT.as_u16() as i64),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,120 @@
+use crate::api::handlers::get_user_handler::{find_user, reject_self};
+use crate::api::response::error::AppError;
+use crate::api::response::user_response::UserResponse;
+use crate::api::response::TokenClaims;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use opentelemetry::{Key, Value};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Disable a user
+///
+/// Disable a user and revoke every token issued to them. Disabled users cannot log in until
+/// you enable them again.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    post,
+    path = "/users/{username}/disable",
+    tag = "Users",
+    params(
+        ("username" = String, Path, description = "The username of the user", example = "jdoe")
+    ),
+    responses(
+        (status = 200, description = "Success", body = UserResponse),
+        (status = 400, description = "You cannot disable your own account", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The user doesn't exist", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "disable_user", skip_all)]
+pub async fn disable(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(username): Path<String>,
+) -> Result<Json<UserResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    reject_self(&span, &claims, &username)?;
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let model = find_user(db, &span, &username).await?;
+    let mut active: user::ActiveModel = model.into();
+    active.active_flag = Set(false);
+    let model = active.update(db).await?;
+
+    state.revocations.revoke_user(db, model.clone()).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(UserResponse { data: model.into() }))
+}
+
+/// Enable a user
+///
+/// Enable a previously disabled user so they can log in again.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    post,
+    path = "/users/{username}/enable",
+    tag = "Users",
+    params(
+        ("username" = String, Path, description = "The username of the user", example = "jdoe")
+    ),
+    responses(
+        (status = 200, description = "Success", body = UserResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The user doesn't exist", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "enable_user", skip_all)]
+pub async fn enable(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(username): Path<String>,
+) -> Result<Json<UserResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let model = find_user(db, &span, &username).await?;
+    let mut active: user::ActiveModel = model.into();
+    active.active_flag = Set(true);
+    let model = active.update(db).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(UserResponse { data: model.into() }))
+}
@@ -1,36 +1,57 @@
+use crate::api::conditional;
+use crate::api::purge;
 use crate::api::response::{
     create_patient_response::{
-        AddressData, 
-        BirthdateData, 
         CreatePatientResponse, 
-        NameData, 
         Patient
     },
     error::AppError
 };
 use crate::api::response::TokenClaims;
-use crate::entities::patient::{self, address, birthdate, name};
+use crate::entities::patient::{self, StoredRecord};
+use crate::entities::patient_history;
 use crate::state::ApplicationState;
 
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use axum::{
     debug_handler,
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Extension, 
     Json,
 };
 use opentelemetry::{Key, Value};
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use sea_orm::{ColumnTrait, QueryFilter};
 use std::sync::Arc;
 use tracing::instrument;
 use tracing::Span;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 use uuid::Uuid;
 
+#[derive(Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+pub struct GetPatientParams {
+    /// Return the record as it was at this RFC 3339 timestamp
+    #[param(example = "2025-03-01T00:00:00Z")]
+    pub as_of: Option<DateTime<Utc>>,
+}
+
 /// Get a patient record
 ///
 /// Get a patient record by patient ID.
+///
+/// The response carries the record's version as an `ETag`. Send it back in `If-None-Match` to
+/// get an empty 304 when the record hasn't changed, or in `If-Match` when updating or deleting it.
+///
+/// Pass `as_of` to read the record as it was at an earlier time, from its history. Times before
+/// the patient was created, or before history recording began, get a 404.
+///
+/// A patient that was deleted or purged, or that was deleted at `as_of`, gets a 410 rather than
+/// the 404 for an unknown ID.
+///
+/// Requires one of the `admin`, `clinician`, `front_desk`, or `read_only` roles, or an API key with
+/// the `patients:read` scope.
 #[utoipa::path(
     get,
     path = "/patient/{patient_id}",
@@ -38,15 +59,23 @@ use uuid::Uuid;
         // utoipa doesn't support uuid directly, so the path param
         // has to be a String instead
         ("patient_id" = String, Path, description = "Patient ID as UUID v4", example = "3973ebb8-11e5-4725-93b7-3b752caad60f
-")
+"),
+        GetPatientParams,
+        ("If-None-Match" = Option<String>, Header, description = "An ETag from an earlier response"),
     ),
     tag = "Patient Records",
     responses(
-        (status = 200, description = "Success", body = CreatePatientResponse),
+        (status = 200, description = "Success", body = CreatePatientResponse,
+            headers(("ETag" = String, description = "The version of the patient record"))),
+        (status = 304, description = "The record still matches the ETag in If-None-Match"),
         (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "No patient has this ID, or it had no recorded state at as_of", body = ErrorResponse),
+        (status = 410, description = "The patient was deleted or purged", body = ErrorResponse),
     ),
     security(
-        ("api_jwt_token" = [])
+        ("api_jwt_token" = ["admin", "clinician", "front_desk", "read_only"]),
+        ("api_key" = ["patients:read"])
     )
 )]
 #[debug_handler]
@@ -55,7 +84,9 @@ pub async fn get_patient(
     Extension(claims): Extension<TokenClaims>,
     State(state): State<Arc<ApplicationState>>,
     Path(patient_id): Path<Uuid>,
-) -> Result<Json<CreatePatientResponse>, AppError> {
+    Query(params): Query<GetPatientParams>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     let span = Span::current();
     span.set_attribute(Key::from("http.method"), Value::from("GET"));
     let name = &claims.sub;
@@ -65,102 +96,72 @@ pub async fn get_patient(
     let db_conn = state.db_conn.load();
     let db = db_conn.as_ref();
 
-    // Query the patient by UUID
-    match patient::Entity::find()
+    // A point-in-time read comes from the patient's history instead
+    if let Some(as_of) = params.as_of {
+        let Some(entry) = patient_history::Entity::find_as_of(db, patient_id, as_of).await? else {
+            if purge::was_purged(db, patient_id).await? {
+                span.set_attribute(
+                    Key::from("http.status_code"),
+                    Value::from(StatusCode::GONE.as_u16() as i64),
+                );
+                return Err(conditional::purged(patient_id));
+            }
+            let code = StatusCode::NOT_FOUND;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            return Err(AppError(
+                code,
+                anyhow!("Patient {patient_id} has no recorded state at {}", as_of.to_rfc3339()),
+            ));
+        };
+        if !entry.active_flag {
+            let code = StatusCode::GONE;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            return Err(conditional::gone(patient_id));
+        }
+        let version = entry.version;
+        let entry = state.field_encryption.open_history(entry)?;
+        return Ok(respond(&span, &headers, version, Patient::from(entry)));
+    }
+
+    // Query the patient and its related records by UUID
+    match patient::Entity::find_records()
         .filter(patient::Column::PatientId.eq(patient_id))
-        .one(state.db_conn.load().as_ref())
+        .into_model::<StoredRecord>()
+        .one(db)
         .await
     {
         // await returns Result<Option<Model>, DbErr>
         // so you have to safely unwrap all its thorny layers
         Ok(conn) => {
-            // If the search returns a hit, fetch its data,
-            // assemble the JSON, and return it
-            if let Some(model) = conn {
-                // Fetch related name
-                let name = name::Entity::find_by_id(model.name_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &patient_id)),
-                        );
-                        AppError(code, anyhow!("Birthdate record not found"))
-                    })?;
-
-                // Fetch related address
-                let address = address::Entity::find_by_id(model.address_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &patient_id)),
-                        );
-                        AppError(code, anyhow!("Birthdate record not found"))
-                    })?;
-
-                // Fetch related birthdate
-                let birthdate = birthdate::Entity::find_by_id(model.birthdate_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &patient_id)),
-                        );
-                        AppError(code, anyhow!("Birthdate record not found"))
-                    })?;
-
-                // Construct the response
-                let response_data = Patient {
-                    patient_id: model.patient_id.into(),
-                    created_at: model.created_at.to_rfc3339(),
-                    name: NameData {
-                        first: name.first,
-                        middle: name.middle,
-                        surname: name.surname,
-                    },
-                    address: AddressData {
-                        address_lines: address.address_lines,
-                        sublocality: address.sublocality,
-                        locality: address.locality,
-                        administrative_area: address.administrative_area,
-                        postal_code: address.postal_code,
-                        country_region: address.country_region,
-                    },
-                    birthdate: BirthdateData {
-                        day: birthdate.day,
-                        month: birthdate.month,
-                        year: birthdate.year,
-                    },
-                };
-                // Happy path
+            // If the search returns a hit, assemble
+            // the JSON and return it
+            if let Some(stored) = conn {
+                // A deleted patient stays hidden until an admin restores it
+                if !stored.active_flag {
+                    let code = StatusCode::GONE;
+                    span.set_attribute(
+                        Key::from("http.status_code"),
+                        Value::from(code.as_u16() as i64),
+                    );
+                    return Err(conditional::gone(patient_id));
+                }
+                let record = state.field_encryption.open(stored)?;
+                let version = record.version;
+                return Ok(respond(&span, &headers, version, Patient::from(record)));
+            // If the search is Ok, but there is no hit, return a
+            // 410 GONE error if it was purged, or 404 NOT_FOUND
+            } else if purge::was_purged(db, patient_id).await? {
                 span.set_attribute(
                     Key::from("http.status_code"),
-                    Value::from(StatusCode::OK.as_u16() as i64),
+                    Value::from(StatusCode::GONE.as_u16() as i64),
                 );
-                return Ok(Json(CreatePatientResponse {
-                    data: response_data,
-                }));
-            // If the search is Ok, but there is no hit,
-            // return a 404 NOT_FOUND error
+                return Err(conditional::purged(patient_id));
             } else {
                 let code = StatusCode::NOT_FOUND;
                 span.set_attribute(
@@ -190,3 +191,27 @@ pub async fn get_patient(
         }
     }
 }
+
+/// Sends the patient with its version as the ETag, or an empty 304 if the client's copy is current
+fn respond(span: &Span, headers: &HeaderMap, version: i32, patient: Patient) -> Response {
+    let etag = conditional::etag(version);
+
+    if conditional::if_none_match(headers, version) {
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(StatusCode::NOT_MODIFIED.as_u16() as i64),
+        );
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    // Happy path
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    (
+        [(header::ETAG, etag)],
+        Json(CreatePatientResponse { data: patient }),
+    )
+        .into_response()
+}
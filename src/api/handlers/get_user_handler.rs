@@ -0,0 +1,96 @@
+use crate::api::response::error::AppError;
+use crate::api::response::user_response::UserResponse;
+use crate::api::response::TokenClaims;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use opentelemetry::{Key, Value};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Get a user
+///
+/// Get a user by username.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    get,
+    path = "/users/{username}",
+    tag = "Users",
+    params(
+        ("username" = String, Path, description = "The username of the user", example = "jdoe")
+    ),
+    responses(
+        (status = 200, description = "Success", body = UserResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The user doesn't exist", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "get_user", skip_all)]
+pub async fn get_user(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(username): Path<String>,
+) -> Result<Json<UserResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("GET"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let model = find_user(state.db_conn.load().as_ref(), &span, &username).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(UserResponse { data: model.into() }))
+}
+
+/// Fetches a user by username, or a 404 error if there isn't one
+pub async fn find_user<C: ConnectionTrait>(
+    db: &C,
+    span: &Span,
+    username: &str,
+) -> Result<user::Model, AppError> {
+    user::Entity::find()
+        .filter(user::Column::Username.eq(username))
+        .one(db)
+        .await?
+        .ok_or_else(|| {
+            let code = StatusCode::NOT_FOUND;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            AppError(code, anyhow!("User {username} not found"))
+        })
+}
+
+/// Rejects operations that would lock the caller out of their own account
+pub fn reject_self(span: &Span, claims: &TokenClaims, username: &str) -> Result<(), AppError> {
+    if claims.sub == username {
+        let code = StatusCode::BAD_REQUEST;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("You cannot perform this operation on your own account"),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use crate::state::ApplicationState;
+use axum::{extract::State, http::StatusCode, Json};
+use jsonwebtoken::jwk::JwkSet;
+use std::sync::Arc;
+
+use opentelemetry::{Key, Value};
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Publish the token verification keys
+///
+/// Served at `/.well-known/jwks.json`, outside the versioned API, so that other services can
+/// verify access tokens without sharing a secret. The set is empty when tokens are signed with
+/// HS256.
+#[instrument(level = "info", name = "jwks", skip_all)]
+pub async fn jwks(State(state): State<Arc<ApplicationState>>) -> Json<JwkSet> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("GET"));
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Json(state.signing_keys.jwks().clone())
+}
@@ -0,0 +1,56 @@
+use crate::api::response::api_key_response::ListApiKeysResponse;
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::entities::api_key;
+use crate::state::ApplicationState;
+use axum::{debug_handler, extract::State, http::StatusCode, Extension, Json};
+use opentelemetry::{Key, Value};
+use sea_orm::{EntityTrait, QueryOrder};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// List API keys
+///
+/// Returns every API key, including revoked and expired keys, ordered by creation time. The
+/// keys themselves are never returned.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    get,
+    path = "/api-keys",
+    tag = "API keys",
+    responses(
+        (status = 200, description = "Success", body = ListApiKeysResponse),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "list_api_keys", skip_all)]
+pub async fn list(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+) -> Result<Json<ListApiKeysResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("GET"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let api_keys = api_key::Entity::find()
+        .order_by_asc(api_key::Column::CreatedAt)
+        .all(state.db_conn.load().as_ref())
+        .await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(ListApiKeysResponse {
+        api_keys: api_keys.into_iter().map(Into::into).collect(),
+    }))
+}
@@ -0,0 +1,147 @@
+use crate::api::response::audit_log_response::{AuditEntry, AuditLogResponse};
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::entities::audit_log::{self, Outcome};
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use opentelemetry::{Key, Value};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+pub struct AuditLogQuery {
+    /// Requests made by this user or API key
+    #[param(example = "jdoe")]
+    pub actor: Option<String>,
+
+    /// Requests that touched this patient
+    #[param(example = "3973ebb8-11e5-4725-93b7-3b752caad60f")]
+    pub patient_id: Option<Uuid>,
+
+    /// Requests to this method and route
+    #[param(example = "GET /v1/patient/:patient_id")]
+    pub action: Option<String>,
+
+    pub outcome: Option<Outcome>,
+
+    /// Requests made at or after this RFC 3339 timestamp
+    #[param(example = "2025-04-01T00:00:00Z")]
+    pub from: Option<DateTime<Utc>>,
+
+    /// Requests made before this RFC 3339 timestamp
+    #[param(example = "2025-05-01T00:00:00Z")]
+    pub to: Option<DateTime<Utc>>,
+
+    /// Records with an ID below this, from `next_before` of the previous page
+    pub before: Option<i32>,
+
+    /// Records per page, from 1 to 1000; defaults to 100
+    #[param(example = 100)]
+    pub limit: Option<u64>,
+}
+
+/// Query the audit log
+///
+/// Returns records of API requests, newest first, filtered by any combination of actor, patient,
+/// action, outcome and time range. Follow `next_before` to page through older records. Each
+/// record carries the hash chain that `api-doc audit export` verifies.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    get,
+    path = "/audit",
+    params(AuditLogQuery),
+    tag = "Audit",
+    responses(
+        (status = 200, description = "Success", body = AuditLogResponse),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "list_audit_log", skip_all)]
+pub async fn list(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Json<AuditLogResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("GET"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        let code = StatusCode::BAD_REQUEST;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("limit must be from 1 to {MAX_LIMIT}"),
+        ));
+    }
+
+    let mut select = audit_log::Entity::find();
+    if let Some(actor) = query.actor {
+        select = select.filter(audit_log::Column::Actor.eq(actor));
+    }
+    if let Some(patient_id) = query.patient_id {
+        select = select.filter(audit_log::Column::PatientId.eq(patient_id));
+    }
+    if let Some(action) = query.action {
+        select = select.filter(audit_log::Column::Action.eq(action));
+    }
+    if let Some(outcome) = query.outcome {
+        select = select.filter(audit_log::Column::Outcome.eq(outcome));
+    }
+    if let Some(from) = query.from {
+        select = select.filter(audit_log::Column::OccurredAt.gte(from));
+    }
+    if let Some(to) = query.to {
+        select = select.filter(audit_log::Column::OccurredAt.lt(to));
+    }
+    if let Some(before) = query.before {
+        select = select.filter(audit_log::Column::Id.lt(before));
+    }
+
+    // One extra record tells whether there is another page
+    let mut entries = select
+        .order_by_desc(audit_log::Column::Id)
+        .limit(limit + 1)
+        .all(state.db_conn.load().as_ref())
+        .await?;
+    let next_before = if entries.len() as u64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|entry| entry.id)
+    } else {
+        None
+    };
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(AuditLogResponse {
+        entries: entries.into_iter().map(AuditEntry::from).collect(),
+        next_before,
+    }))
+}
@@ -0,0 +1,117 @@
+use crate::api::audit::AuditPatients;
+use crate::api::duplicates;
+use crate::api::response::duplicates::{DuplicatePairData, DuplicatesReportResponse};
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use opentelemetry::{Key, Value};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+#[derive(Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+pub struct DuplicatesQuery {
+    /// The lowest score to report, from 0 to 1; defaults to `duplicates.match_threshold`
+    #[param(example = 0.8)]
+    pub min_score: Option<f32>,
+
+    /// The most pairs to return, from 1 to 1000; defaults to 100
+    #[param(example = 100)]
+    pub limit: Option<u64>,
+}
+
+/// Report likely duplicate patients
+///
+/// Returns pairs of active patients that are likely the same person, best match first, scored the
+/// same way as new patients are checked against existing ones. Names count for half the score,
+/// compared by trigram similarity so that misspellings still match; the same birth date and the
+/// same postal code make up the rest. Only patients born on the same date, or in the same year
+/// with the same postal code, are compared.
+///
+/// Every active patient is read to build the report, a birth year at a time, so it suits a periodic
+/// review rather than frequent polling.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    get,
+    path = "/patient/duplicates",
+    params(DuplicatesQuery),
+    tag = "Patient Records",
+    responses(
+        (status = 200, description = "Success", body = DuplicatesReportResponse),
+        (status = 400, description = "Invalid min_score or limit", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "list_duplicates", skip_all)]
+pub async fn list(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Query(query): Query<DuplicatesQuery>,
+) -> Result<(Extension<AuditPatients>, Json<DuplicatesReportResponse>), AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("GET"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let min_score = query
+        .min_score
+        .unwrap_or(state.settings.load().duplicates.match_threshold);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let invalid = if !(0.0..=1.0).contains(&min_score) {
+        Some("min_score must be from 0 to 1".to_string())
+    } else if !(1..=MAX_LIMIT).contains(&limit) {
+        Some(format!("limit must be from 1 to {MAX_LIMIT}"))
+    } else {
+        None
+    };
+    if let Some(message) = invalid {
+        let code = StatusCode::BAD_REQUEST;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!(message)));
+    }
+
+    let (total, pairs) = duplicates::report(
+        state.db_conn.load().as_ref(),
+        &state.field_encryption,
+        min_score,
+        limit as usize,
+    )
+    .await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    // Both patients of every reported pair are disclosed
+    let disclosed = pairs
+        .iter()
+        .flat_map(|pair| [pair.patient_id, pair.duplicate_id])
+        .collect();
+    Ok((
+        Extension(AuditPatients(disclosed)),
+        Json(DuplicatesReportResponse {
+            min_score,
+            total,
+            pairs: pairs.into_iter().map(DuplicatePairData::from).collect(),
+        }),
+    ))
+}
@@ -1,15 +1,16 @@
+use crate::api::audit::AuditPatients;
+use crate::api::encryption::{FieldEncryption, SearchField};
+use crate::api::redact::Redacted;
 use crate::api::response::error::AppError;
-use crate::api::response::list_patients::{
-    AddressData, 
-    BirthdateData, 
-    ListPatientsResponse, 
-    NameData, 
-    Patient,
-};
+use crate::api::response::list_patients::{ListPatientsResponse, Patient};
+use crate::api::request::validation::MAX_AGE;
 use crate::api::response::TokenClaims;
-use crate::entities::patient::{self, address, birthdate, name};
+use crate::entities::user::Role;
+use crate::entities::patient::{self, address, birthdate, name, PatientRecord, StoredRecord};
 use crate::state::ApplicationState;
 use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
 use axum::{
     debug_handler,
     extract::{Query, State},
@@ -17,20 +18,41 @@ use axum::{
     Extension, Json,
 };
 use opentelemetry::{Key, Value};
+use sea_orm::sea_query::{Expr, SimpleExpr};
 use sea_orm::{
     ColumnTrait, 
-    EntityTrait, 
-    JoinType, 
+    ConnectionTrait,
+    DatabaseBackend,
+    DbErr,
+    FromQueryResult,
+    Order,
+    PaginatorTrait,
     QueryFilter, 
+    QueryOrder,
     QuerySelect, 
-    RelationTrait
+    Select,
+    Statement,
+    TransactionTrait,
 };
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::instrument;
 use tracing::Span;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-#[derive(Debug, serde::Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+/// The page size used when the request doesn't set `limit`
+const DEFAULT_LIMIT: u64 = 50;
+
+/// The largest page a request may ask for
+const MAX_LIMIT: u64 = 200;
+
+/// The lowest trigram similarity at which `fuzzy` matches a name
+///
+/// A little below the `pg_trgm` default of 0.3, so that short names one letter apart, such as
+/// "Jon" and "John", still match.
+const SIMILARITY_THRESHOLD: f32 = 0.25;
+
+#[derive(Debug, Default, Serialize, Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
 pub struct GetPatientQuery {
     #[schema(example = "Jane")]
     pub first_name: Option<String>,
@@ -40,12 +62,250 @@ pub struct GetPatientQuery {
 
     #[schema(example = "1974")]
     pub birth_year: Option<i32>,
+
+    /// How `first_name` and `surname` match; defaults to `prefix`, or to `exact` when patient
+    /// fields are encrypted
+    #[param(inline)]
+    pub name_match: Option<NameMatch>,
+
+    /// Patients born on this date
+    #[schema(example = "1997-08-06")]
+    pub birth_date: Option<NaiveDate>,
+
+    /// Patients born on or after this date
+    #[schema(example = "1990-01-01")]
+    pub born_from: Option<NaiveDate>,
+
+    /// Patients born on or before this date
+    #[schema(example = "1999-12-31")]
+    pub born_to: Option<NaiveDate>,
+
+    /// Patients at least this many years old today
+    #[schema(example = "18")]
+    pub min_age: Option<u32>,
+
+    /// Patients at most this many years old today
+    #[schema(example = "65")]
+    pub max_age: Option<u32>,
+
+    /// Patients with this postal code, ignoring case
+    #[schema(example = "97211")]
+    pub postal_code: Option<String>,
+
+    /// Patients in this locality, ignoring case
+    #[schema(example = "Portland")]
+    pub locality: Option<String>,
+
+    /// Patients in this administrative area, ignoring case
+    #[schema(example = "OR")]
+    pub administrative_area: Option<String>,
+
+    /// Patients in this country or region, ignoring case
+    #[schema(example = "US")]
+    pub country_region: Option<String>,
+
+    /// Records created at or after this RFC 3339 timestamp
+    #[schema(example = "2025-04-01T00:00:00Z")]
+    pub created_from: Option<DateTime<Utc>>,
+
+    /// Records created before this RFC 3339 timestamp
+    #[schema(example = "2025-05-01T00:00:00Z")]
+    pub created_to: Option<DateTime<Utc>>,
+
+    /// Which records to return by whether they were deleted; defaults to `active`. Other values
+    /// require the `admin` role.
+    #[param(inline)]
+    pub status: Option<PatientStatus>,
+
+    /// Also return deleted records, like `status=all`; requires the `admin` role
+    #[schema(example = "false")]
+    pub include_inactive: Option<bool>,
+
+    /// The maximum number of patients to return, from 1 to 200; defaults to 50
+    #[schema(example = "50")]
+    pub limit: Option<u64>,
+
+    /// The `next_cursor` value from the previous page
+    pub cursor: Option<String>,
+
+    /// The field to sort by; defaults to `created_at`. Sorting by `relevance` requires a name
+    /// filter and a `name_match` other than `exact`.
+    #[param(inline)]
+    pub sort: Option<PatientSort>,
+
+    /// The sort direction; defaults to `desc` for `relevance` and `asc` otherwise
+    #[param(inline)]
+    pub order: Option<SortOrder>,
+
+    /// Also return the number of matching patients across all pages, which takes a second query
+    #[schema(example = "false")]
+    pub include_total: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PatientSort {
+    Surname,
+    #[default]
+    CreatedAt,
+    Birthdate,
+    Relevance,
+}
+
+/// How the name filters compare against patient names
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NameMatch {
+    /// Case-sensitive equality
+    Exact,
+    /// Case-insensitive prefix
+    #[default]
+    Prefix,
+    /// Case-insensitive prefix, or trigram similarity of at least 0.25
+    Fuzzy,
+    /// Names that sound alike, sharing a primary or alternate Double Metaphone code
+    Phonetic,
+}
+
+/// Which patients to list by whether they were deleted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PatientStatus {
+    /// Patients that weren't deleted
+    #[default]
+    Active,
+    /// Deleted patients, which an admin can restore
+    Inactive,
+    All,
+}
+
+impl GetPatientQuery {
+    /// The requested status, from `status` or the older `include_inactive`
+    fn status(&self) -> PatientStatus {
+        self.status.unwrap_or(match self.include_inactive {
+            Some(true) => PatientStatus::All,
+            _ => PatientStatus::Active,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl From<SortOrder> for Order {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Asc => Order::Asc,
+            SortOrder::Desc => Order::Desc,
+        }
+    }
+}
+
+/// The position after the last patient of a page
+///
+/// Holds that patient's sort key, with its row ID to break ties, and is handed to clients
+/// base64-encoded so they treat it as opaque.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    order: SortOrder,
+    #[serde(flatten)]
+    key: CursorKey,
+    id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "sort", rename_all = "snake_case")]
+enum CursorKey {
+    Surname { surname: String },
+    CreatedAt { created_at: DateTime<Utc> },
+    Birthdate { year: i32, month: i32, day: i32 },
+    Relevance { score: f32 },
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursors always serialize"))
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    fn sort(&self) -> PatientSort {
+        match self.key {
+            CursorKey::Surname { .. } => PatientSort::Surname,
+            CursorKey::CreatedAt { .. } => PatientSort::CreatedAt,
+            CursorKey::Birthdate { .. } => PatientSort::Birthdate,
+            CursorKey::Relevance { .. } => PatientSort::Relevance,
+        }
+    }
+
+    /// Matches the rows that sort after the cursor
+    ///
+    /// `score` is the relevance expression, which relevance cursors compare against.
+    fn after(&self, score: Option<&SimpleExpr>) -> SimpleExpr {
+        let (mut columns, mut values): (Vec<SimpleExpr>, Vec<SimpleExpr>) = match &self.key {
+            CursorKey::Surname { surname } => (
+                vec![Expr::col((name::Entity, name::Column::Surname)).into()],
+                vec![Expr::value(surname.clone())],
+            ),
+            CursorKey::CreatedAt { created_at } => (
+                vec![Expr::col((patient::Entity, patient::Column::CreatedAt)).into()],
+                vec![Expr::value(*created_at)],
+            ),
+            CursorKey::Birthdate { year, month, day } => (
+                vec![
+                    Expr::col((birthdate::Entity, birthdate::Column::Year)).into(),
+                    Expr::col((birthdate::Entity, birthdate::Column::Month)).into(),
+                    Expr::col((birthdate::Entity, birthdate::Column::Day)).into(),
+                ],
+                vec![Expr::value(*year), Expr::value(*month), Expr::value(*day)],
+            ),
+            CursorKey::Relevance { score: value } => (
+                score.into_iter().cloned().collect(),
+                vec![Expr::value(*value)],
+            ),
+        };
+        columns.push(Expr::col((patient::Entity, patient::Column::Id)).into());
+        values.push(Expr::value(self.id));
+
+        // Row comparison keeps the keyset to a single index-friendly predicate
+        let columns = Expr::tuple(columns);
+        match self.order {
+            SortOrder::Asc => columns.gt(Expr::tuple(values)),
+            SortOrder::Desc => columns.lt(Expr::tuple(values)),
+        }
+    }
 }
 
 /// List patient records
 ///
-/// Returns a list of patient records based on optional query parameters. The system returns all
-/// active records if you do not provide any query arguments.
+/// Returns a page of active patient records matching the optional query parameters, sorted by
+/// `sort` and `order`. If more records match, `next_cursor` is set; pass it as `cursor`, keeping
+/// the other parameters the same, to fetch the next page. Set `include_total` to also count the
+/// matching records across all pages.
+///
+/// Name filters match case-insensitive prefixes by default, so `smi` finds "Smith". Set
+/// `name_match` to `fuzzy` to also find misspellings, or to `phonetic` to find names that sound
+/// alike. Unless `name_match` is `exact`, each patient has a relevance `score` from 0 to 1, the
+/// mean trigram similarity of the filtered names, which `sort=relevance` orders by.
+///
+/// Birth date and age ranges are inclusive. Address filters ignore case. Admins can set `status`
+/// to `inactive` to list deleted records, which carry who deleted them and when, or to `all` to
+/// list every record.
+///
+/// When the server encrypts patient fields, names, birth dates, localities and postal codes only
+/// match exactly, ignoring case, and sorting by them or filtering by birth date or age range gets
+/// a 400.
+///
+/// Requires one of the `admin`, `clinician`, `front_desk`, or `read_only` roles, or an API key with
+/// the `patients:read` scope.
 #[utoipa::path(
     get,
     path = "/patient",
@@ -53,10 +313,12 @@ pub struct GetPatientQuery {
     tag = "Patient Records",
     responses(
         (status = 200, description = "Success", body = ListPatientsResponse),
-        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 400, description = "Invalid limit, cursor, sort, or age, or a search that encrypted fields don't support", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation, or to list deleted records", body = ErrorResponse),
     ),
     security(
-        ("api_jwt_token" = [])
+        ("api_jwt_token" = ["admin", "clinician", "front_desk", "read_only"]),
+        ("api_key" = ["patients:read"])
     )
 )]
 #[debug_handler]
@@ -65,136 +327,222 @@ pub async fn list(
     Extension(claims): Extension<TokenClaims>,
     State(state): State<Arc<ApplicationState>>,
     Query(query): Query<GetPatientQuery>,
-) -> Result<Json<ListPatientsResponse>, AppError> {
+) -> Result<(Extension<AuditPatients>, Json<ListPatientsResponse>), AppError> {
     // Create a span and add info
     let span = Span::current();
     span.set_attribute(Key::from("http.method"), Value::from("POST"));
     span.set_attribute(
         Key::from("request.payload"),
-        Value::from(format!("{:?}", &query)),
+        Value::from(Redacted::new(&query, &state.settings.load().redaction).to_string()),
     );
     let name = &claims.sub;
     span.set_attribute(Key::from("user"), Value::from(name.to_string()));
 
-    // Create a DB connection binding to share it
-    let db_conn = state.db_conn.load();
-    let db = db_conn.as_ref();
+    let sort = query.sort.unwrap_or_default();
+    let order = query.order.unwrap_or(match sort {
+        PatientSort::Relevance => SortOrder::Desc,
+        _ => SortOrder::Asc,
+    });
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        let code = StatusCode::BAD_REQUEST;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("limit must be between 1 and {MAX_LIMIT}"),
+        ));
+    }
 
-    // Start building the query
-    let mut query_builder = patient::Entity::find();
-    query_builder = query_builder
-        .join(JoinType::LeftJoin, patient::Relation::Name.def())
-        .join(JoinType::LeftJoin, patient::Relation::Birthdate.def())
-        // Only returns active (non-deleted) patient records
-        .filter(patient::Column::ActiveFlag.into_expr().eq(true));
+    if query.min_age.max(query.max_age).is_some_and(|age| age > MAX_AGE) {
+        let code = StatusCode::BAD_REQUEST;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("min_age and max_age must be at most {MAX_AGE}"),
+        ));
+    }
 
-    // Add filters if query parameters are present
-    if let Some(first) = &query.first_name {
-        query_builder = query_builder.filter(name::Column::First.eq(first));
+    if query.status.is_some() && query.include_inactive.is_some() {
+        let code = StatusCode::BAD_REQUEST;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("Set either status or include_inactive, not both"),
+        ));
     }
-    if let Some(surname) = &query.surname {
-        query_builder = query_builder.filter(name::Column::Surname.eq(surname));
+
+    // Deleted records are only visible to admins
+    if query.status() != PatientStatus::Active && claims.role != Role::Admin {
+        let code = StatusCode::FORBIDDEN;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("Only admins can list inactive records"),
+        ));
     }
-    if let Some(year) = &query.birth_year {
-        query_builder = query_builder.filter(birthdate::Column::Year.eq(*year));
+
+    // Encrypted fields can only be compared for equality
+    let encryption = &state.field_encryption;
+    if encryption.is_enabled() {
+        if let Some(reason) = unsupported_when_encrypted(&query, sort) {
+            let code = StatusCode::BAD_REQUEST;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            return Err(AppError(code, anyhow!(reason)));
+        }
+    }
+
+    // A cursor only makes sense for the ordering it was issued for
+    let cursor = match query
+        .cursor
+        .as_deref()
+        .filter(|cursor| !cursor.is_empty())
+        .map(Cursor::decode)
+    {
+        None => None,
+        Some(Some(cursor)) if cursor.sort() == sort && cursor.order == order => Some(cursor),
+        Some(_) => {
+            let code = StatusCode::BAD_REQUEST;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            return Err(AppError(
+                code,
+                anyhow!("Invalid cursor for this sort and order"),
+            ));
+        }
+    };
+
+    // Create a DB connection binding to share it
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let (query_builder, score) = matching(&query, encryption);
+    if sort == PatientSort::Relevance && score.is_none() {
+        let code = StatusCode::BAD_REQUEST;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("Sorting by relevance requires a name filter and a name_match other than exact"),
+        ));
     }
 
     // Execute the query and get the patients
-    let patient_models = query_builder.all(db).await;
-
-    let mut response_vec = Vec::new();
-
-    match patient_models {
-        // await returns Result<Option<Model>, DbErr>
-        // so you have to safely unwrap all its thorny layers
-        Ok(conn) => {
-            // Fetches data for each match and pushes to response vec
-            for model in conn {
-                // Fetch related name
-                let name = name::Entity::find_by_id(model.name_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &query)),
-                        );
-                        AppError(code, anyhow!("Name record not found"))
-                    })?;
-
-                // Fetch related address
-                let address = address::Entity::find_by_id(model.address_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &query)),
-                        );
-                        AppError(code, anyhow!("Address record not found"))
-                    })?;
-
-                // Fetch related birthdate
-                let birthdate = birthdate::Entity::find_by_id(model.birthdate_id)
-                    .one(db)
-                    .await?
-                    .ok_or_else(|| {
-                        let code = StatusCode::NOT_FOUND;
-                        span.set_attribute(
-                            Key::from("http.status_code"),
-                            Value::from(code.as_u16() as i64),
-                        );
-                        span.set_attribute(
-                            Key::from("request.payload"),
-                            Value::from(format!("{:?}", &query)),
-                        );
-                        AppError(code, anyhow!("Birthdate record not found"))
-                    })?;
-
-                // Construct the Patient
-                let patient = Patient {
-                    patient_id: model.patient_id.into(),
-                    created_at: model.created_at.to_rfc3339(),
-                    name: NameData {
-                        first: name.first,
-                        middle: name.middle,
-                        surname: name.surname,
+    let page = Page {
+        score: score.as_ref(),
+        sort,
+        order,
+        limit,
+        cursor: cursor.as_ref(),
+        include_total: query.include_total == Some(true),
+    };
+    let has_name = query.first_name.is_some() || query.surname.is_some();
+    let records = match query.name_match {
+        // `%` matches against the session's threshold, so it is set for this transaction only
+        Some(NameMatch::Fuzzy) if has_name => {
+            async {
+                let txn = db.begin().await?;
+                txn.execute(Statement::from_sql_and_values(
+                    DatabaseBackend::Postgres,
+                    "SELECT set_config('pg_trgm.similarity_threshold', $1, true)",
+                    [SIMILARITY_THRESHOLD.to_string().into()],
+                ))
+                .await?;
+                let records = find_page(&txn, query_builder, page).await?;
+                txn.commit().await?;
+                Ok(records)
+            }
+            .await
+        }
+        _ => find_page(db, query_builder, page).await,
+    };
+
+    // Decrypts the page, which fails as a whole if any patient can't be read
+    let records = records.map_err(anyhow::Error::from).and_then(|(total, records)| {
+        records
+            .into_iter()
+            .map(|listed| {
+                Ok(ListedPatient {
+                    record: encryption.open(listed.record)?,
+                    score: listed.score,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map(|records| (total, records))
+    });
+
+    match records {
+        Ok((total, mut records)) => {
+            let has_more = records.len() as u64 > limit;
+            records.truncate(limit as usize);
+
+            // Positions the cursor after the last patient on the page
+            let next_cursor = records.last().filter(|_| has_more).map(|record| {
+                let key = match sort {
+                    PatientSort::Surname => CursorKey::Surname {
+                        surname: record.record.surname.clone(),
+                    },
+                    PatientSort::CreatedAt => CursorKey::CreatedAt {
+                        created_at: record.record.created_at,
                     },
-                    address: AddressData {
-                        address_lines: address.address_lines,
-                        sublocality: address.sublocality,
-                        locality: address.locality,
-                        administrative_area: address.administrative_area,
-                        postal_code: address.postal_code,
-                        country_region: address.country_region,
+                    PatientSort::Birthdate => CursorKey::Birthdate {
+                        year: record.record.year,
+                        month: record.record.month,
+                        day: record.record.day,
                     },
-                    birthdate: BirthdateData {
-                        day: birthdate.day,
-                        month: birthdate.month,
-                        year: birthdate.year,
+                    PatientSort::Relevance => CursorKey::Relevance {
+                        score: record.score.unwrap_or_default(),
                     },
                 };
-
-                response_vec.push(patient);
-            }
+                Cursor {
+                    order,
+                    key,
+                    id: record.record.id,
+                }
+                .encode()
+            });
 
             span.set_attribute(
                 Key::from("http.status_code"),
                 Value::from(StatusCode::OK.as_u16() as i64),
             );
-            return Ok(Json(ListPatientsResponse {
-                patients: response_vec,
-            }));
+            // Every patient on the page is disclosed, so each is audited
+            let disclosed = records
+                .iter()
+                .map(|record| record.record.patient_id)
+                .collect();
+            Ok((
+                Extension(AuditPatients(disclosed)),
+                Json(ListPatientsResponse {
+                    patients: records
+                        .into_iter()
+                        .map(|record| Patient {
+                            score: record.score,
+                            ..Patient::from(record.record)
+                        })
+                        .collect(),
+                    next_cursor,
+                    total,
+                }),
+            ))
         }
         // If the search is not Ok, issue a generic DB
         // connection error and obfuscate the specifics
@@ -206,9 +554,489 @@ pub async fn list(
             );
             span.set_attribute(
                 Key::from("request.payload"),
-                Value::from(format!("{:?}", &query)),
+                Value::from(Redacted::new(&query, &state.settings.load().redaction).to_string()),
             );
-            return Err(AppError(code, anyhow!("Uh oh...")));
+            Err(AppError(code, anyhow!("Uh oh...")))
+        }
+    }
+}
+
+/// A patient record with its relevance to the name filters
+#[derive(Debug, FromQueryResult)]
+struct ListedRecord {
+    #[sea_orm(nested)]
+    record: StoredRecord,
+    score: Option<f32>,
+}
+
+/// A listed record, decrypted
+struct ListedPatient {
+    record: PatientRecord,
+    score: Option<f32>,
+}
+
+/// Explains why a query can't run against encrypted fields, if it can't
+fn unsupported_when_encrypted(query: &GetPatientQuery, sort: PatientSort) -> Option<&'static str> {
+    let has_name = query.first_name.is_some() || query.surname.is_some();
+    let has_date_range = query.born_from.is_some()
+        || query.born_to.is_some()
+        || query.min_age.is_some()
+        || query.max_age.is_some();
+
+    if has_name
+        && query
+            .name_match
+            .is_some_and(|mode| mode != NameMatch::Exact)
+    {
+        Some("Patient names are encrypted, so name_match must be exact")
+    } else if has_date_range {
+        Some("Birth dates are encrypted, so filter by birth_date or birth_year instead of a date or age range")
+    } else if matches!(
+        sort,
+        PatientSort::Surname | PatientSort::Birthdate | PatientSort::Relevance
+    ) {
+        Some("Names and birth dates are encrypted, so patients can only be sorted by created_at")
+    } else {
+        None
+    }
+}
+
+/// Selects the active patients that match the query filters, with their relevance scores
+///
+/// Also returns the relevance expression, which is `None` when no name is matched inexactly.
+/// With encryption on, the filters on encrypted fields compare blind indexes instead.
+fn matching(
+    query: &GetPatientQuery,
+    encryption: &FieldEncryption,
+) -> (Select<patient::Entity>, Option<SimpleExpr>) {
+    let mut query_builder = patient::Entity::find_records();
+
+    // Only returns active (non-deleted) patient records unless asked otherwise
+    match query.status() {
+        PatientStatus::Active => {
+            query_builder = query_builder.filter(patient::Column::ActiveFlag.into_expr().eq(true));
+        }
+        PatientStatus::Inactive => {
+            query_builder = query_builder.filter(patient::Column::ActiveFlag.into_expr().eq(false));
+        }
+        PatientStatus::All => {}
+    }
+
+    // Add filters if query parameters are present
+    let encrypted = encryption.is_enabled();
+    let mode = query.name_match.unwrap_or(match encrypted {
+        true => NameMatch::Exact,
+        false => NameMatch::default(),
+    });
+    let names = [
+        (
+            name::Column::First,
+            name::Column::FirstIndex,
+            SearchField::First,
+            &query.first_name,
+        ),
+        (
+            name::Column::Surname,
+            name::Column::SurnameIndex,
+            SearchField::Surname,
+            &query.surname,
+        ),
+    ];
+    let mut similarities = Vec::new();
+    for (column, index, field, value) in names {
+        let Some(value) = value else { continue };
+        if encrypted {
+            query_builder = query_builder.filter(blind_index_matches(
+                Expr::col((name::Entity, index)),
+                Expr::col((name::Entity, column)).into(),
+                encryption.blind_indexes(field, value),
+                value,
+            ));
+            continue;
         }
+        query_builder = query_builder.filter(name_matches(column, value, mode));
+        if mode != NameMatch::Exact {
+            similarities.push(similarity(column, value));
+        }
+    }
+    if let Some(year) = &query.birth_year {
+        query_builder = query_builder.filter(birthdate::Column::Year.eq(*year));
+    }
+    if let Some(date) = query.birth_date {
+        query_builder = query_builder.filter(match encrypted {
+            true => Expr::col((birthdate::Entity, birthdate::Column::BirthDateIndex))
+                .is_in(encryption.blind_indexes(SearchField::BirthDate, &date.to_string()))
+                .or(plaintext().and(birth_date().eq(date_value(date)))),
+            false => birth_date().eq(date_value(date)),
+        });
+    }
+    if let Some(date) = query.born_from {
+        query_builder = query_builder.filter(birth_date().gte(date_value(date)));
+    }
+    if let Some(date) = query.born_to {
+        query_builder = query_builder.filter(birth_date().lte(date_value(date)));
+    }
+
+    // Someone is N years old from their Nth birthday until the day before their N+1th
+    let today = Utc::now().date_naive();
+    if let Some(date) = query.min_age.and_then(|age| years_before(today, age)) {
+        query_builder = query_builder.filter(birth_date().lte(date_value(date)));
+    }
+    if let Some(date) = query.max_age.and_then(|age| years_before(today, age + 1)) {
+        query_builder = query_builder.filter(birth_date().gt(date_value(date)));
+    }
+
+    let encrypted_addresses = [
+        (
+            address::Column::PostalCode,
+            address::Column::PostalCodeIndex,
+            SearchField::PostalCode,
+            &query.postal_code,
+        ),
+        (
+            address::Column::Locality,
+            address::Column::LocalityIndex,
+            SearchField::Locality,
+            &query.locality,
+        ),
+    ];
+    for (column, index, field, value) in encrypted_addresses {
+        let Some(value) = value else { continue };
+        query_builder = query_builder.filter(match encrypted {
+            true => blind_index_matches(
+                Expr::col((address::Entity, index)),
+                Expr::col((address::Entity, column)).into(),
+                encryption.blind_indexes(field, value),
+                value,
+            ),
+            false => equals_ignoring_case(Expr::col((address::Entity, column)).into(), value),
+        });
+    }
+
+    let addresses = [
+        (address::Column::AdministrativeArea, &query.administrative_area),
+        (address::Column::CountryRegion, &query.country_region),
+    ];
+    for (column, value) in addresses {
+        if let Some(value) = value {
+            query_builder = query_builder.filter(equals_ignoring_case(
+                Expr::col((address::Entity, column)).into(),
+                value,
+            ));
+        }
+    }
+
+    if let Some(from) = query.created_from {
+        query_builder = query_builder.filter(patient::Column::CreatedAt.gte(from));
+    }
+    if let Some(to) = query.created_to {
+        query_builder = query_builder.filter(patient::Column::CreatedAt.lt(to));
+    }
+
+    // Averages the similarity of each filtered name
+    let count = similarities.len() as f32;
+    let score = similarities
+        .into_iter()
+        .reduce(SimpleExpr::add)
+        .map(|sum| Expr::cust_with_exprs("CAST(($1) / $2 AS real)", [sum, Expr::value(count)]));
+    let query_builder = query_builder.expr_as(
+        score.clone().unwrap_or_else(|| Expr::cust("NULL::real")),
+        "score",
+    );
+
+    (query_builder, score)
+}
+
+/// Matches an encrypted field by its blind index under any key, or, for patients still stored in
+/// plaintext, by the field itself ignoring case
+fn blind_index_matches(
+    index: Expr,
+    column: SimpleExpr,
+    indexes: Vec<String>,
+    value: &str,
+) -> SimpleExpr {
+    index
+        .is_in(indexes)
+        .or(plaintext().and(equals_ignoring_case(column, value)))
+}
+
+/// Patients whose fields aren't encrypted yet
+fn plaintext() -> SimpleExpr {
+    Expr::col((patient::Entity, patient::Column::KeyVersion)).is_null()
+}
+
+fn equals_ignoring_case(column: SimpleExpr, value: &str) -> SimpleExpr {
+    Expr::cust_with_exprs("lower($1) = lower($2)", [column, Expr::value(value)])
+}
+
+/// The birth date columns, which compare in date order as a tuple
+fn birth_date() -> Expr {
+    Expr::tuple([
+        Expr::col((birthdate::Entity, birthdate::Column::Year)).into(),
+        Expr::col((birthdate::Entity, birthdate::Column::Month)).into(),
+        Expr::col((birthdate::Entity, birthdate::Column::Day)).into(),
+    ])
+}
+
+fn date_value(date: NaiveDate) -> Expr {
+    Expr::tuple([
+        Expr::value(date.year()),
+        Expr::value(date.month() as i32),
+        Expr::value(date.day() as i32),
+    ])
+}
+
+/// The same day the given number of years earlier, or February 28 for February 29
+fn years_before(date: NaiveDate, years: u32) -> Option<NaiveDate> {
+    date.checked_sub_months(Months::new(years * 12))
+}
+
+/// Compares a name column against a filter value in the given mode
+fn name_matches(column: name::Column, value: &str, mode: NameMatch) -> SimpleExpr {
+    let column: SimpleExpr = Expr::col((name::Entity, column)).into();
+    let prefix = || {
+        Expr::cust_with_exprs(
+            "lower($1) LIKE lower($2)",
+            [column.clone(), Expr::value(format!("{}%", escape_like(value)))],
+        )
+    };
+
+    match mode {
+        NameMatch::Exact => column.eq(value),
+        NameMatch::Prefix => prefix(),
+        // `%` rather than `similarity() >=`, so that the trigram indexes can serve it
+        NameMatch::Fuzzy => prefix().or(Expr::cust_with_exprs(
+            "lower($1) % lower($2)",
+            [column.clone(), Expr::value(value)],
+        )),
+        NameMatch::Phonetic => Expr::cust_with_exprs(
+            "(dmetaphone($1) IN (dmetaphone($2), dmetaphone_alt($2)) \
+             OR dmetaphone_alt($1) IN (dmetaphone($2), dmetaphone_alt($2)))",
+            [column.clone(), Expr::value(value)],
+        ),
+    }
+}
+
+/// The trigram similarity of a name column to a filter value, from 0 to 1
+fn similarity(column: name::Column, value: &str) -> SimpleExpr {
+    Expr::cust_with_exprs(
+        "similarity(lower($1), lower($2))",
+        [Expr::col((name::Entity, column)).into(), Expr::value(value)],
+    )
+}
+
+/// Escapes the `LIKE` wildcards in a value, so that it matches literally
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Which page of the matching patients to load
+struct Page<'a> {
+    score: Option<&'a SimpleExpr>,
+    sort: PatientSort,
+    order: SortOrder,
+    limit: u64,
+    cursor: Option<&'a Cursor>,
+    include_total: bool,
+}
+
+/// Loads the page after the cursor, with one extra record to show whether there's another page
+///
+/// Also counts every match when asked, which takes a query of its own before the page's.
+async fn find_page<C: ConnectionTrait>(
+    db: &C,
+    mut query_builder: Select<patient::Entity>,
+    Page {
+        score,
+        sort,
+        order,
+        limit,
+        cursor,
+        include_total,
+    }: Page<'_>,
+) -> Result<(Option<u64>, Vec<ListedRecord>), DbErr> {
+    let total = match include_total {
+        true => Some(query_builder.clone().count(db).await?),
+        false => None,
+    };
+
+    if let Some(cursor) = cursor {
+        query_builder = query_builder.filter(cursor.after(score));
+    }
+    query_builder = match sort {
+        PatientSort::Surname => query_builder.order_by(name::Column::Surname, order.into()),
+        PatientSort::CreatedAt => query_builder.order_by(patient::Column::CreatedAt, order.into()),
+        PatientSort::Birthdate => query_builder
+            .order_by(birthdate::Column::Year, order.into())
+            .order_by(birthdate::Column::Month, order.into())
+            .order_by(birthdate::Column::Day, order.into()),
+        PatientSort::Relevance => match score {
+            Some(score) => query_builder.order_by(score.clone(), order.into()),
+            None => query_builder,
+        },
+    };
+
+    query_builder
+        .order_by(patient::Column::Id, order.into())
+        .limit(limit + 1)
+        .into_model::<ListedRecord>()
+        .all(db)
+        .await
+        .map(|records| (total, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{MockDatabase, MockExecResult};
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    fn record(id: i32, surname: &str) -> BTreeMap<&'static str, sea_orm::Value> {
+        BTreeMap::from([
+            ("id", id.into()),
+            ("active_flag", true.into()),
+            ("patient_id", Uuid::new_v4().into()),
+            ("created_at", Utc::now().into()),
+            ("version", 1i32.into()),
+            ("key_version", Option::<i32>::None.into()),
+            ("deleted_at", Option::<DateTime<Utc>>::None.into()),
+            ("deleted_by", Option::<String>::None.into()),
+            ("name_id", id.into()),
+            ("address_id", id.into()),
+            ("birthdate_id", id.into()),
+            ("first", "Jane".into()),
+            ("middle", "Q.".into()),
+            ("surname", surname.into()),
+            ("address_lines", vec!["123 Fake St.".to_string()].into()),
+            ("sublocality", "Brooklyn".into()),
+            ("locality", "Portland".into()),
+            ("administrative_area", "OR".into()),
+            ("postal_code", "97211".into()),
+            ("country_region", "US".into()),
+            ("day", 6.into()),
+            ("month", 8.into()),
+            ("year", 1997.into()),
+            ("sealed_date", Option::<String>::None.into()),
+            ("score", Some(1.0f32).into()),
+        ])
+    }
+
+    fn claims() -> TokenClaims {
+        TokenClaims {
+            jti: Uuid::new_v4(),
+            sub: "jdoe".to_string(),
+            role: Role::ReadOnly,
+            iat: 0.0,
+            exp: usize::MAX,
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_a_page_in_one_query() {
+        let rows: Vec<_> = (11..=35).map(|id| record(id, "Doe")).collect();
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([rows])
+                .into_connection(),
+        );
+
+        let cursor = Cursor {
+            order: SortOrder::Asc,
+            key: CursorKey::Surname {
+                surname: "Doe".to_string(),
+            },
+            id: 10,
+        };
+        let query = GetPatientQuery {
+            surname: Some("Doe".to_string()),
+            cursor: Some(cursor.encode()),
+            sort: Some(PatientSort::Surname),
+            limit: Some(20),
+            ..Default::default()
+        };
+        let (_, Json(page)) = list(Extension(claims()), State(state.clone()), Query(query))
+            .await
+            .unwrap();
+
+        assert_eq!(page.patients.len(), 20);
+        assert!(page.next_cursor.is_some());
+        assert!(page.total.is_none());
+        assert_eq!(state.into_transaction_log().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn counts_every_match_in_one_more_query() {
+        let rows: Vec<_> = (1..=3).map(|id| record(id, "Doe")).collect();
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([[BTreeMap::from([("num_items", 3i64.into())])]])
+                .append_query_results([rows])
+                .into_connection(),
+        );
+
+        let query = GetPatientQuery {
+            surname: Some("Doe".to_string()),
+            include_total: Some(true),
+            ..Default::default()
+        };
+        let (_, Json(page)) = list(Extension(claims()), State(state.clone()), Query(query))
+            .await
+            .unwrap();
+
+        assert_eq!(page.patients.len(), 3);
+        assert_eq!(page.total, Some(3));
+        assert!(page.next_cursor.is_none());
+
+        let log = state.into_transaction_log();
+        assert_eq!(log.len(), 2);
+        assert!(log[0].statements()[0].sql.starts_with("SELECT COUNT(*)"));
+    }
+
+    #[tokio::test]
+    async fn fuzzy_names_use_the_trigram_operator() {
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_exec_results([MockExecResult {
+                    last_insert_id: 0,
+                    rows_affected: 1,
+                }])
+                .append_query_results([[record(1, "Jon")]])
+                .into_connection(),
+        );
+
+        let query = GetPatientQuery {
+            surname: Some("John".to_string()),
+            name_match: Some(NameMatch::Fuzzy),
+            ..Default::default()
+        };
+        let (_, Json(page)) = list(Extension(claims()), State(state.clone()), Query(query))
+            .await
+            .unwrap();
+        assert_eq!(page.patients.len(), 1);
+
+        let log = state.into_transaction_log();
+        let statements = log[0].statements();
+        assert_eq!(statements.len(), 4);
+        assert_eq!(statements[0].sql, "BEGIN");
+        assert!(statements[1].sql.contains("pg_trgm.similarity_threshold"));
+        assert_eq!(
+            statements[1].values.as_ref().unwrap().0,
+            vec![SIMILARITY_THRESHOLD.to_string().into()]
+        );
+        let select = &statements[2].sql;
+        assert!(
+            select.contains(r#"lower("name"."surname") % lower($"#),
+            "{select}"
+        );
+        assert!(!select.contains(">= $"), "{select}");
+        assert_eq!(statements[3].sql, "COMMIT");
     }
 }
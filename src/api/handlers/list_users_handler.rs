@@ -0,0 +1,55 @@
+use crate::api::response::error::AppError;
+use crate::api::response::user_response::ListUsersResponse;
+use crate::api::response::TokenClaims;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use axum::{debug_handler, extract::State, http::StatusCode, Extension, Json};
+use opentelemetry::{Key, Value};
+use sea_orm::{EntityTrait, QueryOrder};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// List users
+///
+/// Returns every user, including disabled users, ordered by username.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "Users",
+    responses(
+        (status = 200, description = "Success", body = ListUsersResponse),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "list_users", skip_all)]
+pub async fn list(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+) -> Result<Json<ListUsersResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("GET"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let users = user::Entity::find()
+        .order_by_asc(user::Column::Username)
+        .all(state.db_conn.load().as_ref())
+        .await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(ListUsersResponse {
+        users: users.into_iter().map(Into::into).collect(),
+    }))
+}
@@ -1,18 +1,21 @@
+use crate::api::middleware::client_ip::ClientIp;
 use crate::api::middleware::json::CustomJson;
 use crate::api::request::login_request::LoginRequest;
+use crate::api::redact::Redacted;
 use crate::api::response::error::AppError;
 use crate::api::response::login_response::LoginResponse;
-use crate::api::response::TokenClaims;
+use crate::api::response::mfa_response::MfaChallengeResponse;
+use crate::api::auth::mfa;
+use crate::api::auth::password::{dummy_hash, validate_password};
+use crate::api::auth::tokens;
 use crate::state::ApplicationState;
 use axum::extract::State;
 use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use jsonwebtoken::{encode, EncodingKey, Header};
 use std::sync::Arc;
 
 use anyhow::anyhow;
-use argon2::Argon2;
-use password_hash::{PasswordHash, PasswordVerifier};
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use tracing::instrument;
 //use tracing::{Level, Span};
@@ -24,7 +27,12 @@ use crate::entities::user;
 
 /// Generate a JWT
 ///
-/// Generate a JSON web token (JWT) from your user credentials.
+/// Generate a JSON web token (JWT) from your user credentials. Repeated failures for a username
+/// or from a client require increasing delays between attempts and eventually lock it out
+/// temporarily.
+///
+/// If you have enabled multi-factor authentication, a correct password instead returns a
+/// short-lived challenge token to exchange, together with a code, at `/login/mfa`.
 #[utoipa::path(
     post,
     path = "/login",
@@ -32,20 +40,46 @@ use crate::entities::user;
     request_body = LoginRequest,
     responses(
         (status = 200, description = "Success", body = LoginResponse),
+        (status = 202, description = "The password is correct and a second factor is required", body = MfaChallengeResponse),
         (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 401, description = "Invalid credentials", body = ErrorResponse),
+        (status = 429, description = "Too many failed login attempts for the username or client", body = ErrorResponse),
     ),
 )]
 #[instrument(level = "info", name = "login", skip_all)]
 pub async fn login(
     State(state): State<Arc<ApplicationState>>,
+    ClientIp(ip): ClientIp,
     CustomJson(payload): CustomJson<LoginRequest>,
-) -> Result<Json<LoginResponse>, AppError> {
+) -> Result<Response, AppError> {
     // Start a tracing span
     let span = Span::current();
     span.set_attribute(Key::from("http.method"), Value::from("POST"));
 
+    // Adds the username to the trace
+    let name = &payload.username;
+    span.set_attribute(Key::from("user"), Value::from(name.to_string()));
+
+    // Rejects the attempt outright while the username or client is backing off
+    let throttle = &state.settings.load().login_throttle;
+    if let Some(wait) = state.failed_logins.retry_after(throttle, &payload.username, ip) {
+        let response: AppError = AppError(
+            StatusCode::TOO_MANY_REQUESTS,
+            anyhow!(
+                "Too many failed login attempts; try again in {} seconds",
+                wait.as_secs().max(1)
+            ),
+        );
+        span.set_attribute(
+            Key::from("response.payload"),
+            Value::from(format!("{:?}", &response)),
+        );
+        span.set_attribute(Key::from("http.status_code"), Value::from(429));
+        return Err(response);
+    }
+
     // Validate that the password is correct
-    match user::Entity::find()
+    let user = match user::Entity::find()
         .filter(user::Column::Username.eq(&payload.username))
         // NOTE: EntityTrait::find().all() returns a list
         //.all(state.db_conn.load().as_ref())
@@ -53,18 +87,27 @@ pub async fn login(
         .await
     {
         Ok(admins) => {
-            // Adds the username to the trace
-            let name = &payload.username;
-            span.set_attribute(Key::from("user"), Value::from(name.to_string()));
-
-            // The user wasn't found
-            if admins.is_none() {
-                //span.record("payload", &tracing::field::debug(&payload));
+            // Verifies against a dummy hash if the user wasn't found, so
+            // unknown usernames take as long to reject as wrong passwords
+            let hash = admins
+                .as_ref()
+                .map(|admin| admin.password.as_str())
+                .unwrap_or_else(|| dummy_hash());
+            let verified = validate_password(&payload.password, hash).is_ok();
+
+            // The user wasn't found or the password doesn't match; both
+            // get the same response so it doesn't reveal which usernames exist
+            let Some(admin) = admins.filter(|_| verified) else {
+                state
+                    .failed_logins
+                    .record_failure(throttle, &payload.username, ip);
                 let response: AppError =
-                    AppError(StatusCode::UNAUTHORIZED, anyhow!("User doesn't exist"));
+                    AppError(StatusCode::UNAUTHORIZED, anyhow!("Invalid credentials"));
                 span.set_attribute(
                     Key::from("request.payload"),
-                    Value::from(format!("{:?}", &payload)),
+                    Value::from(
+                        Redacted::new(&payload, &state.settings.load().redaction).to_string(),
+                    ),
                 );
                 span.set_attribute(
                     Key::from("response.payload"),
@@ -72,16 +115,12 @@ pub async fn login(
                 );
                 span.set_attribute(Key::from("http.status_code"), Value::from(401));
                 return Err(response);
-            }
+            };
 
-            // The password doesn't match
-            if validate_password(&payload.password, &admins.unwrap().password).is_err() {
+            // The user has been disabled by an admin
+            if !admin.active_flag {
                 let response: AppError =
-                    AppError(StatusCode::UNAUTHORIZED, anyhow!("Invalid password"));
-                span.set_attribute(
-                    Key::from("request.payload"),
-                    Value::from(format!("{:?}", &payload)),
-                );
+                    AppError(StatusCode::UNAUTHORIZED, anyhow!("User is disabled"));
                 span.set_attribute(
                     Key::from("response.payload"),
                     Value::from(format!("{:?}", &response)),
@@ -89,6 +128,8 @@ pub async fn login(
                 span.set_attribute(Key::from("http.status_code"), Value::from(401));
                 return Err(response);
             }
+
+            admin
         }
         // Something went wrong on the client side
         Err(_) => {
@@ -98,47 +139,37 @@ pub async fn login(
                 anyhow!("We fucked up"),
             ));
         }
-    }
-
-    // If validation doesn't error, issue the token
-    let secret = &state.settings.load().token_secret;
-    let timeout = state.settings.load().token_timeout_seconds;
-
-    let now = chrono::Utc::now();
-    let iat = now.timestamp() as usize;
-    //let exp =
-    //  (now + chrono::Duration::minutes(60)).timestamp() as usize;
-    let exp = (now + chrono::Duration::seconds(timeout)).timestamp() as usize;
-    let claims = TokenClaims {
-        sub: payload.username,
-        exp,
-        iat,
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
-    .unwrap_or("".to_string());
+    let settings = state.settings.load();
+
+    // The failure count is only reset once the second factor is verified too,
+    // so a known password doesn't allow unlimited code guesses
+    if user.totp_enabled_at.is_some() {
+        let response = MfaChallengeResponse {
+            mfa_token: mfa::issue_challenge(&settings, &state.signing_keys, &user)?,
+            expires_in: settings.mfa.challenge_timeout_seconds,
+        };
+        span.set_attribute(Key::from("http.status_code"), Value::from(202));
+        return Ok((StatusCode::ACCEPTED, Json(response)).into_response());
+    }
+    state.failed_logins.record_success(&payload.username);
 
-    let response = LoginResponse { token };
+    // If validation doesn't error, issue the tokens
+    let token = tokens::issue_access_token(&settings, &state.signing_keys, &user)?;
+    let refresh_token =
+        tokens::issue_refresh_token(state.db_conn.load().as_ref(), &settings, user.id, None)
+            .await?;
+
+    let response = LoginResponse {
+        token,
+        refresh_token,
+    };
 
     span.set_attribute(Key::from("http.status_code"), Value::from(200));
 
     //tracing::info!("Login span");
 
     //Ok(crate::api::middleware::json::to_response(Json(response)))
-    Ok(Json(response))
-}
-
-fn validate_password(password: &str, hash: &str) -> anyhow::Result<()> {
-    let argon2 = Argon2::default();
-    let parsed_hash = PasswordHash::new(hash).map_err(|e| anyhow!(e.to_string()))?;
-
-    argon2
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .map_err(|_e| anyhow!("Failed to verify password"))?;
-
-    Ok(())
+    Ok(Json(response).into_response())
 }
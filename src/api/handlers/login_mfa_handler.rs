@@ -0,0 +1,114 @@
+use crate::api::auth::mfa;
+use crate::api::auth::revocation;
+use crate::api::auth::tokens;
+use crate::api::middleware::client_ip::ClientIp;
+use crate::api::middleware::json::CustomJson;
+use crate::api::request::mfa_login_request::MfaLoginRequest;
+use crate::api::response::error::AppError;
+use crate::api::response::login_response::LoginResponse;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use opentelemetry::{Key, Value};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Complete a multi-factor login
+///
+/// Exchange the challenge token returned by `/login` and a code from your authenticator app, or
+/// an unused recovery code, for a JWT. Each code is accepted only once. Failed codes count
+/// towards the same lockout as failed passwords.
+#[utoipa::path(
+    post,
+    path = "/login/mfa",
+    tag = "Auth",
+    request_body = MfaLoginRequest,
+    responses(
+        (status = 200, description = "Success", body = LoginResponse),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 401, description = "Invalid or expired challenge token, or incorrect code", body = ErrorResponse),
+        (status = 429, description = "Too many failed login attempts for the username or client", body = ErrorResponse),
+    ),
+)]
+#[instrument(level = "info", name = "login_mfa", skip_all)]
+pub async fn login_mfa(
+    State(state): State<Arc<ApplicationState>>,
+    ClientIp(ip): ClientIp,
+    CustomJson(payload): CustomJson<MfaLoginRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+
+    let Some(challenge) = mfa::verify_challenge(&state.signing_keys, &payload.mfa_token) else {
+        span.set_attribute(Key::from("http.status_code"), Value::from(401));
+        return Err(AppError(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("Invalid or expired MFA token"),
+        ));
+    };
+    span.set_attribute(Key::from("user"), Value::from(challenge.sub.clone()));
+
+    // Rejects the attempt outright while the username or client is backing off
+    let throttle = &state.settings.load().login_throttle;
+    if let Some(wait) = state
+        .failed_logins
+        .retry_after(throttle, &challenge.sub, ip)
+    {
+        span.set_attribute(Key::from("http.status_code"), Value::from(429));
+        return Err(AppError(
+            StatusCode::TOO_MANY_REQUESTS,
+            anyhow!(
+                "Too many failed login attempts; try again in {} seconds",
+                wait.as_secs().max(1)
+            ),
+        ));
+    }
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    // The user may have been disabled, had MFA turned off, or had their
+    // tokens revoked since the password step
+    let Some(user) = user::Entity::find()
+        .filter(user::Column::Username.eq(&challenge.sub))
+        .one(db)
+        .await?
+        .filter(|user| user.active_flag && user.totp_enabled_at.is_some())
+        .filter(|user| {
+            user.tokens_revoked_at
+                .is_none_or(|revoked_at| revocation::epoch_seconds(revoked_at) < challenge.iat)
+        })
+    else {
+        span.set_attribute(Key::from("http.status_code"), Value::from(401));
+        return Err(AppError(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("Invalid or expired MFA token"),
+        ));
+    };
+
+    if !mfa::verify_second_factor(db, &user, &payload.code).await? {
+        state
+            .failed_logins
+            .record_failure(throttle, &challenge.sub, ip);
+        span.set_attribute(Key::from("http.status_code"), Value::from(401));
+        return Err(AppError(StatusCode::UNAUTHORIZED, anyhow!("Invalid code")));
+    }
+    state.failed_logins.record_success(&challenge.sub);
+
+    let settings = state.settings.load();
+    let token = tokens::issue_access_token(&settings, &state.signing_keys, &user)?;
+    let refresh_token = tokens::issue_refresh_token(db, &settings, user.id, None).await?;
+
+    span.set_attribute(Key::from("http.status_code"), Value::from(200));
+    Ok(Json(LoginResponse {
+        token,
+        refresh_token,
+    }))
+}
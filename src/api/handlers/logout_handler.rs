@@ -0,0 +1,71 @@
+use crate::api::auth::tokens;
+use crate::api::middleware::json::OptionalJson;
+use crate::api::request::logout_request::LogoutRequest;
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use axum::{extract::State, http::StatusCode, Extension};
+use std::sync::Arc;
+
+use opentelemetry::{Key, Value};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Revoke a JWT
+///
+/// Revoke the bearer token used to make this request. Optionally supply your refresh token to
+/// revoke it, and every token issued from the same login, as well.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "Auth",
+    request_body(content = Option<LogoutRequest>, description = "Optional refresh token to revoke"),
+    responses(
+        (status = 204, description = "Success"),
+        (status = 400, description = "The body isn't valid JSON", body = ErrorResponse),
+        (status = 422, description = "The body has no refresh token", body = ErrorResponse),
+        (status = 401, description = "Missing, invalid, or already revoked bearer token", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = [])
+    )
+)]
+#[instrument(level = "info", name = "logout", skip_all)]
+pub async fn logout(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    OptionalJson(payload): OptionalJson<LogoutRequest>,
+) -> Result<StatusCode, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    state.revocations.revoke_token(db, &claims).await?;
+
+    // Only revokes the refresh token if it belongs to the caller
+    if let Some(payload) = payload {
+        let caller = user::Entity::find()
+            .filter(user::Column::Username.eq(&claims.sub))
+            .one(db)
+            .await?;
+        let refresh_token = tokens::find_refresh_token(db, &payload.refresh_token).await?;
+        if let (Some(caller), Some(refresh_token)) = (caller, refresh_token) {
+            if refresh_token.user_id == caller.id {
+                tokens::revoke_family(db, refresh_token.family_id).await?;
+            }
+        }
+    }
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::NO_CONTENT.as_u16() as i64),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,6 +1,26 @@
+pub mod change_password_handler;
+pub mod create_api_key_handler;
 pub mod create_patient_handler;
+pub mod create_user_handler;
+pub mod delete_patient_handler;
+pub mod delete_user_handler;
+pub mod disable_user_handler;
 pub mod get_patient_handler;
+pub mod get_user_handler;
+pub mod jwks_handler;
+pub mod list_audit_log_handler;
+pub mod list_api_keys_handler;
+pub mod list_duplicates_handler;
 pub mod list_patients_handler;
+pub mod list_users_handler;
 pub mod login_handler;
-pub mod delete_patient_handler;
+pub mod login_mfa_handler;
+pub mod logout_handler;
+pub mod patient_history_handler;
+pub mod purge_patient_handler;
+pub mod refresh_token_handler;
+pub mod reset_password_handler;
+pub mod revoke_api_key_handler;
+pub mod revoke_tokens_handler;
+pub mod totp_handler;
 pub mod update_patient_handler;
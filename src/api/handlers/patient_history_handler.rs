@@ -0,0 +1,166 @@
+use crate::api::conditional;
+use crate::api::purge;
+use crate::api::response::error::AppError;
+use crate::api::response::patient_history::{PatientHistoryEntry, PatientHistoryResponse};
+use crate::api::response::TokenClaims;
+use crate::entities::{patient, patient_history};
+use crate::state::ApplicationState;
+
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use opentelemetry::{Key, Value};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+/// Get a patient record's history
+///
+/// List every recorded change to a patient, oldest first. Each entry holds the whole record as it
+/// was after the change, along with who made it and when. Changes made before history recording
+/// began are summed up in a single `baseline` entry.
+///
+/// A deleted patient's history stays hidden, like the patient itself, until an admin restores it.
+///
+/// Requires one of the `admin`, `clinician`, `front_desk`, or `read_only` roles, or an API key with
+/// the `patients:read` scope.
+#[utoipa::path(
+    get,
+    path = "/patient/{patient_id}/history",
+    params(
+        ("patient_id" = String, Path, description = "Patient ID as UUID v4", example = "3973ebb8-11e5-4725-93b7-3b752caad60f"),
+    ),
+    tag = "Patient Records",
+    responses(
+        (status = 200, description = "Success", body = PatientHistoryResponse),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The patient doesn't exist", body = ErrorResponse),
+        (status = 410, description = "The patient was deleted, or purged along with its history", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin", "clinician", "front_desk", "read_only"]),
+        ("api_key" = ["patients:read"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "patient_history", skip_all)]
+pub async fn history(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(patient_id): Path<Uuid>,
+) -> Result<Json<PatientHistoryResponse>, AppError> {
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("GET"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.to_string()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let Some(model) = patient::Entity::find()
+        .filter(patient::Column::PatientId.eq(patient_id))
+        .one(db)
+        .await?
+    else {
+        if purge::was_purged(db, patient_id).await? {
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(StatusCode::GONE.as_u16() as i64),
+            );
+            return Err(conditional::purged(patient_id));
+        }
+        let code = StatusCode::NOT_FOUND;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!("Patient {patient_id} not found")));
+    };
+    // A deleted patient stays hidden until an admin restores it
+    if !model.active_flag {
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(StatusCode::GONE.as_u16() as i64),
+        );
+        return Err(conditional::gone(patient_id));
+    }
+
+    let entries = patient_history::Entity::find()
+        .filter(patient_history::Column::PatientId.eq(patient_id))
+        .order_by_asc(patient_history::Column::Version)
+        .all(db)
+        .await?;
+
+    let history = entries
+        .into_iter()
+        .map(|entry| state.field_encryption.open_history(entry))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(PatientHistoryResponse {
+        history: history.into_iter().map(PatientHistoryEntry::from).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    fn claims() -> TokenClaims {
+        TokenClaims {
+            jti: Uuid::new_v4(),
+            sub: "clinician".to_string(),
+            role: crate::entities::user::Role::Clinician,
+            iat: 0.0,
+            exp: usize::MAX,
+        }
+    }
+
+    #[tokio::test]
+    async fn hides_the_history_of_a_deleted_patient() {
+        let patient_id = Uuid::new_v4();
+        let deleted = patient::Model {
+            id: 1,
+            active_flag: false,
+            patient_id,
+            created_at: Utc::now(),
+            version: 2,
+            key_version: None,
+            deleted_at: Some(Utc::now()),
+            deleted_by: Some("admin".to_string()),
+            name_id: 1,
+            address_id: 1,
+            birthdate_id: 1,
+        };
+        let state = ApplicationState::mock(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([[deleted]])
+                .into_connection(),
+        );
+
+        let Err(err) = history(Extension(claims()), State(state.clone()), Path(patient_id)).await
+        else {
+            panic!("expected the deleted patient to be gone");
+        };
+        assert_eq!(err.0, StatusCode::GONE);
+
+        // The history itself is never read
+        let log = state.into_transaction_log();
+        assert_eq!(log.len(), 1);
+        assert!(log[0].statements()[0]
+            .sql
+            .starts_with("SELECT \"patient\"."));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::api::purge::{self, Purge};
+use crate::api::response::error::AppError;
+use crate::api::response::purge_response::PurgeResponse;
+use crate::api::response::TokenClaims;
+use crate::state::ApplicationState;
+
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use opentelemetry::{Key, Value};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+#[derive(Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+pub struct PurgeParams {
+    /// Report what would be removed without removing it
+    #[param(example = false)]
+    pub dry_run: Option<bool>,
+}
+
+/// Permanently purge a deleted patient record
+///
+/// Remove a deleted patient record for good, along with its history and anything else that holds
+/// its name, address or birth date. Only a tombstone with the patient ID and the dates it was
+/// created, deleted and purged remains, so later requests for it get a 410. Audit records are
+/// kept, since they hold only the patient ID.
+///
+/// The patient must be deleted first, or the purge gets a 409. Set `dry_run` to see what would be
+/// removed without removing it.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    post,
+    path = "/patient/{patient_id}/purge",
+    params(
+        ("patient_id" = String, Path, description = "Patient ID as UUID v4", example = "3973ebb8-11e5-4725-93b7-3b752caad60f"),
+        PurgeParams,
+    ),
+    tag = "Patient Records",
+    responses(
+        (status = 200, description = "Success", body = PurgeResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "No patient has this ID", body = ErrorResponse),
+        (status = 409, description = "The patient isn't deleted", body = ErrorResponse),
+        (status = 410, description = "The patient was already purged", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "purge_patient", skip_all)]
+pub async fn purge(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(patient_id): Path<Uuid>,
+    Query(params): Query<PurgeParams>,
+) -> Result<Json<PurgeResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let dry_run = params.dry_run.unwrap_or(false);
+    let outcome = purge::purge(
+        state.db_conn.load().as_ref(),
+        patient_id,
+        &claims.sub,
+        dry_run,
+    )
+    .await?;
+
+    let (code, message) = match outcome {
+        Purge::Purged(erasure) => {
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(StatusCode::OK.as_u16() as i64),
+            );
+            return Ok(Json(PurgeResponse::new(erasure, dry_run)));
+        }
+        Purge::NotFound => (StatusCode::NOT_FOUND, "not found"),
+        Purge::AlreadyPurged => (StatusCode::GONE, "was already purged"),
+        Purge::Active => (
+            StatusCode::CONFLICT,
+            "isn't deleted; delete it before purging it",
+        ),
+    };
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(code.as_u16() as i64),
+    );
+    Err(AppError(code, anyhow!("Patient {patient_id} {message}")))
+}
@@ -0,0 +1,82 @@
+use crate::api::auth::tokens::{self, RefreshError};
+use crate::api::middleware::json::CustomJson;
+use crate::api::request::refresh_token_request::RefreshTokenRequest;
+use crate::api::response::error::AppError;
+use crate::api::response::login_response::LoginResponse;
+use crate::state::ApplicationState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use opentelemetry::{Key, Value};
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Refresh a JWT
+///
+/// Exchange a refresh token for a new access token and refresh token. Each refresh token is
+/// single use; presenting one that was already exchanged revokes every token issued from the
+/// same login, and you must log in again.
+#[utoipa::path(
+    post,
+    path = "/token/refresh",
+    tag = "Auth",
+    request_body = RefreshTokenRequest,
+    responses(
+        (status = 200, description = "Success", body = LoginResponse),
+        (status = 401, description = "The refresh token is invalid, expired, revoked, or was already used", body = ErrorResponse),
+    ),
+)]
+#[instrument(level = "info", name = "refresh_token", skip_all)]
+pub async fn refresh(
+    State(state): State<Arc<ApplicationState>>,
+    CustomJson(payload): CustomJson<RefreshTokenRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+
+    let settings = state.settings.load();
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let (user, refresh_token) =
+        match tokens::rotate_refresh_token(db, &settings, &payload.refresh_token).await {
+            Ok(rotated) => rotated,
+            Err(RefreshError::Invalid) => {
+                span.set_attribute(Key::from("http.status_code"), Value::from(401));
+                return Err(AppError(
+                    StatusCode::UNAUTHORIZED,
+                    anyhow!("Invalid refresh token"),
+                ));
+            }
+            Err(RefreshError::Reused) => {
+                span.set_attribute(Key::from("http.status_code"), Value::from(401));
+                tracing::warn!("Refresh token reuse detected; token family revoked");
+                return Err(AppError(
+                    StatusCode::UNAUTHORIZED,
+                    anyhow!("Refresh token was already used; log in again"),
+                ));
+            }
+            Err(RefreshError::Db(err)) => {
+                span.set_attribute(Key::from("http.status_code"), Value::from(500));
+                tracing::error!("Failed to rotate refresh token: {err}");
+                return Err(AppError(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    anyhow!("Uh oh..."),
+                ));
+            }
+        };
+
+    span.set_attribute(Key::from("user"), Value::from(user.username.clone()));
+    let token = tokens::issue_access_token(&settings, &state.signing_keys, &user)?;
+
+    span.set_attribute(Key::from("http.status_code"), Value::from(200));
+    Ok(Json(LoginResponse {
+        token,
+        refresh_token,
+    }))
+}
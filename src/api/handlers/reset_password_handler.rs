@@ -0,0 +1,81 @@
+use crate::api::auth::password::change_password;
+use crate::api::handlers::get_user_handler::find_user;
+use crate::api::middleware::json::CustomJson;
+use crate::api::request::reset_password_request::ResetPasswordRequest;
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::state::ApplicationState;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use opentelemetry::{Key, Value};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Reset a user's password
+///
+/// Replace a user's password and revoke every token issued to them. The new password must meet
+/// the password policy and must not match a recently used password.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    put,
+    path = "/users/{username}/password",
+    tag = "Users",
+    params(
+        ("username" = String, Path, description = "The username of the user", example = "jdoe")
+    ),
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 204, description = "Success"),
+        (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The user doesn't exist", body = ErrorResponse),
+        (status = 422, description = "The password breaks the password policy or was used recently", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "reset_password", skip_all)]
+pub async fn reset_password(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(username): Path<String>,
+    CustomJson(payload): CustomJson<ResetPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("PUT"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let model = find_user(db, &span, &username).await?;
+    let policy = &state.settings.load().password_policy;
+    let model = change_password(db, policy, model, &payload.password)
+        .await
+        .map_err(|err| {
+            let err = err.into_app_error();
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(err.0.as_u16() as i64),
+            );
+            err
+        })?;
+
+    state.revocations.revoke_user(db, model).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::NO_CONTENT.as_u16() as i64),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,65 @@
+use crate::api::auth::api_keys::revoke_api_key;
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use opentelemetry::{Key, Value};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Revoke an API key
+///
+/// Stop accepting an API key. The key stays listed for reference.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    delete,
+    path = "/api-keys/{api_key_id}",
+    tag = "API keys",
+    params(
+        ("api_key_id" = i32, Path, description = "The ID of the API key", example = 3)
+    ),
+    responses(
+        (status = 204, description = "Success"),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The API key doesn't exist", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "revoke_api_key", skip_all)]
+pub async fn revoke(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(api_key_id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("DELETE"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    if !revoke_api_key(state.db_conn.load().as_ref(), api_key_id).await? {
+        let code = StatusCode::NOT_FOUND;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!("API key {api_key_id} not found")));
+    }
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::NO_CONTENT.as_u16() as i64),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
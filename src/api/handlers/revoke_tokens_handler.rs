@@ -0,0 +1,61 @@
+use crate::api::handlers::get_user_handler::find_user;
+use crate::api::response::error::AppError;
+use crate::api::response::TokenClaims;
+use crate::state::ApplicationState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use std::sync::Arc;
+
+use opentelemetry::{Key, Value};
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Revoke all tokens for a user
+///
+/// Revoke every access token and refresh token issued to a user so far. The user can log in
+/// again to receive new tokens.
+///
+/// Requires the `admin` role.
+#[utoipa::path(
+    post,
+    path = "/users/{username}/revoke-tokens",
+    tag = "Auth",
+    params(
+        ("username" = String, Path, description = "The username whose tokens to revoke", example = "admin")
+    ),
+    responses(
+        (status = 204, description = "Success"),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "The user doesn't exist", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = ["admin"])
+    )
+)]
+#[instrument(level = "info", name = "revoke_tokens", skip_all)]
+pub async fn revoke_tokens(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    Path(username): Path<String>,
+) -> Result<StatusCode, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let target = find_user(db, &span, &username).await?;
+    state.revocations.revoke_user(db, target).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::NO_CONTENT.as_u16() as i64),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
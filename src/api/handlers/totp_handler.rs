@@ -0,0 +1,214 @@
+use crate::api::auth::mfa;
+use crate::api::middleware::json::CustomJson;
+use crate::api::request::totp_code_request::TotpCodeRequest;
+use crate::api::response::error::AppError;
+use crate::api::response::mfa_response::{RecoveryCodesResponse, TotpEnrolmentResponse};
+use crate::api::response::TokenClaims;
+use crate::entities::user;
+use crate::state::ApplicationState;
+use anyhow::anyhow;
+use axum::{debug_handler, extract::State, http::StatusCode, Extension, Json};
+use opentelemetry::{Key, Value};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+use std::sync::Arc;
+use tracing::instrument;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Start TOTP enrolment
+///
+/// Generate a new authenticator secret for your account. Add it to an authenticator app, for
+/// example by showing the URI as a QR code, then confirm it with a code at
+/// `/me/mfa/totp/confirm`. Logins don't require a second factor until you confirm.
+#[utoipa::path(
+    post,
+    path = "/me/mfa/totp",
+    tag = "Auth",
+    responses(
+        (status = 200, description = "Success", body = TotpEnrolmentResponse),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorResponse),
+        (status = 409, description = "MFA is already enabled", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = [])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "enrol_totp", skip_all)]
+pub async fn enrol(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+) -> Result<Json<TotpEnrolmentResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let model = current_user(db, &span, &claims).await?;
+    if model.totp_enabled_at.is_some() {
+        let code = StatusCode::CONFLICT;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("MFA is already enabled; disable it before enrolling again"),
+        ));
+    }
+
+    let username = model.username.clone();
+    let secret = mfa::start_enrolment(db, model).await?;
+    let otpauth_uri = mfa::otpauth_uri(&state.settings.load().mfa.issuer, &username, &secret);
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(TotpEnrolmentResponse {
+        secret,
+        otpauth_uri,
+    }))
+}
+
+/// Confirm TOTP enrolment
+///
+/// Enable multi-factor authentication by supplying the current code for your pending secret.
+/// The response contains recovery codes, which are only ever shown once.
+#[utoipa::path(
+    post,
+    path = "/me/mfa/totp/confirm",
+    tag = "Auth",
+    request_body = TotpCodeRequest,
+    responses(
+        (status = 200, description = "Success", body = RecoveryCodesResponse),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorResponse),
+        (status = 409, description = "MFA is already enabled", body = ErrorResponse),
+        (status = 422, description = "No enrolment is pending or the code is incorrect", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = [])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "confirm_totp", skip_all)]
+pub async fn confirm(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    CustomJson(payload): CustomJson<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("POST"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let model = current_user(db, &span, &claims).await?;
+    if model.totp_enabled_at.is_some() {
+        let code = StatusCode::CONFLICT;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!("MFA is already enabled")));
+    }
+
+    let settings = state.settings.load();
+    let Some(recovery_codes) = mfa::confirm_enrolment(db, &settings, model, &payload.code).await?
+    else {
+        let code = StatusCode::UNPROCESSABLE_ENTITY;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(
+            code,
+            anyhow!("No enrolment is pending or the code is incorrect"),
+        ));
+    };
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::OK.as_u16() as i64),
+    );
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Disable TOTP
+///
+/// Turn off multi-factor authentication for your account, or cancel a pending enrolment. Unless
+/// enrolment is still pending, supply a current code or an unused recovery code.
+#[utoipa::path(
+    delete,
+    path = "/me/mfa/totp",
+    tag = "Auth",
+    request_body = TotpCodeRequest,
+    responses(
+        (status = 204, description = "Success"),
+        (status = 401, description = "Missing or invalid bearer token", body = ErrorResponse),
+        (status = 403, description = "The code is incorrect", body = ErrorResponse),
+    ),
+    security(
+        ("api_jwt_token" = [])
+    )
+)]
+#[debug_handler]
+#[instrument(level = "info", name = "disable_totp", skip_all)]
+pub async fn disable(
+    Extension(claims): Extension<TokenClaims>,
+    State(state): State<Arc<ApplicationState>>,
+    CustomJson(payload): CustomJson<TotpCodeRequest>,
+) -> Result<StatusCode, AppError> {
+    // Start a tracing span
+    let span = Span::current();
+    span.set_attribute(Key::from("http.method"), Value::from("DELETE"));
+    span.set_attribute(Key::from("user"), Value::from(claims.sub.clone()));
+
+    let db_conn = state.db_conn.load();
+    let db = db_conn.as_ref();
+
+    let model = current_user(db, &span, &claims).await?;
+    if model.totp_enabled_at.is_some()
+        && !mfa::verify_second_factor(db, &model, &payload.code).await?
+    {
+        let code = StatusCode::FORBIDDEN;
+        span.set_attribute(
+            Key::from("http.status_code"),
+            Value::from(code.as_u16() as i64),
+        );
+        return Err(AppError(code, anyhow!("The code is incorrect")));
+    }
+
+    mfa::disable(db, model).await?;
+
+    span.set_attribute(
+        Key::from("http.status_code"),
+        Value::from(StatusCode::NO_CONTENT.as_u16() as i64),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Looks up the user the bearer token was issued to
+async fn current_user<C: ConnectionTrait>(
+    db: &C,
+    span: &Span,
+    claims: &TokenClaims,
+) -> Result<user::Model, AppError> {
+    user::Entity::find()
+        .filter(user::Column::Username.eq(&claims.sub))
+        .one(db)
+        .await?
+        .ok_or_else(|| {
+            let code = StatusCode::UNAUTHORIZED;
+            span.set_attribute(
+                Key::from("http.status_code"),
+                Value::from(code.as_u16() as i64),
+            );
+            AppError(code, anyhow!("User doesn't exist"))
+        })
+}
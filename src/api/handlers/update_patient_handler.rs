@@ -1,34 +1,36 @@
+use crate::api::conditional;
 use crate::api::request::update_patient_request::UpdatePatientRequest;
 use crate::api::response::{
     create_patient_response::{
-        AddressData, 
-        BirthdateData, 
         CreatePatientResponse, 
-        NameData, 
         Patient
     },
     error::AppError
 };
 use crate::api::response::TokenClaims;
-use crate::entities::patient;
+use crate::entities::patient::{self, StoredRecord};
+use crate::entities::patient_history::{self, Action};
 use crate::state::ApplicationState;
-use crate::api::middleware::json::CustomJson;
+use crate::api::middleware::json::ValidJson;
 
 use anyhow::anyhow;
 use axum::{
     debug_handler, 
     extract::{Path, State}, 
-    http::StatusCode, 
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Extension, 
     Json
 };
 use opentelemetry::{Key, Value};
+use sea_orm::sea_query::Expr;
 use sea_orm::{
     ActiveModelTrait, 
     ActiveValue::Set,
     ColumnTrait, 
     EntityTrait, 
-    QueryFilter
+    QueryFilter,
+    TransactionTrait,
 };
 use std::sync::Arc;
 use tracing::instrument;
@@ -47,6 +49,16 @@ use uuid::Uuid;
 /// Update a patient record
 ///
 /// Update all fields for a given patient record aside from `name.first`, `name.surname`, and `birtdate`
+///
+/// Send the `ETag` from an earlier read in `If-Match` to make sure nobody else changed the record
+/// in the meantime. A stale ETag gets a 412, and the response carries the new `ETag`. The header
+/// is optional unless the server is configured to require it, in which case leaving it out gets
+/// a 428.
+///
+/// A deleted patient can't be updated until an admin restores it, and gets a 410.
+///
+/// Requires one of the `admin`, `clinician`, or `front_desk` roles, or an API key with the
+/// `patients:write` scope.
 #[utoipa::path(
     patch,
     path = "/patient/{patient_id}",
@@ -55,15 +67,24 @@ use uuid::Uuid;
         // utoipa doesn't support uuid directly, so the path param
         // has to be a String instead
         ("patient_id" = String, Path, description = "Patient ID as UUID v4", example = "3973ebb8-11e5-4725-93b7-3b752caad60f
-")
+"),
+        ("If-Match" = Option<String>, Header, description = "The ETag of the version being updated"),
     ),
     request_body = UpdatePatientRequestOas,
     responses(
-        (status = 200, description = "Success", body = CreatePatientResponse),
+        (status = 200, description = "Success", body = CreatePatientResponse,
+            headers(("ETag" = String, description = "The version of the updated patient record"))),
         (status = 400, description = "Generic error response format", body = ErrorResponse),
+        (status = 403, description = "The token role is not permitted to perform this operation", body = ErrorResponse),
+        (status = 404, description = "No patient has this ID", body = ErrorResponse),
+        (status = 410, description = "The patient was deleted", body = ErrorResponse),
+        (status = 412, description = "The patient has changed since the ETag in If-Match was issued", body = ErrorResponse),
+        (status = 422, description = "One or more fields are invalid or immutable, or the body doesn't match the schema", body = UnprocessableResponse),
+        (status = 428, description = "If-Match is required but was not sent", body = ErrorResponse),
     ),
     security(
-        ("api_jwt_token" = [])
+        ("api_jwt_token" = ["admin", "clinician", "front_desk"]),
+        ("api_key" = ["patients:write"])
     )
 )]
 #[debug_handler]
@@ -72,8 +93,9 @@ pub async fn update(
     Extension(claims): Extension<TokenClaims>,
     State(state): State<Arc<ApplicationState>>,
     Path(patient_id): Path<Uuid>,
-    CustomJson(payload): CustomJson<UpdatePatientRequest>
-) -> Result<Json<CreatePatientResponse>, AppError> {
+    headers: HeaderMap,
+    ValidJson(payload): ValidJson<UpdatePatientRequest>
+) -> Result<Response, AppError> {
     // Start a tracing span
     let span = Span::current();
     span.set_attribute(Key::from("http.method"), Value::from("PATCH"));
@@ -95,158 +117,123 @@ pub async fn update(
             // If the search returns a hit, fetch its data,
             // assemble the JSON, and return it
             if let Some(mut model) = conn {
+                if !model.active_flag {
+                    let code = StatusCode::GONE;
+                    span.set_attribute(
+                        Key::from("http.status_code"),
+                        Value::from(code.as_u16() as i64),
+                    );
+                    return Err(conditional::gone(patient_id));
+                }
+                let require_if_match = state.settings.load().require_if_match;
+                conditional::check_if_match(
+                    &span,
+                    &headers,
+                    model.version,
+                    require_if_match,
+                )?;
 
-                // Convert request payload to `ActiveModel`
-                let mut name_active_model = patient::name::ActiveModel {
-                    id: Set(model.name_id),
-                    ..Default::default()
-                };
-                //set_if_some!(name_active_model, first, payload.name.first);
-                let cloned_payload = payload.clone();
+                // Stores Models together, so a failure part-way changes nothing
+                let txn = db.begin().await?;
+                let encryption = &state.field_encryption;
+
+                // Bumps the version only if nobody changed the patient since it was read; the
+                // row lock also holds off concurrent updates until this one commits. Every field
+                // is rewritten below with the active key, which the patient now records.
+                let bumped = patient::Entity::update_many()
+                    .col_expr(
+                        patient::Column::Version,
+                        Expr::col(patient::Column::Version).add(1),
+                    )
+                    .col_expr(
+                        patient::Column::KeyVersion,
+                        Expr::value(encryption.key_version()),
+                    )
+                    .filter(patient::Column::Id.eq(model.id))
+                    .filter(patient::Column::Version.eq(model.version))
+                    .exec(&txn)
+                    .await?;
+                if bumped.rows_affected == 0 {
+                    return Err(conditional::stale(&span));
+                }
+                model.version += 1;
+
+                // Reads the current fields in the clear, then applies the changes
+                let stored = patient::Entity::find_records()
+                    .filter(patient::Column::Id.eq(model.id))
+                    .into_model::<StoredRecord>()
+                    .one(&txn)
+                    .await?
+                    .ok_or_else(|| anyhow!("Patient {patient_id} not found"))?;
+                let mut record = encryption.open(stored)?;
+
+                // Only the middle name is mutable; validation rejects the rest
                 if let Some(name) = payload.name {
-                    //if let Some(v) = name.first {
-                    //    name_active_model.first = Set(v);
-                    //}    
-                    if name.first.is_some() {
-                        return Err(reject_immutable_field(&span, &patient_id, &cloned_payload, "name.first is immutable"));
-                    }
-                        //let code = StatusCode::BAD_REQUEST;
-                        //span.set_attribute(
-                        //    Key::from("http.status_code"),
-                        //    Value::from(code.as_u16() as i64),
-                        //);
-                        //span.set_attribute(
-                        //    Key::from("request.payload"),
-                        //    Value::from(format!("{:?}", &patient_id)),
-                        //);
-                        //return Err(AppError(code, anyhow!("Error: name.first is immutable")));
-                    //}
                     if let Some(v) = name.middle {
-                        name_active_model.middle = Set(v);
-                    }    
-                    //if let Some(v) = name.surname {
-                    //    name_active_model.surname = Set(v);
-                    //}
-                    if name.surname.is_some() {
-                        return Err(reject_immutable_field(&span, &patient_id, &cloned_payload, "name.surname is immutable"));
+                        record.middle = v;
                     }
-                        //let code = StatusCode::BAD_REQUEST;
-                        //span.set_attribute(
-                        //    Key::from("http.status_code"),
-                        //    Value::from(code.as_u16() as i64),
-                        //);
-                        //span.set_attribute(
-                        //    Key::from("request.payload"),
-                        //    Value::from(format!("{:?}", &patient_id)),
-                        //);
-                        //return Err(AppError(code, anyhow!("Error: name.surname is immutable")));
-                    //}
-
                 }
-
-                let mut address_active_model = patient::address::ActiveModel {
-                    id: Set(model.address_id),
-                    ..Default::default()
-                };
                 if let Some(address) = payload.address {
                     if let Some(v) = address.address_lines {
-                        address_active_model.address_lines = Set(v);
+                        record.address_lines = v;
                     }
                     if let Some(v) = address.sublocality {
-                        address_active_model.sublocality = Set(v);
+                        record.sublocality = v;
                     }
                     if let Some(v) = address.locality {
-                        address_active_model.locality = Set(v);
+                        record.locality = v;
                     }
                     if let Some(v) = address.administrative_area {
-                        address_active_model.administrative_area = Set(v);
+                        record.administrative_area = v;
                     }
                     if let Some(v) = address.postal_code {
-                        address_active_model.postal_code = Set(v);
+                        record.postal_code = v;
                     }
                     if let Some(v) = address.country_region {
-                        address_active_model.country_region = Set(v);
+                        record.country_region = v;
                     }
                 }
 
-                //let mut birthdate_active_model = patient::birthdate::ActiveModel {
-                let birthdate_active_model = patient::birthdate::ActiveModel {
+                let sealed = encryption.seal(&record)?;
+                patient::name::ActiveModel {
+                    id: Set(model.name_id),
+                    ..sealed.name
+                }
+                .update(&txn)
+                .await?;
+                patient::address::ActiveModel {
+                    id: Set(model.address_id),
+                    ..sealed.address
+                }
+                .update(&txn)
+                .await?;
+                patient::birthdate::ActiveModel {
                     id: Set(model.birthdate_id),
-                    ..Default::default()
-                };
-                if payload.birthdate.is_some() {
-                    return Err(reject_immutable_field(&span, &patient_id, &cloned_payload, "birthdate is immutable"));
-                    //let code = StatusCode::BAD_REQUEST;
-                    //span.set_attribute(
-                    //    Key::from("http.status_code"),
-                    //    Value::from(code.as_u16() as i64),
-                    //);
-                    //span.set_attribute(
-                    //    Key::from("request.payload"),
-                    //    Value::from(format!("{:?}", &patient_id)),
-                    //);
-                    //return Err(AppError(code, anyhow!("Error: birthdate is immutable")));
+                    ..sealed.birthdate
                 }
-                //if let Some(birth_date) = payload.birth_date {
-                //    if let Some(v) = birth_date.year {
-                //        birthdate_active_model.year = Set(v);
-                //    }
-                //    if let Some(v) = birth_date.month {
-                //        birthdate_active_model.month = Set(v);
-                //    }
-                //    if let Some(v) = birth_date.day {
-                //        birthdate_active_model.day = Set(v);
-                //    }
-                //}
-                
-                // Stores Models
-                let name_model: patient::name::Model = name_active_model.update(db).await?;
-                let address_model: patient::address::Model = address_active_model.update(db).await?;
-                let birthdate_model: patient::birthdate::Model = birthdate_active_model.update(db).await?;
-
-                // Create and store the full patient record
-                let patient_active_model = patient::ActiveModel {
-                    id: Set(model.id),
-                    name_id: Set(name_model.id),
-                    address_id: Set(address_model.id),
-                    //birthdate_id: Set(birthdate_model.id),
-                    ..Default::default()
-                };
+                .update(&txn)
+                .await?;
+                patient_history::record(&txn, model.id, Action::Updated, name).await?;
+                txn.commit().await?;
 
-                model = patient_active_model.update(db).await?;
-
-                // Constructs response from generated models
+                // Constructs response from the updated record
                 let response_data = Patient {
                     created_at: model.created_at.to_string(),
-                    patient_id: model.patient_id.to_string(),
-                    name: NameData {
-                        first: name_model.first,
-                        middle: name_model.middle,
-                        surname: name_model.surname,
-                    },
-                    address: AddressData {
-                        address_lines: address_model.address_lines,
-                        sublocality: address_model.sublocality,
-                        locality: address_model.locality,
-                        administrative_area: address_model.administrative_area,
-                        postal_code: address_model.postal_code,
-                        country_region: address_model.country_region,
-                    },
-                    birthdate: BirthdateData {
-                        day: birthdate_model.day,
-                        month: birthdate_model.month,
-                        year: birthdate_model.year,
-                    },
+                    ..Patient::from(record)
                 };
 
                 span.set_attribute(
                     Key::from("http.status_code"),
                     Value::from(StatusCode::OK.as_u16() as i64),
                 );
-                Ok(Json(CreatePatientResponse {
-                    //status: 200,
-                    data: response_data,
-                }))
+                Ok((
+                    [(header::ETAG, conditional::etag(model.version))],
+                    Json(CreatePatientResponse {
+                        //status: 200,
+                        data: response_data,
+                    }),
+                )
+                    .into_response())
             // If the search is Ok, but there is no hit,
             // return a 404 NOT_FOUND error
             } else {
@@ -277,15 +264,3 @@ pub async fn update(
         }
     }
 }
-
-fn reject_immutable_field(
-    span: &Span,
-    patient_id: &Uuid,
-    payload: &UpdatePatientRequest,
-    message: &str,
-) -> AppError {
-    let code = StatusCode::BAD_REQUEST;
-    span.set_attribute(Key::from("http.status_code"), Value::from(code.as_u16() as i64));
-    span.set_attribute(Key::from("request.payload"), Value::from(format!("patient ID: {:?}\n{:?}", patient_id, payload)));
-    AppError(code, anyhow!("{message}"))
-}
@@ -0,0 +1,313 @@
+use crate::api::auth::tokens::hash_token;
+use crate::entities::idempotency_key;
+use crate::settings::Settings;
+
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ActiveValue::Set, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// The request header clients set to make a retried request safe
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Set on responses that were replayed rather than produced by this request
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// The longest key accepted, which leaves room for a UUID or any similar client identifier
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// What to do with a request that carries an idempotency key
+pub enum Claim {
+    /// The key is new, so handle the request and then call `complete` or `release`
+    New,
+    /// The key was used with the same body, so send back the original response, rebuilt from the
+    /// patient it created
+    Replay {
+        status: StatusCode,
+        patient_id: Uuid,
+        /// The patient's version once the original request created it
+        version: i32,
+    },
+    /// The key was used with a different body
+    Mismatch,
+    /// The first request with this key hasn't finished yet, and its claim hasn't timed out
+    InProgress,
+}
+
+/// Digests a request body so that a retry can be told apart from a different request
+///
+/// Hashing the deserialized payload ignores differences in whitespace and field order.
+pub fn hash_request<T: Serialize>(payload: &T) -> Result<String, serde_json::Error> {
+    Ok(hash_token(&serde_json::to_string(payload)?))
+}
+
+/// Reserves `key` for the caller, or reports how an earlier request with it turned out
+///
+/// Expired keys are removed first, so a key can be reused once its window has passed. A claim
+/// that is still unfinished after `claim_timeout_seconds`, because its process died, is taken
+/// over by the next request with the key.
+pub async fn claim<C: ConnectionTrait>(
+    db: &C,
+    settings: &Settings,
+    principal: &str,
+    key: &str,
+    request_hash: &str,
+) -> Result<Claim, DbErr> {
+    let now = Utc::now();
+    idempotency_key::Entity::delete_many()
+        .filter(idempotency_key::Column::ExpiresAt.lte(now))
+        .exec(db)
+        .await?;
+
+    // The unique index on (principal, key) lets only one concurrent request reserve a key
+    let inserted = idempotency_key::Entity::insert(idempotency_key::ActiveModel {
+        principal: Set(principal.to_string()),
+        key: Set(key.to_string()),
+        request_hash: Set(request_hash.to_string()),
+        response_status: Set(None),
+        patient_id: Set(None),
+        version: Set(None),
+        created_at: Set(now),
+        expires_at: Set(now + Duration::seconds(settings.idempotency.window_seconds)),
+        claimed_at: Set(now),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::columns([
+            idempotency_key::Column::Principal,
+            idempotency_key::Column::Key,
+        ])
+        .do_nothing()
+        .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await?;
+    if inserted == 1 {
+        return Ok(Claim::New);
+    }
+
+    // Row locking lets only one of several concurrent retries take over a stale claim
+    let stale_before = now - Duration::seconds(settings.idempotency.claim_timeout_seconds);
+    let taken_over = idempotency_key::Entity::update_many()
+        .col_expr(idempotency_key::Column::RequestHash, request_hash.into())
+        .col_expr(idempotency_key::Column::CreatedAt, now.into())
+        .col_expr(
+            idempotency_key::Column::ExpiresAt,
+            (now + Duration::seconds(settings.idempotency.window_seconds)).into(),
+        )
+        .col_expr(idempotency_key::Column::ClaimedAt, now.into())
+        .filter(idempotency_key::Column::Principal.eq(principal))
+        .filter(idempotency_key::Column::Key.eq(key))
+        .filter(idempotency_key::Column::ResponseStatus.is_null())
+        .filter(idempotency_key::Column::ClaimedAt.lte(stale_before))
+        .exec(db)
+        .await?;
+    if taken_over.rows_affected == 1 {
+        return Ok(Claim::New);
+    }
+
+    let Some(existing) = find(db, principal, key).await? else {
+        // Removed between the insert and the lookup, which only happens as the key expires
+        return Ok(Claim::InProgress);
+    };
+    if existing.request_hash != request_hash {
+        return Ok(Claim::Mismatch);
+    }
+    match (
+        existing.response_status,
+        existing.patient_id,
+        existing.version,
+    ) {
+        (Some(status), Some(patient_id), Some(version)) => Ok(Claim::Replay {
+            status: u16::try_from(status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .unwrap_or(StatusCode::OK),
+            patient_id,
+            version,
+        }),
+        _ => Ok(Claim::InProgress),
+    }
+}
+
+/// Records how a claimed key's request turned out so retries can replay it
+///
+/// Only the status and the patient are stored, never the response body, which would hold the
+/// patient's fields in plaintext. Run it in the transaction that makes the change, so that a key
+/// is never left unfinished once its change is stored.
+pub async fn complete<C: ConnectionTrait>(
+    db: &C,
+    principal: &str,
+    key: &str,
+    status: StatusCode,
+    (patient_id, version): (Uuid, i32),
+) -> Result<(), DbErr> {
+    idempotency_key::Entity::update_many()
+        .col_expr(
+            idempotency_key::Column::ResponseStatus,
+            (status.as_u16() as i16).into(),
+        )
+        .col_expr(idempotency_key::Column::PatientId, patient_id.into())
+        .col_expr(idempotency_key::Column::Version, version.into())
+        .filter(idempotency_key::Column::Principal.eq(principal))
+        .filter(idempotency_key::Column::Key.eq(key))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Frees a claimed key after the request failed, so the client can retry it
+pub async fn release<C: ConnectionTrait>(db: &C, principal: &str, key: &str) -> Result<(), DbErr> {
+    idempotency_key::Entity::delete_many()
+        .filter(idempotency_key::Column::Principal.eq(principal))
+        .filter(idempotency_key::Column::Key.eq(key))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+async fn find<C: ConnectionTrait>(
+    db: &C,
+    principal: &str,
+    key: &str,
+) -> Result<Option<idempotency_key::Model>, DbErr> {
+    idempotency_key::Entity::find()
+        .filter(idempotency_key::Column::Principal.eq(principal))
+        .filter(idempotency_key::Column::Key.eq(key))
+        .one(db)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    fn affected(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    fn stored(request_hash: &str, response_status: Option<i16>) -> idempotency_key::Model {
+        let now = Utc::now();
+        idempotency_key::Model {
+            id: 1,
+            principal: "jdoe".to_string(),
+            key: "key-1".to_string(),
+            request_hash: request_hash.to_string(),
+            response_status,
+            patient_id: response_status.map(|_| Uuid::nil()),
+            version: response_status.map(|_| 1),
+            created_at: now,
+            expires_at: now + Duration::days(1),
+            claimed_at: now,
+        }
+    }
+
+    /// Claims the key after an earlier request stored `existing`
+    async fn claim_after(existing: idempotency_key::Model, request_hash: &str) -> Claim {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([affected(0), affected(0), affected(0)])
+            .append_query_results([[existing]])
+            .into_connection();
+        claim(&db, &Settings::default(), "jdoe", "key-1", request_hash)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn claims_a_new_key() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([affected(0), affected(1)])
+            .into_connection();
+
+        let claimed = claim(&db, &Settings::default(), "jdoe", "key-1", "hash")
+            .await
+            .unwrap();
+        assert!(matches!(claimed, Claim::New));
+
+        // Expired keys are cleared before the insert that reserves this one
+        let log = db.into_transaction_log();
+        assert!(log[0].statements()[0]
+            .sql
+            .starts_with("DELETE FROM \"idempotency_key\""));
+        assert!(log[1].statements()[0].sql.contains("ON CONFLICT"));
+    }
+
+    #[tokio::test]
+    async fn replays_the_stored_response_to_a_retry() {
+        let existing = stored("hash", Some(200));
+        match claim_after(existing, "hash").await {
+            Claim::Replay {
+                status,
+                patient_id,
+                version,
+            } => {
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!((patient_id, version), (Uuid::nil(), 1));
+            }
+            _ => panic!("expected a replay"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_a_key_reused_with_another_body() {
+        let existing = stored("hash", Some(200));
+        assert!(matches!(
+            claim_after(existing, "other-hash").await,
+            Claim::Mismatch
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_a_key_whose_request_is_unfinished() {
+        let existing = stored("hash", None);
+        assert!(matches!(
+            claim_after(existing, "hash").await,
+            Claim::InProgress
+        ));
+    }
+
+    #[tokio::test]
+    async fn takes_over_a_claim_that_timed_out() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([affected(0), affected(0), affected(1)])
+            .into_connection();
+
+        let claimed = claim(&db, &Settings::default(), "jdoe", "key-1", "hash")
+            .await
+            .unwrap();
+        assert!(matches!(claimed, Claim::New));
+
+        // Only an unfinished claim older than the timeout is taken over
+        let log = db.into_transaction_log();
+        let takeover = &log[2].statements()[0];
+        assert!(takeover.sql.starts_with("UPDATE \"idempotency_key\""));
+        assert!(takeover.sql.contains("\"response_status\" IS NULL"));
+        assert!(takeover.sql.contains("\"claimed_at\" <= $"));
+        let stale_before = takeover.values.as_ref().unwrap().0.last().unwrap().clone();
+        let sea_orm::Value::ChronoDateTimeUtc(Some(stale_before)) = stale_before else {
+            panic!("expected the lease cutoff last");
+        };
+        let lease = Utc::now() - *stale_before;
+        assert!((Duration::seconds(300)..Duration::seconds(301)).contains(&lease));
+    }
+
+    #[test]
+    fn hashes_the_payload_rather_than_its_formatting() {
+        let spaced: serde_json::Value = serde_json::from_str(r#"{ "a": 1,  "b": [2] }"#).unwrap();
+        let compact: serde_json::Value = serde_json::from_str(r#"{"a":1,"b":[2]}"#).unwrap();
+        assert_eq!(
+            hash_request(&spaced).unwrap(),
+            hash_request(&compact).unwrap()
+        );
+        assert_ne!(
+            hash_request(&spaced).unwrap(),
+            hash_request(&serde_json::json!({ "a": 2, "b": [2] })).unwrap()
+        );
+    }
+}
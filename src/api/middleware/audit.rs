@@ -0,0 +1,112 @@
+use crate::api::audit::{AuditActor, AuditPatients, Event, ANONYMOUS};
+use crate::api::middleware::client_ip::ClientIp;
+use crate::api::response::error::ErrorResponse;
+use crate::state::ApplicationState;
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Echoed on every response, and taken from the request when the client sends one
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longer client request IDs are replaced with a generated one
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Writes an audit log record for every request, once its response is ready
+///
+/// The actor comes from `jwt::auth` and the patient from the `:patient_id` path parameter, or
+/// from handlers that return patients not named in the path. A request that touches several
+/// patients gets one record per patient.
+///
+/// A response that names a patient is replaced with a 500 if its records can't be written, so
+/// patient data never leaves unaudited; a change it made is still kept. Every other response
+/// fails open: it is sent anyway, and the failure is logged.
+pub async fn record<B>(
+    State(state): State<Arc<ApplicationState>>,
+    ClientIp(client_ip): ClientIp,
+    matched_path: Option<MatchedPath>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let route = matched_path
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| req.uri().path().to_owned());
+    let action = format!("{} {route}", req.method());
+    let path_patient = patient_in_path(&route, req.uri().path());
+
+    let mut response = next.run(req).await;
+
+    let actor = response
+        .extensions()
+        .get::<AuditActor>()
+        .map_or_else(|| ANONYMOUS.to_string(), |AuditActor(actor)| actor.clone());
+    let patients = match path_patient {
+        Some(patient_id) => vec![Some(patient_id)],
+        None => match response.extensions().get::<AuditPatients>() {
+            Some(AuditPatients(patients)) if !patients.is_empty() => {
+                patients.iter().copied().map(Some).collect()
+            }
+            _ => vec![None],
+        },
+    };
+    let names_patient = patients.iter().any(Option::is_some);
+    let events = patients
+        .into_iter()
+        .map(|patient_id| Event {
+            actor: actor.clone(),
+            action: action.clone(),
+            patient_id,
+            request_id: request_id.clone(),
+            client_ip: client_ip.map(|ip| ip.to_string()),
+            status: response.status(),
+        })
+        .collect();
+
+    if let Err(err) = state.audit_writer.write(events).await {
+        tracing::error!("Failed to write the audit log for request {request_id}: {err}");
+        if names_patient {
+            let code = StatusCode::INTERNAL_SERVER_ERROR;
+            response = (
+                code,
+                Json(ErrorResponse {
+                    status_code: code.as_u16(),
+                    reason: code.canonical_reason().unwrap_or("Unknown error"),
+                    message: "The request couldn't be audited, so its response was withheld"
+                        .to_string(),
+                }),
+            )
+                .into_response();
+        }
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// The patient named by the `:patient_id` parameter of `route`, if it has one
+///
+/// The route and path are matched from the end, since a nested router may leave its prefix off
+/// the route.
+fn patient_in_path(route: &str, path: &str) -> Option<Uuid> {
+    route
+        .rsplit('/')
+        .zip(path.rsplit('/'))
+        .find(|(segment, _)| *segment == ":patient_id")
+        .and_then(|(_, value)| value.parse().ok())
+}
@@ -0,0 +1,80 @@
+use crate::state::ApplicationState;
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// The IP address of the client that made the request, if known
+///
+/// Reads the last X-Forwarded-For entry, the one the proxy appended, when `trust_forwarded_for`
+/// is set, and the peer address of the connection otherwise. Earlier entries come from the
+/// client and can't be trusted.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<Arc<ApplicationState>> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApplicationState>,
+    ) -> Result<Self, Self::Rejection> {
+        if state.settings.load().trust_forwarded_for {
+            let forwarded = parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|value| value.trim().parse().ok());
+            if forwarded.is_some() {
+                return Ok(Self(forwarded));
+            }
+        }
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Ok(Self(peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use axum::http::Request;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    async fn client_ip(trust_forwarded_for: bool) -> Option<IpAddr> {
+        let state =
+            ApplicationState::mock(MockDatabase::new(DatabaseBackend::Postgres).into_connection());
+        state.settings.store(Arc::new(Settings {
+            trust_forwarded_for,
+            ..(**state.settings.load()).clone()
+        }));
+
+        let (mut parts, _) = Request::get("/v1/login")
+            .header("x-forwarded-for", "198.51.100.7, 203.0.113.9")
+            .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 2], 443))))
+            .body(())
+            .unwrap()
+            .into_parts();
+        let Ok(ClientIp(ip)) = ClientIp::from_request_parts(&mut parts, &state).await;
+        ip
+    }
+
+    #[tokio::test]
+    async fn takes_the_entry_the_proxy_appended() {
+        assert_eq!(client_ip(true).await, Some([203, 0, 113, 9].into()));
+    }
+
+    #[tokio::test]
+    async fn ignores_the_header_unless_trusted() {
+        assert_eq!(client_ip(false).await, Some([10, 0, 0, 2].into()));
+    }
+}
@@ -1,8 +1,11 @@
-use crate::api::response::error::ErrorResponse;
+use crate::api::redact;
+use crate::api::request::validation::Validate;
+use crate::api::response::error::{ErrorResponse, ValidationErrorResponse};
+use crate::state::ApplicationState;
 use axum::{
     async_trait,
     body::Body,
-    extract::{rejection::JsonRejection, FromRequest},
+    extract::FromRequest,
     response::{IntoResponse, Response},
     http::{Request, StatusCode},
     Json,
@@ -11,22 +14,28 @@ use serde_json::Value;
 
 use opentelemetry::{Key, Value as otelVal};
 use serde::de::DeserializeOwned;
+use std::sync::Arc;
 use tracing::{instrument, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+/// Parses a JSON body, answering malformed bodies with an `ErrorResponse`
+///
+/// The body of a rejected request is logged with its credentials and PHI masked, following the
+/// redaction settings.
 pub struct CustomJson<T>(pub T);
 
 #[async_trait]
-impl<S, T> FromRequest<S, Body> for CustomJson<T>
+impl<T> FromRequest<Arc<ApplicationState>, Body> for CustomJson<T>
 where
-    Json<T>: FromRequest<S, Body, Rejection = JsonRejection>,
-    S: Send + Sync,
-    T: DeserializeOwned,
+    T: DeserializeOwned + Send,
 {
     type Rejection = (StatusCode, axum::Json<Value>);
 
     #[instrument(level = "info", name = "middleware_json_parsing", skip_all)]
-    async fn from_request(req: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request(
+        req: Request<Body>,
+        state: &Arc<ApplicationState>,
+    ) -> Result<Self, Self::Rejection> {
         // Clones the request method for error tracing
         let method = req.method().clone();
 
@@ -63,9 +72,9 @@ where
                     Key::from("http.status_code"),
                     otelVal::from(status.as_u16() as i64),
                 );
-                let body = String::from_utf8_lossy(&body_bytes);
+                let rules = &state.settings.load().redaction;
                 tracing::info!(
-                    request.payload = %body,
+                    request.payload = %redact::body(&body_bytes, rules),
                     "Malformed JSON"
                 );
                 //span.set_attribute(
@@ -74,7 +83,7 @@ where
                 //);
                 span.set_attribute(
                     Key::from("response.payload"),
-                    otelVal::from(format!("{:?}", &rejection)),
+                    otelVal::from(redact::error_text(&format!("{:?}", &rejection), rules)),
                 );
 
                 let error_response = ErrorResponse {
@@ -103,9 +112,249 @@ where
     }
 }
 
+/// Parses the body like `CustomJson` if there is one
+///
+/// An empty body gives `None`, but a body that doesn't parse is rejected rather than ignored.
+pub struct OptionalJson<T>(pub Option<T>);
+
+#[async_trait]
+impl<T> FromRequest<Arc<ApplicationState>, Body> for OptionalJson<T>
+where
+    T: DeserializeOwned + Send,
+{
+    type Rejection = (StatusCode, axum::Json<Value>);
+
+    async fn from_request(
+        req: Request<Body>,
+        state: &Arc<ApplicationState>,
+    ) -> Result<Self, Self::Rejection> {
+        let (parts, body) = req.into_parts();
+        let body_bytes = hyper::body::to_bytes(body).await.map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": format!("Failed to read body: {}", err) })),
+            )
+        })?;
+        if body_bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Self(None));
+        }
+
+        let req = Request::from_parts(parts, Body::from(body_bytes));
+        let CustomJson(value) = CustomJson::<T>::from_request(req, state).await?;
+        Ok(Self(Some(value)))
+    }
+}
+
+/// Parses the body like `CustomJson`, then validates it
+///
+/// Bodies that parse but fail validation get a 422 listing every failing field.
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T> FromRequest<Arc<ApplicationState>, Body> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate + Send,
+{
+    type Rejection = Response;
+
+    #[instrument(level = "info", name = "middleware_json_validation", skip_all)]
+    async fn from_request(
+        req: Request<Body>,
+        state: &Arc<ApplicationState>,
+    ) -> Result<Self, Self::Rejection> {
+        let CustomJson(value) = CustomJson::<T>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        match value.validate() {
+            Ok(()) => Ok(Self(value)),
+            Err(errors) => {
+                let status = StatusCode::UNPROCESSABLE_ENTITY;
+                let count = errors.0.len();
+                let error_response = ValidationErrorResponse {
+                    status_code: status.as_u16(),
+                    reason: status.canonical_reason().unwrap_or("Unknown"),
+                    message: format!(
+                        "The request body has {count} invalid field{}",
+                        if count == 1 { "" } else { "s" }
+                    ),
+                    errors: errors.0,
+                };
+
+                let span = Span::current();
+                span.set_attribute(
+                    Key::from("http.status_code"),
+                    otelVal::from(status.as_u16() as i64),
+                );
+                span.set_attribute(
+                    Key::from("response.payload"),
+                    otelVal::from(format!("{:?}", &error_response)),
+                );
+
+                Err((status, Json(error_response)).into_response())
+            }
+        }
+    }
+}
+
 #[instrument(level = "info", name = "middleware_json_wrapper", skip_all)]
 pub fn to_response(res: Json<Value>) -> Response {
     let mut body = res.0.to_string();
     body.push('\n');
     body.into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::request::create_patient_request::CreatePatientRequest;
+    use chrono::{Datelike, Utc};
+    use sea_orm::{DatabaseBackend, MockDatabase};
+    use serde_json::json;
+
+    fn patient() -> Value {
+        json!({
+            "name": { "first": "John", "middle": "R.", "surname": "Smith" },
+            "address": {
+                "address_lines": ["123 Fake St."],
+                "locality": "Portland",
+                "administrative_area": "OR",
+                "postal_code": "97211",
+                "country_region": "US",
+            },
+            "birth_date": { "day": 6, "month": 8, "year": 1997 },
+        })
+    }
+
+    /// The status and body of the response to posting the patient
+    async fn post(body: Value) -> (StatusCode, Value) {
+        let state =
+            ApplicationState::mock(MockDatabase::new(DatabaseBackend::Postgres).into_connection());
+        let req = Request::post("/v1/patient")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        match ValidJson::<CreatePatientRequest>::from_request(req, &state).await {
+            Ok(_) => (StatusCode::OK, Value::Null),
+            Err(response) => {
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, serde_json::from_slice(&body).unwrap())
+            }
+        }
+    }
+
+    /// The paths of the fields the response reports as invalid
+    fn fields(body: &Value) -> Vec<&str> {
+        body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn accepts_a_valid_patient() {
+        assert_eq!(post(patient()).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_dates_that_are_not_on_the_calendar() {
+        let mut body = patient();
+        body["birth_date"] = json!({ "day": 1, "month": 13, "year": 1997 });
+        let (status, response) = post(body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&response), ["birth_date.month"]);
+
+        let mut body = patient();
+        body["birth_date"] = json!({ "day": 30, "month": 2, "year": 2000 });
+        let (status, response) = post(body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&response), ["birth_date.day"]);
+        assert_eq!(response["errors"][0]["message"], "must be from 1 to 29");
+
+        // 1900 is not a leap year, but 2000 is
+        let mut body = patient();
+        body["birth_date"] = json!({ "day": 29, "month": 2, "year": 1900 });
+        let (status, response) = post(body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["errors"][0]["message"], "must be from 1 to 28");
+
+        let mut body = patient();
+        body["birth_date"] = json!({ "day": 29, "month": 2, "year": 2000 });
+        assert_eq!(post(body).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_future_birth_date() {
+        let mut body = patient();
+        body["birth_date"]["year"] = json!(Utc::now().year() + 1);
+        let (status, response) = post(body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(fields(&response), ["birth_date"]);
+    }
+
+    #[tokio::test]
+    async fn lists_every_invalid_field() {
+        let mut body = patient();
+        body["name"]["first"] = json!(" ");
+        body["address"]["country_region"] = json!("XX");
+        let (status, response) = post(body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response["message"], "The request body has 2 invalid fields");
+        assert_eq!(fields(&response), ["name.first", "address.country_region"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_fields() {
+        let mut body = patient();
+        let birth_date = body.as_object_mut().unwrap().remove("birth_date").unwrap();
+        body["birthdate"] = birth_date;
+        let (status, response) = post(body).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(
+            response["message"]
+                .as_str()
+                .unwrap()
+                .contains("unknown field `birthdate`"),
+            "{response}"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_a_malformed_optional_body() {
+        use crate::api::request::logout_request::LogoutRequest;
+
+        let state =
+            ApplicationState::mock(MockDatabase::new(DatabaseBackend::Postgres).into_connection());
+        let logout = |body: &'static str| {
+            Request::post("/v1/logout")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let OptionalJson(payload) = OptionalJson::<LogoutRequest>::from_request(logout(""), &state)
+            .await
+            .unwrap();
+        assert!(payload.is_none());
+
+        let body = r#"{"refresh_token": "abc"}"#;
+        let OptionalJson(payload) =
+            OptionalJson::<LogoutRequest>::from_request(logout(body), &state)
+                .await
+                .unwrap();
+        assert_eq!(payload.unwrap().refresh_token, "abc");
+
+        let Err((status, response)) =
+            OptionalJson::<LogoutRequest>::from_request(logout(r#"{"refresh_token": "#), &state)
+                .await
+        else {
+            panic!("a malformed body was accepted");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.0["status_code"], 400);
+    }
+}
@@ -9,16 +9,58 @@ use axum::{
     Json,
 };
 
+use crate::api::audit::AuditActor;
+use crate::api::auth::api_keys::{self, ApiKeyScopes, API_KEY_HEADER};
 use crate::api::response::error::ErrorResponse;
 use crate::api::response::TokenClaims;
 use crate::state::ApplicationState;
-use jsonwebtoken::{decode, DecodingKey, Validation};
 
 pub async fn auth<B>(
     State(state): State<Arc<ApplicationState>>,
     mut req: Request<B>,
     next: Next<B>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    // Machine clients authenticate with an API key instead of a bearer token
+    let api_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|api_key| api_key.to_str().ok())
+        .map(|api_key| api_key.to_owned());
+
+    if let Some(api_key) = api_key {
+        let key = api_keys::authenticate(state.db_conn.load().as_ref(), &api_key)
+            .await
+            .map_err(|err| {
+                tracing::error!("Failed to look up API key: {err}");
+                let json_error = ErrorResponse {
+                    status_code: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                    reason: StatusCode::INTERNAL_SERVER_ERROR
+                        .canonical_reason()
+                        .unwrap_or("Unknown error"),
+                    message: "Failed to verify API key".to_string(),
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(json_error))
+            })?
+            .ok_or_else(|| {
+                let json_error = ErrorResponse {
+                    status_code: StatusCode::UNAUTHORIZED.as_u16(),
+                    reason: StatusCode::UNAUTHORIZED
+                        .canonical_reason()
+                        .unwrap_or("Unknown error"),
+                    message: "Invalid API key".to_string(),
+                };
+                (StatusCode::UNAUTHORIZED, Json(json_error))
+            })?;
+
+        let claims = api_keys::claims(&key);
+        let actor = AuditActor(claims.sub.clone());
+        req.extensions_mut().insert(claims);
+        req.extensions_mut().insert(ApiKeyScopes(key.scopes()));
+        let mut response = next.run(req).await;
+        response.extensions_mut().insert(actor);
+        return Ok(response);
+    }
+
     let token = req
         .headers()
         .get(header::AUTHORIZATION)
@@ -42,14 +84,18 @@ pub async fn auth<B>(
         (StatusCode::UNAUTHORIZED, Json(json_error))
     })?;
 
-    let secret = &state.settings.load().token_secret;
+    // Falls back to the external identity provider, if any, for tokens not issued locally
+    let claims = match state.signing_keys.verify::<TokenClaims>(&token) {
+        Ok(claims) => Ok(claims),
+        Err(err) => match &state.oidc {
+            Some(oidc) => oidc.verify(&token).await.map_err(|oidc_err| {
+                tracing::debug!("Rejected bearer token: {err}; OIDC: {oidc_err:#}");
+            }),
+            None => Err(()),
+        },
+    };
 
-    let claims = decode::<TokenClaims>(
-        &token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|_| {
+    let claims = claims.map_err(|_| {
         let json_error = ErrorResponse {
             //status: "error".to_string(),
             //status: Status::Error,
@@ -60,9 +106,23 @@ pub async fn auth<B>(
             message: "Invalid bearer token".to_string(),
         };
         (StatusCode::UNAUTHORIZED, Json(json_error))
-    })?
-    .claims;
+    })?;
+
+    if state.revocations.is_revoked(&claims) {
+        let json_error = ErrorResponse {
+            status_code: StatusCode::UNAUTHORIZED.as_u16(),
+            reason: StatusCode::UNAUTHORIZED
+                .canonical_reason()
+                .unwrap_or("Unknown error"),
+            message: "Bearer token has been revoked".to_string(),
+        };
+        return Err((StatusCode::UNAUTHORIZED, Json(json_error)));
+    }
 
+    // Tells the audit middleware who made the request
+    let actor = AuditActor(claims.sub.clone());
     req.extensions_mut().insert(claims);
-    Ok(next.run(req).await)
+    let mut response = next.run(req).await;
+    response.extensions_mut().insert(actor);
+    Ok(response)
 }
@@ -1,2 +1,5 @@
+pub mod audit;
+pub mod client_ip;
 pub mod json;
 pub mod jwt;
+pub mod rbac;
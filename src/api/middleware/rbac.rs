@@ -0,0 +1,176 @@
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::IntoResponse,
+    Json,
+};
+
+use crate::api::auth::api_keys::ApiKeyScopes;
+use crate::api::response::error::ErrorResponse;
+use crate::api::response::TokenClaims;
+use crate::entities::api_key::Scope;
+use crate::entities::user::Role;
+
+/// Who may call a route: users with one of the roles, or API keys with the scope
+pub struct Access {
+    pub roles: &'static [Role],
+    /// API keys are refused when this is `None`
+    pub scope: Option<Scope>,
+    /// Refuses users signed in through the external identity provider, for routes that act on
+    /// the caller's local account
+    pub local_only: bool,
+}
+
+// Access policies for the /v1 routes
+pub const PATIENT_READ: &Access = &Access {
+    roles: &[Role::Admin, Role::Clinician, Role::FrontDesk, Role::ReadOnly],
+    scope: Some(Scope::PatientsRead),
+    local_only: false,
+};
+pub const PATIENT_WRITE: &Access = &Access {
+    roles: &[Role::Admin, Role::Clinician, Role::FrontDesk],
+    scope: Some(Scope::PatientsWrite),
+    local_only: false,
+};
+pub const PATIENT_DELETE: &Access = &Access {
+    roles: &[Role::Admin],
+    scope: Some(Scope::PatientsDelete),
+    local_only: false,
+};
+pub const ADMIN: &Access = &Access {
+    roles: &[Role::Admin],
+    scope: None,
+    local_only: false,
+};
+/// Any logged-in user, but no API key
+pub const USER: &Access = &Access {
+    roles: &[Role::Admin, Role::Clinician, Role::FrontDesk, Role::ReadOnly],
+    scope: None,
+    local_only: false,
+};
+/// Any user with a local account, whose password and second factor are managed here
+pub const LOCAL_USER: &Access = &Access {
+    roles: &[Role::Admin, Role::Clinician, Role::FrontDesk, Role::ReadOnly],
+    scope: None,
+    local_only: true,
+};
+
+impl Access {
+    /// Whether the policy admits a caller, by the scopes of its API key if it sent one, or else
+    /// by its role
+    ///
+    /// For handlers that disclose more to some of the callers `authorize` let through.
+    pub fn permits(&self, claims: &TokenClaims, api_key: Option<&ApiKeyScopes>) -> bool {
+        match api_key {
+            Some(ApiKeyScopes(scopes)) => self.scope.is_some_and(|scope| scopes.contains(&scope)),
+            None => {
+                self.roles.contains(&claims.role) && !(self.local_only && claims.is_federated())
+            }
+        }
+    }
+}
+
+/// Rejects requests whose token role or API key scopes are not allowed by the policy
+///
+/// Must run after `jwt::auth`, which places the `TokenClaims` in the request extensions
+pub async fn authorize<B>(
+    State(access): State<&'static Access>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(ApiKeyScopes(scopes)) = req.extensions().get::<ApiKeyScopes>() {
+        let message = match access.scope {
+            Some(scope) if scopes.contains(&scope) => return Ok(next.run(req).await),
+            Some(scope) => format!("API key lacks the \"{scope}\" scope"),
+            None => "API keys are not permitted to perform this operation".to_string(),
+        };
+        let json_error = ErrorResponse {
+            status_code: StatusCode::FORBIDDEN.as_u16(),
+            reason: StatusCode::FORBIDDEN
+                .canonical_reason()
+                .unwrap_or("Unknown error"),
+            message,
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+
+    let claims = req.extensions().get::<TokenClaims>();
+
+    // A provider identity must not act on a local account that happens to share its name
+    if access.local_only && claims.is_some_and(TokenClaims::is_federated) {
+        let json_error = ErrorResponse {
+            status_code: StatusCode::FORBIDDEN.as_u16(),
+            reason: StatusCode::FORBIDDEN
+                .canonical_reason()
+                .unwrap_or("Unknown error"),
+            message: "Accounts from the identity provider are managed by the provider".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+
+    match claims.map(|claims| claims.role) {
+        Some(role) if access.roles.contains(&role) => Ok(next.run(req).await),
+        Some(role) => {
+            let json_error = ErrorResponse {
+                status_code: StatusCode::FORBIDDEN.as_u16(),
+                reason: StatusCode::FORBIDDEN
+                    .canonical_reason()
+                    .unwrap_or("Unknown error"),
+                message: format!("Role \"{role}\" is not permitted to perform this operation"),
+            };
+            Err((StatusCode::FORBIDDEN, Json(json_error)))
+        }
+        None => {
+            let json_error = ErrorResponse {
+                status_code: StatusCode::UNAUTHORIZED.as_u16(),
+                reason: StatusCode::UNAUTHORIZED
+                    .canonical_reason()
+                    .unwrap_or("Unknown error"),
+                message: "Missing token claims".to_string(),
+            };
+            Err((StatusCode::UNAUTHORIZED, Json(json_error)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::oidc::PRINCIPAL_PREFIX;
+    use axum::{body::Body, middleware, routing::put, Router};
+    use tower::ServiceExt;
+
+    async fn call(access: &'static Access, sub: &str) -> StatusCode {
+        let claims = TokenClaims {
+            jti: uuid::Uuid::new_v4(),
+            sub: sub.to_string(),
+            role: Role::Admin,
+            iat: 0.0,
+            exp: usize::MAX,
+        };
+        let app = Router::new().route(
+            "/me/password",
+            put(|| async { StatusCode::NO_CONTENT })
+                .route_layer(middleware::from_fn_with_state(access, authorize))
+                .route_layer(middleware::from_fn(
+                    move |mut req: Request<Body>, next: Next<Body>| {
+                        req.extensions_mut().insert(claims.clone());
+                        next.run(req)
+                    },
+                )),
+        );
+        let req = Request::put("/me/password").body(Body::empty()).unwrap();
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn keeps_provider_identities_out_of_local_accounts() {
+        // A provider user named "admin" is not the local admin
+        let federated = format!("{PRINCIPAL_PREFIX}https://idp.example.com:admin");
+
+        assert_eq!(call(LOCAL_USER, "admin").await, StatusCode::NO_CONTENT);
+        assert_eq!(call(LOCAL_USER, &federated).await, StatusCode::FORBIDDEN);
+        assert_eq!(call(USER, &federated).await, StatusCode::NO_CONTENT);
+    }
+}
@@ -1,13 +1,21 @@
 use crate::state::ApplicationState;
-use axum::Router;
+use axum::{routing::get, Router};
 use std::sync::Arc;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 //use utoipa_scalar::{Scalar, Servable};
 
+pub mod audit;
+pub mod auth;
+mod conditional;
+pub mod duplicates;
+pub mod encryption;
 mod handlers;
+mod idempotency;
 mod middleware;
+pub mod purge;
+mod redact;
 mod request;
 mod response;
 //mod schemas;
@@ -20,6 +28,10 @@ pub fn configure(state: Arc<ApplicationState>) -> Router {
     Router::new()
         // For Swagger UI
         .merge(SwaggerUi::new(SWAGGER).url(JSON, crate::api::v1::ApiDoc::openapi()))
+        .route(
+            "/.well-known/jwks.json",
+            get(handlers::jwks_handler::jwks).with_state(state.clone()),
+        )
         .nest("/v1", v1::configure(state))
 
     // For Scalar UI
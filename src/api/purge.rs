@@ -0,0 +1,313 @@
+use crate::entities::patient::{self, address, birthdate, name};
+use crate::entities::{idempotency_key, patient_history, patient_tombstone};
+use crate::state::ApplicationState;
+
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Recorded as `purged_by` for patients purged by the retention policy
+pub const RETENTION: &str = "retention";
+
+/// What purging a patient removed, or would remove on a dry run
+#[derive(Clone, Debug, Serialize)]
+pub struct Erasure {
+    pub patient_id: Uuid,
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// History entries, each a full copy of the patient at one version
+    pub history_entries: u64,
+    /// Idempotency keys of `POST /v1/patient` requests that created the patient, whose replays
+    /// would have rebuilt it
+    pub idempotency_keys: u64,
+}
+
+/// How a purge turned out
+pub enum Purge {
+    /// The patient was purged, or would have been on a dry run
+    Purged(Erasure),
+    NotFound,
+    /// The patient was purged before
+    AlreadyPurged,
+    /// The patient isn't deleted, which it must be before it can be purged
+    Active,
+}
+
+/// Permanently removes a deleted patient and everything that holds its PHI
+///
+/// The patient's name, address and birth date rows go with it, along with its history and the
+/// idempotency keys of the request that created it. A tombstone keeps its ID and the dates it was created, deleted and
+/// purged. Audit records hold no PHI, only the patient ID, so they are kept and the hash chain
+/// still verifies.
+///
+/// A dry run makes the same changes in a transaction that is rolled back, so its counts are exact.
+pub async fn purge(
+    db: &DatabaseConnection,
+    patient_id: Uuid,
+    purged_by: &str,
+    dry_run: bool,
+) -> Result<Purge, DbErr> {
+    let txn = db.begin().await?;
+
+    // The row lock holds off a concurrent restore until the purge commits
+    let Some(model) = patient::Entity::find()
+        .filter(patient::Column::PatientId.eq(patient_id))
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+    else {
+        return Ok(match was_purged(&txn, patient_id).await? {
+            true => Purge::AlreadyPurged,
+            false => Purge::NotFound,
+        });
+    };
+    if model.active_flag {
+        return Ok(Purge::Active);
+    }
+
+    let history_entries = patient_history::Entity::delete_many()
+        .filter(patient_history::Column::PatientId.eq(patient_id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+    let idempotency_keys = idempotency_key::Entity::delete_many()
+        .filter(idempotency_key::Column::PatientId.eq(patient_id))
+        .exec(&txn)
+        .await?
+        .rows_affected;
+
+    // The patient goes first, since it references the other rows
+    patient::Entity::delete_by_id(model.id).exec(&txn).await?;
+    name::Entity::delete_by_id(model.name_id).exec(&txn).await?;
+    address::Entity::delete_by_id(model.address_id)
+        .exec(&txn)
+        .await?;
+    birthdate::Entity::delete_by_id(model.birthdate_id)
+        .exec(&txn)
+        .await?;
+
+    patient_tombstone::ActiveModel {
+        patient_id: Set(patient_id),
+        created_at: Set(model.created_at),
+        deleted_at: Set(model.deleted_at),
+        deleted_by: Set(model.deleted_by),
+        purged_at: Set(Utc::now()),
+        purged_by: Set(purged_by.to_string()),
+    }
+    .insert(&txn)
+    .await?;
+
+    if dry_run {
+        txn.rollback().await?;
+    } else {
+        txn.commit().await?;
+    }
+    Ok(Purge::Purged(Erasure {
+        patient_id,
+        deleted_at: model.deleted_at,
+        history_entries,
+        idempotency_keys,
+    }))
+}
+
+/// Whether the patient was purged, so requests for it get a 410 rather than a 404
+pub async fn was_purged<C: ConnectionTrait>(db: &C, patient_id: Uuid) -> Result<bool, DbErr> {
+    Ok(patient_tombstone::Entity::find_by_id(patient_id)
+        .one(db)
+        .await?
+        .is_some())
+}
+
+/// Patients deleted more than `days` ago, oldest deletion first
+///
+/// Patients deleted before deletion times were recorded have none, and are only purged by ID.
+pub async fn past_retention<C: ConnectionTrait>(db: &C, days: u32) -> Result<Vec<Uuid>, DbErr> {
+    let cutoff = Utc::now() - Duration::days(i64::from(days));
+    patient::Entity::find()
+        .select_only()
+        .column(patient::Column::PatientId)
+        .filter(patient::Column::ActiveFlag.eq(false))
+        .filter(patient::Column::DeletedAt.lt(cutoff))
+        .order_by_asc(patient::Column::DeletedAt)
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+/// Purges every patient deleted more than `days` ago
+pub async fn purge_past_retention(
+    db: &DatabaseConnection,
+    days: u32,
+    purged_by: &str,
+    dry_run: bool,
+) -> Result<Vec<Erasure>, DbErr> {
+    let mut erased = Vec::new();
+    for patient_id in past_retention(db, days).await? {
+        // A patient restored since it was listed is left alone
+        if let Purge::Purged(erasure) = purge(db, patient_id, purged_by, dry_run).await? {
+            erased.push(erasure);
+        }
+    }
+    Ok(erased)
+}
+
+/// Purges patients past the retention period on the configured interval, for the lifetime of the
+/// server
+pub fn spawn_retention(state: Arc<ApplicationState>) {
+    let every = state.settings.load().retention.interval_seconds.max(1);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(every));
+        loop {
+            interval.tick().await;
+            let Some(days) = state.settings.load().retention.purge_inactive_after_days else {
+                continue;
+            };
+            match purge_past_retention(state.db_conn.load().as_ref(), days, RETENTION, false).await
+            {
+                Ok(erased) if !erased.is_empty() => tracing::info!(
+                    "Purged {} patients deleted more than {days} days ago",
+                    erased.len()
+                ),
+                Ok(_) => {}
+                Err(err) => tracing::error!("Failed to purge patients past retention: {err}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Transaction};
+
+    fn affected(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    fn stored(patient_id: Uuid, active_flag: bool) -> patient::Model {
+        patient::Model {
+            id: 1,
+            active_flag,
+            patient_id,
+            created_at: Utc::now(),
+            version: 3,
+            key_version: None,
+            deleted_at: (!active_flag).then(Utc::now),
+            deleted_by: (!active_flag).then(|| "admin".to_string()),
+            name_id: 2,
+            address_id: 3,
+            birthdate_id: 4,
+        }
+    }
+
+    /// Purges a deleted patient with three history entries and one idempotency key
+    async fn purge_deleted(dry_run: bool) -> (Erasure, Vec<Transaction>) {
+        let patient_id = Uuid::new_v4();
+        let model = stored(patient_id, false);
+        let tombstone = patient_tombstone::Model {
+            patient_id,
+            created_at: model.created_at,
+            deleted_at: model.deleted_at,
+            deleted_by: model.deleted_by.clone(),
+            purged_at: Utc::now(),
+            purged_by: "admin".to_string(),
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[model]])
+            .append_exec_results([affected(3), affected(1)])
+            .append_exec_results((0..4).map(|_| affected(1)))
+            .append_query_results([[tombstone]])
+            .into_connection();
+
+        let Purge::Purged(erasure) = purge(&db, patient_id, "admin", dry_run).await.unwrap() else {
+            panic!("expected the patient to be purged");
+        };
+        (erasure, db.into_transaction_log())
+    }
+
+    #[tokio::test]
+    async fn removes_every_row_holding_the_patient() {
+        let (erasure, log) = purge_deleted(false).await;
+        assert_eq!((erasure.history_entries, erasure.idempotency_keys), (3, 1));
+
+        let statements: Vec<&str> = log[0]
+            .statements()
+            .iter()
+            .map(|statement| statement.sql.as_str())
+            .collect();
+        assert!(statements[1].ends_with("FOR UPDATE"));
+        assert!(statements[2].starts_with("DELETE FROM \"patient_history\""));
+        // Idempotency keys are found by the patient they created
+        assert_eq!(
+            statements[3],
+            "DELETE FROM \"idempotency_key\" WHERE \"idempotency_key\".\"patient_id\" = $1"
+        );
+        for (statement, table) in
+            statements[4..8]
+                .iter()
+                .zip(["patient", "name", "address", "birthdate"])
+        {
+            assert!(statement.starts_with(&format!("DELETE FROM \"{table}\"")));
+        }
+        assert!(statements[8].starts_with("INSERT INTO \"patient_tombstone\""));
+        assert_eq!(statements.last(), Some(&"COMMIT"));
+    }
+
+    #[tokio::test]
+    async fn rolls_back_a_dry_run_with_exact_counts() {
+        let (erasure, log) = purge_deleted(true).await;
+        assert_eq!((erasure.history_entries, erasure.idempotency_keys), (3, 1));
+
+        let statements = log[0].statements();
+        assert_eq!(statements.last().unwrap().sql, "ROLLBACK");
+        assert!(!statements.iter().any(|statement| statement.sql == "COMMIT"));
+    }
+
+    #[tokio::test]
+    async fn leaves_an_active_patient_alone() {
+        let patient_id = Uuid::new_v4();
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[stored(patient_id, true)]])
+            .into_connection();
+
+        let purged = purge(&db, patient_id, "admin", false).await.unwrap();
+        assert!(matches!(purged, Purge::Active));
+
+        let log = db.into_transaction_log();
+        assert!(!log[0]
+            .statements()
+            .iter()
+            .any(|statement| statement.sql.starts_with("DELETE")));
+    }
+
+    #[tokio::test]
+    async fn selects_patients_deleted_before_the_retention_period() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<patient::Model>::new()])
+            .into_connection();
+
+        let before = Utc::now() - Duration::days(30);
+        assert!(past_retention(&db, 30).await.unwrap().is_empty());
+        let after = Utc::now() - Duration::days(30);
+
+        let log = db.into_transaction_log();
+        let statement = &log[0].statements()[0];
+        assert!(statement.sql.ends_with(
+            "WHERE \"patient\".\"active_flag\" = $1 AND \"patient\".\"deleted_at\" < $2 \
+             ORDER BY \"patient\".\"deleted_at\" ASC"
+        ));
+        let values = &statement.values.as_ref().unwrap().0;
+        assert_eq!(values[0], false.into());
+        let sea_orm::Value::ChronoDateTimeUtc(Some(cutoff)) = &values[1] else {
+            panic!("expected the cutoff as a timestamp");
+        };
+        assert!((before..=after).contains(cutoff.as_ref()));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::settings::Redaction;
+
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+/// Replaces masked values in logs and traces
+pub const MASK: &str = "[REDACTED]";
+
+/// Fields that are always masked, whatever the settings: credentials, and the names, birth dates
+/// and addresses of patients
+///
+/// A match masks the whole value, so `address` hides every line of an address.
+const ALWAYS_MASKED: &[&str] = &[
+    // Credentials
+    "password",
+    "current_password",
+    "new_password",
+    "code",
+    "mfa_token",
+    "refresh_token",
+    "token",
+    "secret",
+    // Names
+    "name",
+    "first",
+    "middle",
+    "surname",
+    "first_name",
+    // Birth dates
+    "birth_date",
+    "birthdate",
+    "birth_year",
+    "born_from",
+    "born_to",
+    "min_age",
+    "max_age",
+    // Addresses
+    "address",
+    "address_lines",
+    "sublocality",
+    "locality",
+    "administrative_area",
+    "postal_code",
+    // Page cursors, which hold the last patient's surname or birth date
+    "cursor",
+];
+
+/// Formats a request type as JSON with its sensitive fields masked, for logs and span attributes
+///
+/// Nothing is masked when `debug` is set in the redaction settings.
+pub struct Redacted<'a, T: ?Sized> {
+    value: &'a T,
+    rules: &'a Redaction,
+}
+
+impl<'a, T: Serialize + ?Sized> Redacted<'a, T> {
+    pub fn new(value: &'a T, rules: &'a Redaction) -> Self {
+        Self { value, rules }
+    }
+}
+
+impl<T: Serialize + ?Sized> fmt::Display for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_value(self.value) {
+            Ok(mut value) => {
+                mask(&mut value, self.rules);
+                write!(f, "{value}")
+            }
+            Err(_) => f.write_str(MASK),
+        }
+    }
+}
+
+impl<T: Serialize + ?Sized> fmt::Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Formats a raw request body for logs and traces
+///
+/// Bodies that parse as JSON have their sensitive fields masked. Anything else could hold
+/// sensitive values that can't be found, so only its length is shown.
+pub fn body(bytes: &[u8], rules: &Redaction) -> String {
+    if rules.debug {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut value) => {
+            mask(&mut value, rules);
+            value.to_string()
+        }
+        Err(_) => format!("[{} bytes that aren't valid JSON]", bytes.len()),
+    }
+}
+
+/// Masks the values a parser quotes in its error messages, such as `invalid type: string "Jane"`
+pub fn error_text(text: &str, rules: &Redaction) -> String {
+    if rules.debug {
+        return text.to_string();
+    }
+
+    // serde_json quotes strings with "..." and numbers with `...`, and field names with `...`
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['"', '`']) {
+        let quote = rest[start..].chars().next().unwrap_or('"');
+        let Some(len) = rest[start + 1..].find(quote) else {
+            break;
+        };
+        let quoted = &rest[start + 1..start + 1 + len];
+        redacted.push_str(&rest[..start]);
+        if quote == '`' && !quoted.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            // A field name, which is safe and useful
+            redacted.push_str(&rest[start..start + len + 2]);
+        } else {
+            redacted.push_str(MASK);
+        }
+        rest = &rest[start + len + 2..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Masks matching fields anywhere in `value`, unless in debug mode
+fn mask(value: &mut Value, rules: &Redaction) {
+    if rules.debug {
+        return;
+    }
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if is_masked(key, rules) {
+                    *field = Value::String(MASK.to_string());
+                } else {
+                    mask(field, rules);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| mask(item, rules)),
+        _ => {}
+    }
+}
+
+fn is_masked(key: &str, rules: &Redaction) -> bool {
+    ALWAYS_MASKED
+        .iter()
+        .copied()
+        .chain(rules.fields.iter().map(String::as_str))
+        .any(|field| field.eq_ignore_ascii_case(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::handlers::list_patients_handler::GetPatientQuery;
+    use crate::api::request::create_patient_request::{
+        AddressCreate, BirthDateCreate, CreatePatientRequest, NameCreate,
+    };
+    use crate::api::request::login_request::LoginRequest;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    /// Values that must never appear in a log or trace outside debug mode
+    const PHI: &[&str] = &[
+        "S3cret-passw0rd!",
+        "Jane",
+        "Quinn",
+        "Doe",
+        "123 Fake St.",
+        "Brooklyn",
+        "Portland",
+        "97211",
+        "1997",
+    ];
+
+    fn patient() -> CreatePatientRequest {
+        CreatePatientRequest {
+            name: NameCreate {
+                first: "Jane".to_string(),
+                middle: Some("Quinn".to_string()),
+                surname: "Doe".to_string(),
+            },
+            address: AddressCreate {
+                address_lines: vec!["123 Fake St.".to_string()],
+                sublocality: Some("Brooklyn".to_string()),
+                locality: Some("Portland".to_string()),
+                administrative_area: Some("OR".to_string()),
+                postal_code: Some("97211".to_string()),
+                country_region: "US".to_string(),
+            },
+            birth_date: BirthDateCreate {
+                day: 6,
+                month: 8,
+                year: 1997,
+            },
+        }
+    }
+
+    fn assert_no_phi(output: &str) {
+        for value in PHI {
+            assert!(!output.contains(value), "{value:?} leaked into {output}");
+        }
+    }
+
+    #[test]
+    fn masks_login_passwords() {
+        let login = LoginRequest {
+            username: "admin".to_string(),
+            password: "S3cret-passw0rd!".to_string(),
+        };
+
+        let output = Redacted::new(&login, &Redaction::default()).to_string();
+        assert_no_phi(&output);
+        assert!(output.contains("admin"));
+
+        // Even the Debug output that handlers used to attach to spans
+        assert_no_phi(&format!("{login:?}"));
+    }
+
+    #[test]
+    fn masks_patient_names_birth_dates_and_addresses() {
+        let output = Redacted::new(&patient(), &Redaction::default()).to_string();
+        assert_no_phi(&output);
+        assert!(output.contains(MASK));
+    }
+
+    #[test]
+    fn masks_search_filters() {
+        let cursor =
+            URL_SAFE_NO_PAD.encode(r#"{"order":"asc","sort":"surname","surname":"Doe","id":7}"#);
+        let query = GetPatientQuery {
+            first_name: Some("Jane".to_string()),
+            surname: Some("Doe".to_string()),
+            birth_year: Some(1997),
+            postal_code: Some("97211".to_string()),
+            locality: Some("Portland".to_string()),
+            cursor: Some(cursor.clone()),
+            ..Default::default()
+        };
+
+        let output = Redacted::new(&query, &Redaction::default()).to_string();
+        assert_no_phi(&output);
+        assert!(!output.contains(&cursor), "the cursor leaked into {output}");
+    }
+
+    #[test]
+    fn masks_raw_bodies() {
+        let rules = Redaction::default();
+
+        // A body that parses but doesn't fit the request type
+        let json = serde_json::to_vec(&patient()).unwrap();
+        assert_no_phi(&body(&json, &rules));
+
+        // A body that doesn't parse at all
+        let malformed = br#"{"name": {"first": "Jane", "surname": "Doe"}, "address": "#;
+        let output = body(malformed, &rules);
+        assert_no_phi(&output);
+        assert!(output.contains(&malformed.len().to_string()));
+    }
+
+    #[test]
+    fn masks_values_quoted_in_parser_errors() {
+        let text = "Failed to deserialize the JSON body into the target type: name.first: invalid \
+                    type: integer `1997`, expected a string at line 1 column 20; unknown field \
+                    `nickname`; invalid value: string \"Jane\"";
+
+        let output = error_text(text, &Redaction::default());
+        assert_no_phi(&output);
+        assert!(output.contains("`nickname`"));
+    }
+
+    #[test]
+    fn masks_configured_fields() {
+        let rules = Redaction {
+            fields: vec!["Username".to_string()],
+            ..Default::default()
+        };
+        let login = LoginRequest {
+            username: "admin".to_string(),
+            password: "S3cret-passw0rd!".to_string(),
+        };
+
+        let output = Redacted::new(&login, &rules).to_string();
+        assert!(!output.contains("admin"));
+        assert_no_phi(&output);
+    }
+
+    #[test]
+    fn shows_everything_in_debug_mode() {
+        let rules = Redaction {
+            debug: true,
+            ..Default::default()
+        };
+
+        let output = Redacted::new(&patient(), &rules).to_string();
+        assert!(output.contains("Jane") && output.contains("123 Fake St."));
+        assert!(body(b"{\"first\": \"Jane\"", &rules).contains("Jane"));
+        assert!(error_text("string \"Jane\"", &rules).contains("Jane"));
+    }
+}
@@ -0,0 +1,13 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    /// Your current password
+    #[schema(example = "Correct-Horse-Battery-9")]
+    pub current_password: String,
+
+    /// Your new password, which must meet the password policy
+    #[schema(example = "Tr0ub4dor&3 Horse Staple")]
+    pub new_password: String,
+}
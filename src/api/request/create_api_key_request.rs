@@ -0,0 +1,18 @@
+use crate::entities::api_key::Scope;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    /// A label for the client that holds the key
+    #[schema(example = "lab-results-import")]
+    pub name: String,
+
+    /// The operations the key may perform
+    #[schema(example = json!(["patients:read", "patients:write"]))]
+    pub scopes: Vec<Scope>,
+
+    /// An optional RFC3339-formatted expiry time
+    #[schema(example = "2026-01-01T00:00:00Z")]
+    pub expires_at: Option<String>,
+}
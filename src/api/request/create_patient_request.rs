@@ -1,7 +1,9 @@
+use super::validation::{Validate, ValidationErrors};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 /// The full legal name of the patient
 pub struct NameCreate {
     /// The first name, sometimes refered to as given name, of the patient
@@ -18,6 +20,7 @@ pub struct NameCreate {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct AddressCreate {
     /// Address lines consist of the street number, street name, unit number, or suite number of an address
     ///
@@ -54,6 +57,7 @@ pub struct AddressCreate {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 pub struct BirthDateCreate {
     /// The day for a birth date with no leading zeros
     #[schema(example = "6")]
@@ -74,9 +78,26 @@ pub struct BirthDateCreate {
 //}
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
 /// Patient information
 pub struct CreatePatientRequest {
     pub name: NameCreate,
     pub address: AddressCreate,
     pub birth_date: BirthDateCreate,
 }
+
+impl Validate for CreatePatientRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        errors.check_not_empty("name.first", &self.name.first);
+        errors.check_not_empty("name.surname", &self.name.surname);
+        errors.check_country_code("address.country_region", &self.address.country_region);
+        errors.check_birth_date(
+            "birth_date",
+            self.birth_date.day,
+            self.birth_date.month,
+            self.birth_date.year,
+        );
+        errors.into_result()
+    }
+}
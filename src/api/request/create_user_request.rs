@@ -0,0 +1,18 @@
+use crate::entities::user::Role;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateUserRequest {
+    /// A unique username
+    #[schema(example = "jdoe")]
+    pub username: String,
+
+    /// The initial password for the user
+    #[schema(example = "Correct-Horse-Battery-9")]
+    pub password: String,
+
+    /// The access level for the user
+    #[schema(example = "clinician")]
+    pub role: Role,
+}
@@ -1,8 +1,10 @@
-use serde::Deserialize;
+use crate::api::redact::MASK;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use utoipa::ToSchema;
 
 //#[allow(dead_code)]
-#[derive(Deserialize, ToSchema, std::fmt::Debug)]
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct LoginRequest {
     /// Your username
     #[schema(default = "admin")]
@@ -11,3 +13,13 @@ pub struct LoginRequest {
     #[schema(default = "apidocpass")]
     pub password: String,
 }
+
+// Written out so that the password can't end up in a log through `{:?}`
+impl fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("username", &self.username)
+            .field("password", &MASK)
+            .finish()
+    }
+}
@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct LogoutRequest {
+    /// The refresh token from your last login or refresh, which is revoked along with every
+    /// token issued from the same login
+    #[schema(example = "5f2b8c0e4a7d4e1b9c3f6a8d2e7b1c4f5f2b8c0e4a7d4e1b9c3f6a8d2e7b1c4f")]
+    pub refresh_token: String,
+}
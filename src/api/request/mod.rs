@@ -1,3 +1,12 @@
+pub mod change_password_request;
+pub mod create_api_key_request;
 pub mod create_patient_request;
+pub mod create_user_request;
 pub mod login_request;
+pub mod logout_request;
+pub mod mfa_login_request;
+pub mod refresh_token_request;
+pub mod reset_password_request;
+pub mod totp_code_request;
 pub mod update_patient_request;
+pub mod validation;
@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetPasswordRequest {
+    /// The new password for the user
+    #[schema(example = "Correct-Horse-Battery-9")]
+    pub password: String,
+}
@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct TotpCodeRequest {
+    /// The current code from your authenticator app, or an unused recovery code where accepted
+    #[schema(example = "492039")]
+    pub code: String,
+}
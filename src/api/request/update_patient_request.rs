@@ -1,3 +1,4 @@
+use super::validation::{Validate, ValidationErrors};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -76,3 +77,28 @@ pub struct UpdatePatientRequestOas {
     pub address: Option<Address>,
 }
 
+
+impl Validate for UpdatePatientRequest {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Some(name) = &self.name {
+            if name.first.is_some() {
+                errors.add("name.first", "is immutable");
+            }
+            if name.surname.is_some() {
+                errors.add("name.surname", "is immutable");
+            }
+        }
+        if self.birthdate.is_some() {
+            errors.add("birthdate", "is immutable");
+        }
+        if let Some(country_region) = self
+            .address
+            .as_ref()
+            .and_then(|address| address.country_region.as_deref())
+        {
+            errors.check_country_code("address.country_region", country_region);
+        }
+        errors.into_result()
+    }
+}
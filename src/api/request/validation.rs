@@ -0,0 +1,102 @@
+use crate::api::response::error::FieldError;
+use chrono::{Datelike, Months, NaiveDate, Utc};
+
+/// The oldest age accepted for a patient
+pub const MAX_AGE: u32 = 150;
+
+/// The officially assigned ISO 3166-1 alpha-2 country codes, sorted for binary search
+const COUNTRY_CODES: &[&str] = &[
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS",
+    "BT", "BV", "BW", "BY", "BZ", "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN",
+    "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ", "DE", "DJ", "DK", "DM", "DO", "DZ", "EC", "EE",
+    "EG", "EH", "ER", "ES", "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA", "GB", "GD", "GE", "GF",
+    "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK", "HM",
+    "HN", "HR", "HT", "HU", "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM",
+    "JO", "JP", "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC",
+    "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY", "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK",
+    "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ", "NA",
+    "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ", "OM", "PA", "PE", "PF", "PG",
+    "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA", "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS",
+    "ST", "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO",
+    "TR", "TT", "TV", "TW", "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE", "VG", "VI",
+    "VN", "VU", "WF", "WS", "YE", "YT", "ZA", "ZM", "ZW",
+];
+
+/// A request body that can check its own values once it has deserialized
+///
+/// `ValidJson` calls this and rejects the request with a 422 listing every failing field.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// Collects the failures found while validating a request body
+#[derive(Debug, Default)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Requires a value with something besides whitespace
+    pub fn check_not_empty(&mut self, field: &str, value: &str) {
+        if value.trim().is_empty() {
+            self.add(field, "must not be empty");
+        }
+    }
+
+    /// Requires an ISO 3166-1 alpha-2 code, such as "US"
+    pub fn check_country_code(&mut self, field: &str, value: &str) {
+        if COUNTRY_CODES.binary_search(&value).is_err() {
+            self.add(
+                field,
+                "must be an ISO 3166-1 alpha-2 country code, such as \"US\"",
+            );
+        }
+    }
+
+    /// Requires a calendar date that isn't in the future or more than `MAX_AGE` years ago
+    ///
+    /// `field` is the path of the object that holds `day`, `month` and `year`.
+    pub fn check_birth_date(&mut self, field: &str, day: i32, month: i32, year: i32) {
+        if !(1..=12).contains(&month) {
+            self.add(format!("{field}.month"), "must be from 1 to 12");
+            return;
+        }
+        let Some(first) = NaiveDate::from_ymd_opt(year, month as u32, 1) else {
+            self.add(format!("{field}.year"), "is out of range");
+            return;
+        };
+        let Some(date) = u32::try_from(day).ok().and_then(|day| first.with_day(day)) else {
+            let last = first
+                .checked_add_months(Months::new(1))
+                .and_then(|next| next.pred_opt())
+                .map_or(31, |last| last.day());
+            self.add(format!("{field}.day"), format!("must be from 1 to {last}"));
+            return;
+        };
+
+        let today = Utc::now().date_naive();
+        if date > today {
+            self.add(field, "must not be in the future");
+        } else if today
+            .checked_sub_months(Months::new(MAX_AGE * 12))
+            .is_some_and(|earliest| date < earliest)
+        {
+            self.add(field, format!("must be within the last {MAX_AGE} years"));
+        }
+    }
+}
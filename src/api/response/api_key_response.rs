@@ -0,0 +1,68 @@
+use crate::entities::api_key::{self, Scope};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiKey {
+    #[schema(example = 3)]
+    pub id: i32,
+
+    #[schema(example = "lab-results-import")]
+    pub name: String,
+
+    /// The first characters of the key, to help identify it
+    #[schema(example = "doc_1f0c9a2b")]
+    pub prefix: String,
+
+    #[schema(example = json!(["patients:read", "patients:write"]))]
+    pub scopes: Vec<Scope>,
+
+    /// The user who created the key
+    #[schema(example = "admin")]
+    pub created_by: String,
+
+    /// A system-generated, RFC3339-formatted UTC timestamp
+    #[schema(example = "2025-05-23T09:42:15.630391+00:00")]
+    pub created_at: String,
+
+    /// An RFC3339-formatted UTC timestamp, or null if the key doesn't expire
+    #[schema(example = "2026-01-01T00:00:00+00:00")]
+    pub expires_at: Option<String>,
+
+    /// An RFC3339-formatted UTC timestamp, accurate to about a minute
+    #[schema(example = "2025-05-24T17:03:51.102754+00:00")]
+    pub last_used_at: Option<String>,
+
+    /// Revoked keys are no longer accepted
+    #[schema(example = false)]
+    pub revoked: bool,
+}
+
+impl From<api_key::Model> for ApiKey {
+    fn from(model: api_key::Model) -> Self {
+        Self {
+            id: model.id,
+            scopes: model.scopes(),
+            name: model.name,
+            prefix: model.prefix,
+            created_by: model.created_by,
+            created_at: model.created_at.to_rfc3339(),
+            expires_at: model.expires_at.map(|time| time.to_rfc3339()),
+            last_used_at: model.last_used_at.map(|time| time.to_rfc3339()),
+            revoked: model.revoked_at.is_some(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateApiKeyResponse {
+    /// The API key, which is only ever shown in this response
+    #[schema(example = "doc_1f0c9a2b5e7d4c3a8b6f0e1d2c3b4a5968778695a4b3c2d1e0f9a8b7c6d5e4f3a2b1")]
+    pub key: String,
+    pub data: ApiKey,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListApiKeysResponse {
+    pub api_keys: Vec<ApiKey>,
+}
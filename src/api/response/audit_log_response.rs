@@ -0,0 +1,71 @@
+use crate::api::audit;
+use crate::entities::audit_log::{self, Outcome};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A record of one request
+#[derive(Serialize, ToSchema)]
+pub struct AuditEntry {
+    #[schema(example = 1042)]
+    pub id: i32,
+
+    #[schema(example = "2025-04-01T04:11:48.630391Z")]
+    pub occurred_at: String,
+
+    /// The user or API key that made the request, or "anonymous"
+    #[schema(example = "jdoe")]
+    pub actor: String,
+
+    /// The HTTP method and route
+    #[schema(example = "GET /v1/patient/:patient_id")]
+    pub action: String,
+
+    #[schema(example = "3973ebb8-11e5-4725-93b7-3b752caad60f")]
+    pub patient_id: Option<String>,
+
+    /// Sent back to the client in the X-Request-Id header
+    #[schema(example = "6f1c6a0e-4b0e-4f4e-9f57-0d6c1c2a9b8e")]
+    pub request_id: String,
+
+    #[schema(example = "203.0.113.7")]
+    pub client_ip: Option<String>,
+
+    #[schema(example = 200)]
+    pub status_code: u16,
+
+    pub outcome: Outcome,
+
+    /// The `hash` of the record before this one
+    pub prev_hash: String,
+
+    /// SHA-256 over this record's fields and `prev_hash`
+    pub hash: String,
+}
+
+impl From<audit_log::Model> for AuditEntry {
+    fn from(entry: audit_log::Model) -> Self {
+        AuditEntry {
+            id: entry.id,
+            occurred_at: audit::timestamp(&entry.occurred_at),
+            actor: entry.actor,
+            action: entry.action,
+            patient_id: entry.patient_id.map(|patient_id| patient_id.to_string()),
+            request_id: entry.request_id,
+            client_ip: entry.client_ip,
+            status_code: entry.status_code as u16,
+            outcome: entry.outcome,
+            prev_hash: entry.prev_hash,
+            hash: entry.hash,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditLogResponse {
+    /// Newest first
+    pub entries: Vec<AuditEntry>,
+
+    /// Pass as `before` to get the next, older page; absent on the last page
+    #[schema(example = 1000)]
+    pub next_before: Option<i32>,
+}
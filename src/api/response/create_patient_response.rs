@@ -1,3 +1,5 @@
+use crate::entities::patient::PatientRecord;
+use crate::entities::patient_history;
 use serde::Serialize;
 use utoipa::ToSchema;
 
@@ -64,6 +66,61 @@ pub struct Patient {
     pub birthdate: BirthdateData,
 }
 
+impl From<PatientRecord> for Patient {
+    fn from(record: PatientRecord) -> Self {
+        Patient {
+            patient_id: record.patient_id.into(),
+            created_at: record.created_at.to_rfc3339(),
+            name: NameData {
+                first: record.first,
+                middle: record.middle,
+                surname: record.surname,
+            },
+            address: AddressData {
+                address_lines: record.address_lines,
+                sublocality: record.sublocality,
+                locality: record.locality,
+                administrative_area: record.administrative_area,
+                postal_code: record.postal_code,
+                country_region: record.country_region,
+            },
+            birthdate: BirthdateData {
+                day: record.day,
+                month: record.month,
+                year: record.year,
+            },
+        }
+    }
+}
+
+/// Takes an entry opened by `FieldEncryption::open_history`, which has its birth day and month set
+impl From<patient_history::Model> for Patient {
+    fn from(entry: patient_history::Model) -> Self {
+        Patient {
+            patient_id: entry.patient_id.into(),
+            created_at: entry.created_at.to_rfc3339(),
+            name: NameData {
+                first: entry.first,
+                middle: entry.middle,
+                surname: entry.surname,
+            },
+            address: AddressData {
+                address_lines: entry.address_lines,
+                sublocality: entry.sublocality,
+                locality: entry.locality,
+                administrative_area: entry.administrative_area,
+                postal_code: entry.postal_code,
+                country_region: entry.country_region,
+            },
+            birthdate: BirthdateData {
+                day: entry.day.unwrap_or_default(),
+                month: entry.month.unwrap_or_default(),
+                year: entry.year,
+            },
+        }
+    }
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct CreatePatientResponse {
     pub data: Patient,
@@ -0,0 +1,155 @@
+use crate::api::duplicates::{Candidate, DuplicatePair};
+use crate::api::response::error::ErrorResponse;
+use axum::http::StatusCode;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// An existing patient that the new patient may duplicate
+#[derive(Serialize, ToSchema)]
+pub struct DuplicateCandidate {
+    #[schema(example = "3973ebb8-11e5-4725-93b7-3b752caad60f")]
+    pub patient_id: String,
+
+    /// How likely the patients are to be the same person, from 0 to 1
+    #[schema(example = 0.85)]
+    pub score: f32,
+
+    /// The mean trigram similarity of the first names and of the surnames, from 0 to 1
+    #[schema(example = 1.0)]
+    pub name_similarity: f32,
+
+    #[schema(example = true)]
+    pub same_birth_date: bool,
+
+    #[schema(example = false)]
+    pub same_postal_code: bool,
+}
+
+impl From<Candidate> for DuplicateCandidate {
+    fn from(candidate: Candidate) -> Self {
+        DuplicateCandidate {
+            patient_id: candidate.patient_id.to_string(),
+            score: candidate.score.total,
+            name_similarity: candidate.score.name_similarity,
+            same_birth_date: candidate.score.same_birth_date,
+            same_postal_code: candidate.score.same_postal_code,
+        }
+    }
+}
+
+/// The error returned when a new patient looks like an existing one
+#[derive(Serialize, ToSchema)]
+pub struct DuplicatePatientResponse {
+    /// The HTTP status code value
+    #[schema(example = "409")]
+    pub status_code: u16,
+
+    /// The HTTP status code reason
+    #[schema(example = "Conflict")]
+    pub reason: &'static str,
+
+    /// A contextual message regarding the error
+    #[schema(
+        example = "The patient may already exist; check the candidates, or send force=true to create it anyway"
+    )]
+    pub message: String,
+
+    /// How many existing patients it may duplicate
+    #[schema(example = 1)]
+    pub count: usize,
+
+    /// The existing patients it may duplicate, best match first; empty unless the caller may read
+    /// patients
+    pub candidates: Vec<DuplicateCandidate>,
+}
+
+impl DuplicatePatientResponse {
+    /// Lists the candidates if `disclosed`, or else only counts them
+    pub fn new(candidates: Vec<Candidate>, disclosed: bool) -> Self {
+        let code = StatusCode::CONFLICT;
+        let message = if disclosed {
+            "The patient may already exist; check the candidates, or send force=true to create it anyway"
+        } else {
+            "The patient may already exist; check with someone who can read patients, or send force=true to create it anyway"
+        };
+        DuplicatePatientResponse {
+            status_code: code.as_u16(),
+            reason: code.canonical_reason().unwrap_or_default(),
+            message: message.to_string(),
+            count: candidates.len(),
+            candidates: if disclosed {
+                candidates
+                    .into_iter()
+                    .map(DuplicateCandidate::from)
+                    .collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+/// The body of a 409 from `POST /v1/patient`
+///
+/// Documents both shapes: a likely duplicate gets its candidates, while a request whose
+/// idempotency key is still being processed gets the plain error.
+#[derive(Serialize, ToSchema)]
+#[allow(unused)]
+#[serde(untagged)]
+pub enum CreateConflictResponse {
+    Duplicate(DuplicatePatientResponse),
+    InProgress(ErrorResponse),
+}
+
+/// Two existing patients that may be the same person
+#[derive(Serialize, ToSchema)]
+pub struct DuplicatePairData {
+    /// The patient created first
+    #[schema(example = "3973ebb8-11e5-4725-93b7-3b752caad60f")]
+    pub patient_id: String,
+
+    /// The patient created later, which may duplicate the first
+    #[schema(example = "8d4c2b1e-5f0a-4c3e-9b7d-2a6f1e0c9d84")]
+    pub duplicate_id: String,
+
+    /// How likely the patients are to be the same person, from 0 to 1
+    #[schema(example = 0.85)]
+    pub score: f32,
+
+    /// The mean trigram similarity of the first names and of the surnames, from 0 to 1
+    #[schema(example = 1.0)]
+    pub name_similarity: f32,
+
+    #[schema(example = true)]
+    pub same_birth_date: bool,
+
+    #[schema(example = false)]
+    pub same_postal_code: bool,
+}
+
+impl From<DuplicatePair> for DuplicatePairData {
+    fn from(pair: DuplicatePair) -> Self {
+        DuplicatePairData {
+            patient_id: pair.patient_id.to_string(),
+            duplicate_id: pair.duplicate_id.to_string(),
+            score: pair.score.total,
+            name_similarity: pair.score.name_similarity,
+            same_birth_date: pair.score.same_birth_date,
+            same_postal_code: pair.score.same_postal_code,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DuplicatesReportResponse {
+    /// The lowest score reported
+    #[schema(example = 0.8)]
+    pub min_score: f32,
+
+    /// The number of pairs found, which may be more than were returned
+    #[schema(example = 1)]
+    pub total: u64,
+
+    /// The pairs found, best match first
+    pub pairs: Vec<DuplicatePairData>,
+}
@@ -21,6 +21,48 @@ pub struct ErrorResponse {
     pub message: String,
 }
 
+/// A field that failed validation
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    /// The path to the field in the request body
+    #[schema(example = "birth_date.month")]
+    pub field: String,
+
+    /// Why the value is invalid
+    #[schema(example = "must be from 1 to 12")]
+    pub message: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ValidationErrorResponse {
+    /// The HTTP status code value
+    #[schema(example = "422")]
+    pub status_code: u16,
+
+    /// The HTTP status code reason
+    #[schema(example = "Unprocessable Entity")]
+    pub reason: &'static str,
+
+    /// A summary of the failures
+    #[schema(example = "The request body has 1 invalid field")]
+    pub message: String,
+
+    /// Every field that failed validation
+    pub errors: Vec<FieldError>,
+}
+
+/// The body of a 422 from a route that validates its request body
+///
+/// Documents both shapes: a body that parsed but has invalid fields lists them, while a body that
+/// doesn't match the schema, or a request rejected for another reason, gets the plain error.
+#[derive(Serialize, ToSchema, Debug)]
+#[allow(unused)]
+#[serde(untagged)]
+pub enum UnprocessableResponse {
+    Validation(ValidationErrorResponse),
+    Error(ErrorResponse),
+}
+
 //#[derive(Serialize, ToSchema)]
 //pub enum Status {
 //    Success,
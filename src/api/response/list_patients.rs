@@ -1,3 +1,4 @@
+use crate::entities::patient::PatientRecord;
 use serde::Serialize;
 use utoipa::ToSchema;
 
@@ -62,9 +63,69 @@ pub struct Patient {
     pub name: NameData,
     pub address: AddressData,
     pub birthdate: BirthdateData,
+
+    /// Whether the record is active; only deleted records, listed for admins, are inactive
+    #[schema(example = "true")]
+    pub active: bool,
+
+    /// When the record was deleted, an RFC3339-formatted UTC timestamp; absent for active records
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "2025-04-02T09:30:00.000000+00:00")]
+    pub deleted_at: Option<String>,
+
+    /// Who deleted the record; absent for active records
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "admin")]
+    pub deleted_by: Option<String>,
+
+    /// How closely the patient's names match the name filters, from 0 to 1; absent for exact
+    /// matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "0.8")]
+    pub score: Option<f32>,
+}
+
+impl From<PatientRecord> for Patient {
+    fn from(record: PatientRecord) -> Self {
+        Patient {
+            patient_id: record.patient_id.into(),
+            created_at: record.created_at.to_rfc3339(),
+            name: NameData {
+                first: record.first,
+                middle: record.middle,
+                surname: record.surname,
+            },
+            address: AddressData {
+                address_lines: record.address_lines,
+                sublocality: record.sublocality,
+                locality: record.locality,
+                administrative_area: record.administrative_area,
+                postal_code: record.postal_code,
+                country_region: record.country_region,
+            },
+            birthdate: BirthdateData {
+                day: record.day,
+                month: record.month,
+                year: record.year,
+            },
+            active: record.active_flag,
+            deleted_at: record.deleted_at.map(|deleted_at| deleted_at.to_rfc3339()),
+            deleted_by: record.deleted_by,
+            score: None,
+        }
+    }
 }
 
 #[derive(Serialize, ToSchema)]
 pub struct ListPatientsResponse {
     pub patients: Vec<Patient>,
+
+    /// Pass as `cursor` to fetch the next page; null on the last page
+    #[schema(example = "eyJvcmRlciI6ImFzYyIsInNvcnQiOiJzdXJuYW1lIiwic3VybmFtZSI6IlNtaXRoIiwiaWQiOjQyfQ")]
+    pub next_cursor: Option<String>,
+
+    /// The number of matching patients across all pages, present when `include_total` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "128")]
+    pub total: Option<u64>,
 }
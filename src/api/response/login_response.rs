@@ -11,5 +11,9 @@ use utoipa::ToSchema;
 #[derive(Serialize, ToSchema)]
 pub struct LoginResponse {
     //pub timestamp: String,
+    /// A short-lived access token to supply as a bearer token
     pub token: String,
+
+    /// A single-use token to exchange for a new token pair at `/token/refresh`
+    pub refresh_token: String,
 }
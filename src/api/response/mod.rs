@@ -1,13 +1,33 @@
+pub mod api_key_response;
+pub mod audit_log_response;
 pub mod create_patient_response;
+pub mod duplicates;
 pub mod error;
 pub mod list_patients;
 pub mod login_response;
+pub mod mfa_response;
+pub mod patient_history;
+pub mod purge_response;
+pub mod user_response;
 
 // Struct to store token claims for processing
+use crate::entities::user::Role;
 use serde::{Deserialize, Serialize};
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
+    pub jti: uuid::Uuid,
     pub sub: String,
-    pub iat: usize,
+    pub role: Role,
+    /// Issue time in seconds since the epoch, to the microsecond
+    pub iat: f64,
     pub exp: usize,
 }
+
+impl TokenClaims {
+    /// Whether the caller signed in through the external identity provider, and so has no local
+    /// account, even if a local user shares its provider username
+    pub fn is_federated(&self) -> bool {
+        self.sub
+            .starts_with(crate::api::auth::oidc::PRINCIPAL_PREFIX)
+    }
+}
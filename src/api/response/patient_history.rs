@@ -0,0 +1,45 @@
+use crate::api::response::create_patient_response::Patient;
+use crate::entities::patient_history::{self, Action};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A patient as it was after one change
+#[derive(Serialize, ToSchema)]
+pub struct PatientHistoryEntry {
+    /// The patient's version, and ETag, after the change
+    #[schema(example = 2)]
+    pub version: i32,
+
+    pub action: Action,
+
+    /// The user or API key that made the change
+    #[schema(example = "jdoe")]
+    pub changed_by: String,
+
+    #[schema(example = "2025-04-01T04:11:48.630391+00:00")]
+    pub changed_at: String,
+
+    /// False once the patient has been deleted
+    pub active: bool,
+
+    pub patient: Patient,
+}
+
+impl From<patient_history::Model> for PatientHistoryEntry {
+    fn from(entry: patient_history::Model) -> Self {
+        PatientHistoryEntry {
+            version: entry.version,
+            action: entry.action,
+            changed_by: entry.changed_by.clone(),
+            changed_at: entry.changed_at.to_rfc3339(),
+            active: entry.active_flag,
+            patient: Patient::from(entry),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PatientHistoryResponse {
+    /// Every recorded change, oldest first
+    pub history: Vec<PatientHistoryEntry>,
+}
@@ -0,0 +1,38 @@
+use crate::api::purge::Erasure;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// What a purge removed, or would remove on a dry run
+#[derive(Serialize, ToSchema)]
+pub struct PurgeResponse {
+    #[schema(example = "3973ebb8-11e5-4725-93b7-3b752caad60f")]
+    pub patient_id: String,
+
+    /// Whether this was a dry run, which changed nothing
+    #[schema(example = "false")]
+    pub dry_run: bool,
+
+    /// When the patient was deleted, if that was recorded
+    #[schema(example = "2025-04-02T09:30:00+00:00")]
+    pub deleted_at: Option<String>,
+
+    /// History entries removed with the patient
+    #[schema(example = 4)]
+    pub history_entries: u64,
+
+    /// Idempotency keys of the request that created the patient, removed with it
+    #[schema(example = 1)]
+    pub idempotency_keys: u64,
+}
+
+impl PurgeResponse {
+    pub fn new(erasure: Erasure, dry_run: bool) -> Self {
+        PurgeResponse {
+            patient_id: erasure.patient_id.to_string(),
+            dry_run,
+            deleted_at: erasure.deleted_at.map(|deleted_at| deleted_at.to_rfc3339()),
+            history_entries: erasure.history_entries,
+            idempotency_keys: erasure.idempotency_keys,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::entities::user::{self, Role};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct User {
+    #[schema(example = "jdoe")]
+    pub username: String,
+
+    #[schema(example = "clinician")]
+    pub role: Role,
+
+    /// Disabled users cannot log in or refresh tokens
+    #[schema(example = true)]
+    pub active: bool,
+
+    /// Whether logins require a second factor
+    #[schema(example = false)]
+    pub mfa_enabled: bool,
+
+    /// A system-generated, RFC3339-formatted UTC timestamp
+    #[schema(example = "2025-04-01T04:11:48.630391+00:00")]
+    pub created_at: String,
+}
+
+impl From<user::Model> for User {
+    fn from(model: user::Model) -> Self {
+        Self {
+            username: model.username,
+            role: model.role,
+            active: model.active_flag,
+            mfa_enabled: model.totp_enabled_at.is_some(),
+            created_at: model.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserResponse {
+    pub data: User,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListUsersResponse {
+    pub users: Vec<User>,
+}
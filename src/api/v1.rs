@@ -1,19 +1,245 @@
 use super::handlers;
+use crate::api::middleware::{audit, rbac};
 use crate::state::ApplicationState;
-use axum::routing::{delete, get, patch, post};
+use axum::routing::{delete, get, patch, post, put};
 use axum::{middleware, Router};
 use std::sync::Arc;
 
+// Route layers run in reverse order of declaration, so each protected
+// route declares `rbac::authorize` before `jwt::auth` to check the
+// role only after the token has been decoded. The audit layer wraps every
+// route, so it sees the response after authentication and authorization
 pub fn configure(state: Arc<ApplicationState>) -> Router {
     Router::new()
         .route(
             "/login",
             post(handlers::login_handler::login).with_state(state.clone()),
         )
+        .route(
+            "/login/mfa",
+            post(handlers::login_mfa_handler::login_mfa).with_state(state.clone()),
+        )
+        .route(
+            "/token/refresh",
+            post(handlers::refresh_token_handler::refresh).with_state(state.clone()),
+        )
+        .route(
+            "/logout",
+            post(handlers::logout_handler::logout)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::USER,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users",
+            post(handlers::create_user_handler::create)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users",
+            get(handlers::list_users_handler::list)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users/:username",
+            get(handlers::get_user_handler::get_user)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users/:username",
+            delete(handlers::delete_user_handler::delete)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users/:username/disable",
+            post(handlers::disable_user_handler::disable)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users/:username/enable",
+            post(handlers::disable_user_handler::enable)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users/:username/password",
+            put(handlers::reset_password_handler::reset_password)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/me/password",
+            put(handlers::change_password_handler::change)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::LOCAL_USER,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/me/mfa/totp",
+            post(handlers::totp_handler::enrol)
+                .delete(handlers::totp_handler::disable)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::LOCAL_USER,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/me/mfa/totp/confirm",
+            post(handlers::totp_handler::confirm)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::LOCAL_USER,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/users/:username/revoke-tokens",
+            post(handlers::revoke_tokens_handler::revoke_tokens)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/api-keys",
+            post(handlers::create_api_key_handler::create)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/api-keys",
+            get(handlers::list_api_keys_handler::list)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/api-keys/:api_key_id",
+            delete(handlers::revoke_api_key_handler::revoke)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/audit",
+            get(handlers::list_audit_log_handler::list)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
         .route(
             "/patient",
             post(handlers::create_patient_handler::create)
                 .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::PATIENT_WRITE,
+                    rbac::authorize,
+                ))
                 .route_layer(middleware::from_fn_with_state(
                     state.clone(),
                     crate::api::middleware::jwt::auth,
@@ -23,6 +249,23 @@ pub fn configure(state: Arc<ApplicationState>) -> Router {
             "/patient/:patient_id",
             get(handlers::get_patient_handler::get_patient)
                 .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::PATIENT_READ,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/patient/:patient_id/history",
+            get(handlers::patient_history_handler::history)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::PATIENT_READ,
+                    rbac::authorize,
+                ))
                 .route_layer(middleware::from_fn_with_state(
                     state.clone(),
                     crate::api::middleware::jwt::auth,
@@ -32,6 +275,23 @@ pub fn configure(state: Arc<ApplicationState>) -> Router {
             "/patient",
             get(handlers::list_patients_handler::list)
                 .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::PATIENT_READ,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/patient/duplicates",
+            get(handlers::list_duplicates_handler::list)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
                 .route_layer(middleware::from_fn_with_state(
                     state.clone(),
                     crate::api::middleware::jwt::auth,
@@ -41,6 +301,10 @@ pub fn configure(state: Arc<ApplicationState>) -> Router {
             "/patient/:patient_id",
             patch(handlers::update_patient_handler::update)
                 .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::PATIENT_WRITE,
+                    rbac::authorize,
+                ))
                 .route_layer(middleware::from_fn_with_state(
                     state.clone(),
                     crate::api::middleware::jwt::auth,
@@ -50,17 +314,50 @@ pub fn configure(state: Arc<ApplicationState>) -> Router {
             "/patient/:patient_id",
             delete(handlers::delete_patient_handler::delete)
                 .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::PATIENT_DELETE,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .route(
+            "/patient/:patient_id/purge",
+            post(handlers::purge_patient_handler::purge)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::ADMIN,
+                    rbac::authorize,
+                ))
                 .route_layer(middleware::from_fn_with_state(
                     state.clone(),
                     crate::api::middleware::jwt::auth,
                 )),
         )
+        .route(
+            "/patient/:patient_id/restore",
+            post(handlers::delete_patient_handler::restore)
+                .with_state(state.clone())
+                .route_layer(middleware::from_fn_with_state(
+                    rbac::PATIENT_DELETE,
+                    rbac::authorize,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::api::middleware::jwt::auth,
+                )),
+        )
+        .layer(middleware::from_fn_with_state(state, audit::record))
 }
 
 // OAS doc
 use utoipa::{
     openapi::
         security::{
+            ApiKey,
+            ApiKeyValue,
             HttpAuthScheme, 
             HttpBuilder, 
             SecurityScheme
@@ -73,16 +370,47 @@ use utoipa::{
 #[openapi(
     paths(
         handlers::login_handler::login,
+        handlers::login_mfa_handler::login_mfa,
+        handlers::refresh_token_handler::refresh,
+        handlers::logout_handler::logout,
+        handlers::revoke_tokens_handler::revoke_tokens,
+        handlers::change_password_handler::change,
+        handlers::totp_handler::enrol,
+        handlers::totp_handler::confirm,
+        handlers::totp_handler::disable,
+        handlers::create_user_handler::create,
+        handlers::list_users_handler::list,
+        handlers::get_user_handler::get_user,
+        handlers::delete_user_handler::delete,
+        handlers::disable_user_handler::disable,
+        handlers::disable_user_handler::enable,
+        handlers::reset_password_handler::reset_password,
+        handlers::create_api_key_handler::create,
+        handlers::list_api_keys_handler::list,
+        handlers::revoke_api_key_handler::revoke,
+        handlers::list_audit_log_handler::list,
         handlers::create_patient_handler::create,
         handlers::get_patient_handler::get_patient,
+        handlers::patient_history_handler::history,
         handlers::list_patients_handler::list,
+        handlers::list_duplicates_handler::list,
         handlers::update_patient_handler::update,
         handlers::delete_patient_handler::delete,
+        handlers::delete_patient_handler::restore,
+        handlers::purge_patient_handler::purge,
     ),
     components(
         schemas(
             // Requests
             crate::api::request::login_request::LoginRequest,
+            crate::api::request::mfa_login_request::MfaLoginRequest,
+            crate::api::request::totp_code_request::TotpCodeRequest,
+            crate::api::request::logout_request::LogoutRequest,
+            crate::api::request::create_user_request::CreateUserRequest,
+            crate::api::request::reset_password_request::ResetPasswordRequest,
+            crate::api::request::change_password_request::ChangePasswordRequest,
+            crate::api::request::refresh_token_request::RefreshTokenRequest,
+            crate::api::request::create_api_key_request::CreateApiKeyRequest,
             crate::api::request::create_patient_request::AddressCreate,
             crate::api::request::create_patient_request::BirthDateCreate,
             crate::api::request::create_patient_request::NameCreate,
@@ -95,6 +423,9 @@ use utoipa::{
 
             // Responses
             crate::api::response::login_response::LoginResponse,
+            crate::api::response::mfa_response::MfaChallengeResponse,
+            crate::api::response::mfa_response::TotpEnrolmentResponse,
+            crate::api::response::mfa_response::RecoveryCodesResponse,
             crate::api::response::create_patient_response::AddressData,
             crate::api::response::create_patient_response::BirthdateData,
             crate::api::response::create_patient_response::NameData,
@@ -105,7 +436,32 @@ use utoipa::{
             crate::api::response::list_patients::NameData,
             crate::api::response::list_patients::Patient,
             crate::api::response::list_patients::ListPatientsResponse,
+            crate::api::response::patient_history::PatientHistoryEntry,
+            crate::api::response::patient_history::PatientHistoryResponse,
+            crate::api::response::purge_response::PurgeResponse,
+            crate::api::response::duplicates::DuplicateCandidate,
+            crate::api::response::duplicates::DuplicatePatientResponse,
+            crate::api::response::duplicates::CreateConflictResponse,
+            crate::api::response::duplicates::DuplicatePairData,
+            crate::api::response::duplicates::DuplicatesReportResponse,
+            crate::api::response::user_response::User,
+            crate::api::response::user_response::UserResponse,
+            crate::api::response::user_response::ListUsersResponse,
+            crate::api::response::api_key_response::ApiKey,
+            crate::api::response::api_key_response::CreateApiKeyResponse,
+            crate::api::response::api_key_response::ListApiKeysResponse,
+            crate::api::response::audit_log_response::AuditEntry,
+            crate::api::response::audit_log_response::AuditLogResponse,
             crate::api::response::error::ErrorResponse,
+            crate::api::response::error::FieldError,
+            crate::api::response::error::ValidationErrorResponse,
+            crate::api::response::error::UnprocessableResponse,
+
+            // Entities
+            crate::entities::user::Role,
+            crate::entities::api_key::Scope,
+            crate::entities::audit_log::Outcome,
+            crate::entities::patient_history::Action,
         ),
     ),
     modifiers(&SecurityAddon),
@@ -132,6 +488,10 @@ impl Modify for SecurityAddon {
                     .bearer_format("JWT")
                     .build(),
             ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
         )
     }
 }
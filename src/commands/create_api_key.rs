@@ -0,0 +1,71 @@
+use crate::api::auth::api_keys::create_api_key;
+use crate::settings::Settings;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::entities::api_key::Scope;
+
+use sea_orm::Database;
+
+pub fn configure() -> Command {
+    Command::new("createapikey")
+        .about("Create an API key for a machine client, which sends it in the X-Api-Key header; The key is printed once and only its digest is stored")
+        .arg(
+            Arg::new("name")
+                .short('n')
+                .long("name")
+                .value_name("NAME")
+                .help("Label for the client that holds the key")
+                .required(true),
+        )
+        .arg(
+            Arg::new("scope")
+                .short('s')
+                .long("scope")
+                .value_name("SCOPE")
+                .help("Operation the key may perform; repeat for several scopes")
+                .value_parser(["patients:read", "patients:write", "patients:delete"])
+                .action(ArgAction::Append)
+                .required(true),
+        )
+        .arg(
+            Arg::new("expires-in-days")
+                .short('e')
+                .long("expires-in-days")
+                .value_name("DAYS")
+                .help("Days until the key expires; the key never expires if omitted")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+}
+
+pub fn handle(matches: &ArgMatches, settings: &Settings) -> anyhow::Result<()> {
+    if let Some(matches) = matches.subcommand_matches("createapikey") {
+        let name = matches.get_one::<String>("name").unwrap();
+        let scopes = matches
+            .get_many::<String>("scope")
+            .unwrap()
+            .map(|scope| scope.parse())
+            .collect::<anyhow::Result<Vec<Scope>>>()?;
+        let expires_at = matches
+            .get_one::<u32>("expires-in-days")
+            .map(|days| chrono::Utc::now() + chrono::Duration::days(i64::from(*days)));
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let db_url = settings.database.url.clone().unwrap_or("".to_string());
+                let conn: sea_orm::DatabaseConnection = Database::connect(db_url)
+                    .await
+                    .expect("Database connection failed");
+
+                let (_, key) = create_api_key(&conn, name, &scopes, expires_at, "cli").await?;
+                println!("API key created; store it now, as it cannot be shown again:");
+                println!("{key}");
+
+                Ok::<(), anyhow::Error>(())
+            })?;
+    }
+
+    Ok(())
+}
@@ -1,22 +1,20 @@
+use crate::api::auth::password::{check_policy, encrypt_password};
+use crate::api::auth::reserved_prefix;
 use crate::settings::Settings;
-use anyhow::anyhow;
 use clap::{Arg, ArgMatches, Command};
 use serde_json::json;
 
 use crate::entities;
+use crate::entities::user::Role;
 
 use sea_orm::ColumnTrait;
 use sea_orm::QueryFilter;
 use sea_orm::{ActiveModelTrait, Database, EntityTrait};
 //use serde_json::json;
 
-use argon2::Argon2;
-use password_hash::rand_core::OsRng;
-use password_hash::{PasswordHasher, SaltString};
-
 pub fn configure() -> Command {
     Command::new("createuser")
-        .about("Supply optional values to create a new user; If you supply no values the service attempts to use the documented default values; The system only allows unique usernames, including the default \"admin\" username value")
+        .about("Supply a password and optional values to create a new user; If you supply no optional values the service attempts to use the documented default values; The password must meet the configured password policy; The system only allows unique usernames, including the default \"admin\" username value")
         .arg(
             Arg::new("username")
                 .short('u')
@@ -31,7 +29,16 @@ pub fn configure() -> Command {
                 .long("password")
                 .value_name("PASSWORD")
                 .help("Password for new user")
-                .default_value("apidocpass"),
+                .required(true),
+        )
+        .arg(
+            Arg::new("role")
+                .short('r')
+                .long("role")
+                .value_name("ROLE")
+                .help("Access level for new user")
+                .value_parser(["admin", "clinician", "front_desk", "read_only"])
+                .default_value("admin"),
         )
 }
 
@@ -39,6 +46,15 @@ pub fn handle(matches: &ArgMatches, settings: &Settings) -> anyhow::Result<()> {
     if let Some(matches) = matches.subcommand_matches("createuser") {
         let username = matches.get_one::<String>("username").unwrap();
         let password = matches.get_one::<String>("password").unwrap();
+        let role: Role = matches.get_one::<String>("role").unwrap().parse()?;
+
+        if let Some(prefix) = reserved_prefix(username) {
+            anyhow::bail!("Usernames starting with \"{prefix}\" are reserved");
+        }
+
+        if let Err(err) = check_policy(&settings.password_policy, password) {
+            return Err(err.into_app_error().1);
+        }
 
         tokio::runtime::Builder::new_current_thread()
             .enable_all()
@@ -66,6 +82,7 @@ pub fn handle(matches: &ArgMatches, settings: &Settings) -> anyhow::Result<()> {
                 let admin_model = entities::user::ActiveModel::from_json(json!({
                     "username": username,
                     "password": encrypted_password,
+                    "role": role,
                 }))?;
 
                 // save() creates a new table entry if supplied
@@ -83,14 +100,3 @@ pub fn handle(matches: &ArgMatches, settings: &Settings) -> anyhow::Result<()> {
 
     Ok(())
 }
-
-fn encrypt_password(password: &str) -> anyhow::Result<String> {
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-
-    if let Ok(hash) = argon2.hash_password(password.as_bytes(), &salt) {
-        Ok(hash.to_string())
-    } else {
-        Err(anyhow!("Failed to hash password"))
-    }
-}
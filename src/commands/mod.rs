@@ -1,6 +1,10 @@
+mod audit;
 mod check;
+mod create_api_key;
 mod create_user;
 mod migrate;
+mod purge;
+mod rekey;
 mod serve;
 
 use crate::settings::Settings;
@@ -11,6 +15,10 @@ pub fn configure(command: Command) -> Command {
         .subcommand(serve::configure())
         .subcommand(migrate::configure())
         .subcommand(create_user::configure())
+        .subcommand(create_api_key::configure())
+        .subcommand(audit::configure())
+        .subcommand(rekey::configure())
+        .subcommand(purge::configure())
         .subcommand(check::configure())
 }
 
@@ -18,8 +26,12 @@ pub fn handle(matches: &ArgMatches, settings: &Settings) -> anyhow::Result<()> {
     serve::handle(matches, settings)?;
     migrate::handle(matches, settings)?;
     create_user::handle(matches, settings)?;
+    create_api_key::handle(matches, settings)?;
+    audit::handle(matches, settings)?;
+    rekey::handle(matches, settings)?;
+    purge::handle(matches, settings)?;
     //check::handle(matches, settings).await?;
-    if let Some(_) = matches.subcommand_matches("check") {
+    if matches.subcommand_matches("check").is_some() {
         let rt = tokio::runtime::Runtime::new()?;
         rt.block_on(async { check::handle(matches, settings).await })?;
     }
@@ -0,0 +1,105 @@
+use crate::api::purge::{self, Erasure, Purge};
+use crate::settings::Settings;
+use anyhow::bail;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use sea_orm::Database;
+use uuid::Uuid;
+
+/// Recorded as `purged_by` for patients purged by ID from the command line
+const PURGED_BY: &str = "cli";
+
+pub fn configure() -> Command {
+    Command::new("purge")
+        .about("Permanently remove deleted patients, their history, and the idempotency keys of the requests that created them, leaving only a tombstone; With no options, purges the patients past the configured retention period")
+        .arg(
+            Arg::new("patient")
+                .short('p')
+                .long("patient")
+                .value_name("PATIENT_ID")
+                .help("A deleted patient to purge; repeat for several patients")
+                .value_parser(|value: &str| value.parse::<Uuid>())
+                .action(ArgAction::Append)
+                .conflicts_with("inactive-days"),
+        )
+        .arg(
+            Arg::new("inactive-days")
+                .long("inactive-days")
+                .value_name("DAYS")
+                .help("Purge patients deleted more than this many days ago, instead of retention.purge_inactive_after_days")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Report what would be purged without purging it")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+pub fn handle(matches: &ArgMatches, settings: &Settings) -> anyhow::Result<()> {
+    let Some(matches) = matches.subcommand_matches("purge") else {
+        return Ok(());
+    };
+    let patients: Vec<Uuid> = matches
+        .get_many::<Uuid>("patient")
+        .map(|ids| ids.copied().collect())
+        .unwrap_or_default();
+    let days = matches
+        .get_one::<u32>("inactive-days")
+        .copied()
+        .or(settings.retention.purge_inactive_after_days);
+    if patients.is_empty() && days.is_none() {
+        bail!("Name patients with --patient, or set --inactive-days or retention.purge_inactive_after_days");
+    }
+    let dry_run = matches.get_flag("dry-run");
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async move {
+            let db_url = settings.database.url.clone().unwrap_or("".to_string());
+            let conn: sea_orm::DatabaseConnection = Database::connect(db_url)
+                .await
+                .expect("Database connection failed");
+
+            // Named patients take the place of the retention period
+            let mut erased = match days.filter(|_| patients.is_empty()) {
+                Some(days) => {
+                    purge::purge_past_retention(&conn, days, purge::RETENTION, dry_run).await?
+                }
+                None => Vec::new(),
+            };
+            for patient_id in patients {
+                match purge::purge(&conn, patient_id, PURGED_BY, dry_run).await? {
+                    Purge::Purged(erasure) => erased.push(erasure),
+                    Purge::NotFound => eprintln!("Patient {patient_id} not found"),
+                    Purge::AlreadyPurged => eprintln!("Patient {patient_id} was already purged"),
+                    Purge::Active => {
+                        eprintln!("Patient {patient_id} isn't deleted, so it was left alone")
+                    }
+                }
+            }
+
+            for erasure in &erased {
+                println!("{}", describe(erasure));
+            }
+            match dry_run {
+                true => println!("Would purge {} patients; nothing was changed", erased.len()),
+                false => println!("Purged {} patients", erased.len()),
+            }
+            Ok::<(), anyhow::Error>(())
+        })?;
+
+    Ok(())
+}
+
+fn describe(erasure: &Erasure) -> String {
+    let deleted_at = erasure
+        .deleted_at
+        .map_or_else(|| "at an unrecorded time".to_string(), |at| at.to_rfc3339());
+    format!(
+        "{}: deleted {deleted_at}, with {} history entries and {} idempotency keys",
+        erasure.patient_id, erasure.history_entries, erasure.idempotency_keys
+    )
+}
@@ -0,0 +1,281 @@
+use crate::api::encryption::{self, DataKey, FieldEncryption, KeyLock, MasterKey};
+use crate::entities::data_key;
+use crate::entities::patient::{self, StoredRecord};
+use crate::entities::patient_history;
+use crate::settings::Settings;
+use anyhow::bail;
+use clap::{Arg, ArgMatches, Command};
+use sea_orm::sea_query::Condition;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, Database, EntityTrait, IntoActiveModel,
+    QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+
+/// Rows re-encrypted per transaction
+const BATCH_SIZE: u64 = 500;
+
+pub fn configure() -> Command {
+    Command::new("rekey")
+        .about("Re-encrypt patient names, addresses and birth dates, and their history, under a new data key; Plaintext patients are encrypted too, and data keys no longer in use are dropped. Stop every server first, since servers load the data keys only when they start")
+        .arg(
+            Arg::new("new-master-key-file")
+                .long("new-master-key-file")
+                .value_name("FILE")
+                .help("File with the base64-encoded master key to rotate to; the new data key is wrapped by it instead of the configured master key, which should be replaced by it once this finishes"),
+        )
+}
+
+pub fn handle(matches: &ArgMatches, settings: &Settings) -> anyhow::Result<()> {
+    let Some(matches) = matches.subcommand_matches("rekey") else {
+        return Ok(());
+    };
+    let Some(master) = MasterKey::from_settings(settings)? else {
+        bail!(
+            "No master key is configured; set encryption.master_key or encryption.master_key_file"
+        );
+    };
+    let new_master = matches
+        .get_one::<String>("new-master-key-file")
+        .map(|path| MasterKey::from_file(path))
+        .transpose()?;
+    if new_master
+        .as_ref()
+        .is_some_and(|new_master| new_master.id() == master.id())
+    {
+        bail!("The new master key is the one already configured");
+    }
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async move {
+            let db_url = settings.database.url.clone().unwrap_or("".to_string());
+            let conn: sea_orm::DatabaseConnection = Database::connect(db_url)
+                .await
+                .expect("Database connection failed");
+
+            // A running server would go on encrypting with the keys it loaded at startup
+            let Some(_key_lock) = KeyLock::exclusive(&conn).await? else {
+                bail!("A server is running; stop every server before rekeying, then start them once this finishes");
+            };
+
+            let mut keys = DataKey::load_all(&conn, &master).await?;
+            let (version, key) = match &new_master {
+                // Keys the new master already wraps come from an interrupted rotation, whose
+                // newest key is picked up again
+                Some(new_master) => {
+                    let mut wrapped = DataKey::load_all(&conn, new_master).await?;
+                    match wrapped.pop_last() {
+                        Some(newest) => newest,
+                        None => DataKey::create(&conn, new_master).await?,
+                    }
+                }
+                None => DataKey::create(&conn, &master).await?,
+            };
+            keys.insert(version, key);
+            let encryption = FieldEncryption::new(version, keys);
+
+            let patients = rekey_patients(&conn, &encryption, version).await?;
+            let entries = rekey_history(&conn, &encryption, version).await?;
+
+            let unused: Vec<i32> = encryption::unused_keys(&conn)
+                .await?
+                .into_iter()
+                .filter(|unused| *unused != version)
+                .collect();
+            data_key::Entity::delete_many()
+                .filter(data_key::Column::Id.is_in(unused.clone()))
+                .exec(&conn)
+                .await?;
+
+            println!(
+                "Re-encrypted {patients} patients and {entries} history entries with data key {version}; dropped {} unused data keys",
+                unused.len()
+            );
+            if new_master.is_some() {
+                println!("Replace the configured master key with the new one and restart the server; the old master key no longer unwraps any data key in use");
+            }
+            Ok::<(), anyhow::Error>(())
+        })?;
+
+    Ok(())
+}
+
+/// Rows that aren't yet encrypted with the data key `version`
+fn stale(column: impl ColumnTrait, version: i32) -> Condition {
+    Condition::any()
+        .add(column.is_null())
+        .add(column.ne(version))
+}
+
+/// Re-encrypts every patient's fields in place, without changing the patient's version
+async fn rekey_patients(
+    conn: &sea_orm::DatabaseConnection,
+    encryption: &FieldEncryption,
+    version: i32,
+) -> anyhow::Result<u64> {
+    let mut count = 0;
+    let mut after = 0;
+    loop {
+        let txn = conn.begin().await?;
+        let batch = patient::Entity::find_records()
+            .filter(stale(patient::Column::KeyVersion, version))
+            .filter(patient::Column::Id.gt(after))
+            .order_by_asc(patient::Column::Id)
+            .limit(BATCH_SIZE)
+            .lock_exclusive()
+            .into_model::<StoredRecord>()
+            .all(&txn)
+            .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = last.id;
+
+        for stored in batch {
+            let (id, name_id, address_id, birthdate_id) = (
+                stored.id,
+                stored.name_id,
+                stored.address_id,
+                stored.birthdate_id,
+            );
+            let record = encryption.open(stored)?;
+            let sealed = encryption.seal(&record)?;
+            patient::name::ActiveModel {
+                id: Set(name_id),
+                ..sealed.name
+            }
+            .update(&txn)
+            .await?;
+            patient::address::ActiveModel {
+                id: Set(address_id),
+                ..sealed.address
+            }
+            .update(&txn)
+            .await?;
+            patient::birthdate::ActiveModel {
+                id: Set(birthdate_id),
+                ..sealed.birthdate
+            }
+            .update(&txn)
+            .await?;
+            patient::ActiveModel {
+                id: Set(id),
+                key_version: Set(sealed.key_version),
+                ..Default::default()
+            }
+            .update(&txn)
+            .await?;
+            count += 1;
+        }
+        txn.commit().await?;
+    }
+    Ok(count)
+}
+
+/// Re-encrypts every history entry in place
+async fn rekey_history(
+    conn: &sea_orm::DatabaseConnection,
+    encryption: &FieldEncryption,
+    version: i32,
+) -> anyhow::Result<u64> {
+    let mut count = 0;
+    let mut after = 0;
+    loop {
+        let txn = conn.begin().await?;
+        let batch = patient_history::Entity::find()
+            .filter(stale(patient_history::Column::KeyVersion, version))
+            .filter(patient_history::Column::Id.gt(after))
+            .order_by_asc(patient_history::Column::Id)
+            .limit(BATCH_SIZE)
+            .lock_exclusive()
+            .all(&txn)
+            .await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = last.id;
+
+        for entry in batch {
+            let sealed = encryption.seal_history(encryption.open_history(entry)?)?;
+            sealed.into_active_model().reset_all().update(&txn).await?;
+            count += 1;
+        }
+        txn.commit().await?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::patient_history::Action;
+    use chrono::Utc;
+    use sea_orm::{DatabaseBackend, MockDatabase, Value};
+    use uuid::Uuid;
+
+    fn entry() -> patient_history::Model {
+        let now = Utc::now();
+        patient_history::Model {
+            id: 1,
+            patient_id: Uuid::new_v4(),
+            version: 1,
+            action: Action::Created,
+            changed_by: "admin".to_string(),
+            changed_at: now,
+            active_flag: true,
+            created_at: now,
+            key_version: None,
+            first: "Jane".to_string(),
+            middle: "".to_string(),
+            surname: "Doe".to_string(),
+            address_lines: vec!["123 Fake St.".to_string()],
+            sublocality: "".to_string(),
+            locality: "Portland".to_string(),
+            administrative_area: "OR".to_string(),
+            postal_code: "97211".to_string(),
+            country_region: "US".to_string(),
+            day: Some(6),
+            month: Some(8),
+            year: 1997,
+            sealed_date: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn rotates_history_to_the_new_data_key() {
+        let plaintext = entry();
+        let old = FieldEncryption::mock(&[(1, 7)])
+            .seal_history(plaintext.clone())
+            .unwrap();
+        let rotated = FieldEncryption::mock(&[(1, 7), (2, 9)]);
+        let updated = rotated.seal_history(plaintext.clone()).unwrap();
+
+        // One batch holding the stale entry, whose update returns it, then an empty batch
+        let conn = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![old], vec![updated]])
+            .append_query_results([Vec::<patient_history::Model>::new()])
+            .into_connection();
+
+        assert_eq!(rekey_history(&conn, &rotated, 2).await.unwrap(), 1);
+
+        let log = conn.into_transaction_log();
+        assert_eq!(log.len(), 2);
+        let statements = log[0].statements();
+        assert!(statements[1]
+            .sql
+            .contains("\"key_version\" IS NULL OR \"patient_history\".\"key_version\" <> $1"));
+        assert!(statements[1].sql.ends_with("FOR UPDATE"));
+
+        // The entry is written back under key 2, with no field in the clear
+        let update = &statements[2];
+        assert!(update.sql.starts_with("UPDATE \"patient_history\""));
+        let values = &update.values.as_ref().unwrap().0;
+        assert!(values.contains(&Value::Int(Some(2))));
+        for field in ["Jane", "Doe", "Portland", "97211"] {
+            assert!(!values.contains(&Value::String(Some(Box::new(field.to_string())))));
+        }
+        assert_eq!(statements[3].sql, "COMMIT");
+    }
+}
@@ -1,3 +1,7 @@
+use crate::api::audit;
+use crate::api::auth::revocation;
+use crate::api::purge;
+use crate::api::encryption::{FieldEncryption, KeyLock};
 use crate::settings::Settings;
 use crate::state::ApplicationState;
 use clap::{value_parser, Arg, ArgMatches, Command};
@@ -120,7 +124,31 @@ fn start_tokio(port: u16, settings: &Settings) -> anyhow::Result<()> {
                 .await
                 .expect("Database connection failed");
 
-            let state = Arc::new(ApplicationState::new(settings, db_conn)?);
+            // Keeps rekey from changing the keys for patient fields while they're in use
+            let _key_lock = KeyLock::shared(&db_conn)
+                .await?
+                .context("A rekey is running; start the server once it finishes")?;
+
+            // Loads the keys for patient fields, creating the first one for a new master key
+            let field_encryption = FieldEncryption::load(&db_conn, settings)
+                .await
+                .context("Failed to load the patient field encryption keys")?;
+
+            let state = Arc::new(ApplicationState::new(settings, db_conn, field_encryption)?);
+
+            // Loads revoked tokens and keeps them in sync with the database
+            state
+                .revocations
+                .sync(state.db_conn.load().as_ref(), settings.token_timeout_seconds)
+                .await
+                .context("Failed to load token revocation list")?;
+            revocation::spawn_sync(state.clone());
+
+            // Appends the audit records of finished requests
+            audit::spawn_writer(state.clone());
+
+            // Purges deleted patients once they pass the retention period, if one is set
+            purge::spawn_retention(state.clone());
 
             // Configures Axum server with localhost, user-defined port,
             // and defines the API endpoints
@@ -136,7 +164,7 @@ fn start_tokio(port: u16, settings: &Settings) -> anyhow::Result<()> {
 
             // Starts the Axum server
             axum::Server::bind(&addr)
-                .serve(routes.into_make_service())
+                .serve(routes.into_make_service_with_connect_info::<SocketAddr>())
                 .await?;
 
             Ok::<(), anyhow::Error>(())
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// How an audited request turned out
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize, ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    #[sea_orm(string_value = "success")]
+    Success,
+    /// The caller wasn't authenticated or wasn't permitted
+    #[sea_orm(string_value = "denied")]
+    Denied,
+    #[sea_orm(string_value = "failure")]
+    Failure,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Denied => "denied",
+            Outcome::Failure => "failure",
+        }
+    }
+}
+
+/// A record of one request, chained to the previous record by `prev_hash`
+///
+/// The table is append-only; a database trigger rejects updates and deletes.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "audit_log")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_deserializing)]
+    pub id: i32,
+    pub occurred_at: DateTime<Utc>,
+    /// The token subject, or "anonymous" for unauthenticated requests
+    pub actor: String,
+    /// The HTTP method and route, such as "GET /patient/:patient_id"
+    pub action: String,
+    pub patient_id: Option<Uuid>,
+    pub request_id: String,
+    pub client_ip: Option<String>,
+    pub status_code: i16,
+    pub outcome: Outcome,
+
+    /// The `hash` of the previous record, or zeros for the first one
+    pub prev_hash: String,
+    /// SHA-256 over `prev_hash` and the other fields
+    pub hash: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,22 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+/// A key that encrypts patient fields; its ID is the key version stored alongside them
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "data_key")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// The key, encrypted with the master key
+    pub wrapped_key: String,
+    /// Identifies the master key that wrapped it, without revealing it
+    pub master_key_id: String,
+    /// Set on the first key the master key wrapped, which only one server may create
+    pub initial: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,33 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "idempotency_key")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_deserializing)]
+    pub id: i32,
+    /// The token subject that sent the request, so keys never collide across callers
+    pub principal: String,
+    pub key: String,
+    pub request_hash: String,
+
+    /// Unset while the original request is still being handled
+    pub response_status: Option<i16>,
+    /// The patient the request created, and its version then, whose history entry the replayed
+    /// response is rebuilt from
+    pub patient_id: Option<Uuid>,
+    pub version: Option<i32>,
+
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// When the request handling the key claimed it, which another may take over an unfinished
+    /// claim after
+    pub claimed_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,2 +1,13 @@
+pub mod api_key;
+pub mod audit_log;
+pub mod data_key;
+pub mod idempotency_key;
+pub mod password_history;
 pub mod patient;
+pub mod patient_history;
+pub mod patient_tombstone;
+pub mod recovery_code;
+pub mod refresh_token;
+pub mod revoked_token;
+pub mod revoked_user;
 pub mod user;
@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use sea_orm::entity::prelude::*;
+use sea_orm::{FromQueryResult, JoinType, QuerySelect};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
@@ -16,6 +17,9 @@ pub mod name {
         pub first: String,
         pub middle: String,
         pub surname: String,
+        /// Blind indexes for exact matches, set when the name is encrypted
+        pub first_index: Option<String>,
+        pub surname_index: Option<String>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -40,6 +44,9 @@ pub mod address {
         pub administrative_area: String,
         pub postal_code: String,
         pub country_region: String,
+        /// Blind indexes for exact matches, set when the address is encrypted
+        pub locality_index: Option<String>,
+        pub postal_code_index: Option<String>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -58,9 +65,14 @@ pub mod birthdate {
         #[sea_orm(primary_key)]
         #[serde(skip_deserializing)]
         pub id: i32,
-        pub day: i32,
-        pub month: i32,
+        /// Unset when the birth date is encrypted, which leaves only the year in the clear
+        pub day: Option<i32>,
+        pub month: Option<i32>,
         pub year: i32,
+        /// The encrypted birth date
+        pub sealed_date: Option<String>,
+        /// A blind index for exact matches, set when the birth date is encrypted
+        pub birth_date_index: Option<String>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -81,6 +93,13 @@ pub struct Model {
 
     pub patient_id: Uuid,
     pub created_at: DateTime<Utc>,
+    /// Incremented on every change, and sent to clients as the ETag
+    pub version: i32,
+    /// The data key the patient's name, address and birth date are encrypted with, if they are
+    pub key_version: Option<i32>,
+    /// When and by whom the patient was deleted; unset while the patient is active
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub deleted_by: Option<String>,
 
     #[sea_orm(
         belongs_to = "name::Model",
@@ -129,3 +148,107 @@ pub enum Relation {
 }
 
 impl ActiveModelBehavior for ActiveModel {}
+
+/// A patient together with its name, address, and birth date, in the clear
+///
+/// Read from the database as a `StoredRecord`, which `FieldEncryption::open` decrypts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatientRecord {
+    pub id: i32,
+    pub active_flag: bool,
+    pub patient_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub version: i32,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub deleted_by: Option<String>,
+
+    pub first: String,
+    pub middle: String,
+    pub surname: String,
+
+    pub address_lines: Vec<String>,
+    pub sublocality: String,
+    pub locality: String,
+    pub administrative_area: String,
+    pub postal_code: String,
+    pub country_region: String,
+
+    pub day: i32,
+    pub month: i32,
+    pub year: i32,
+}
+
+/// A patient together with its name, address, and birth date, as stored
+///
+/// When `key_version` is set, the name, address lines, sublocality, locality, postal code and
+/// birth date hold ciphertext, and the birth day and month are unset.
+#[derive(Clone, Debug, PartialEq, Eq, FromQueryResult)]
+pub struct StoredRecord {
+    pub id: i32,
+    pub active_flag: bool,
+    pub patient_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub version: i32,
+    pub key_version: Option<i32>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub deleted_by: Option<String>,
+    pub name_id: i32,
+    pub address_id: i32,
+    pub birthdate_id: i32,
+
+    pub first: String,
+    pub middle: String,
+    pub surname: String,
+
+    pub address_lines: Vec<String>,
+    pub sublocality: String,
+    pub locality: String,
+    pub administrative_area: String,
+    pub postal_code: String,
+    pub country_region: String,
+
+    pub day: Option<i32>,
+    pub month: Option<i32>,
+    pub year: i32,
+    pub sealed_date: Option<String>,
+}
+
+impl Entity {
+    /// Selects patients joined to their related rows, so that a whole page of
+    /// `StoredRecord`s loads in a single query
+    pub fn find_records() -> Select<Entity> {
+        Entity::find()
+            .select_only()
+            .columns([
+                Column::Id,
+                Column::ActiveFlag,
+                Column::PatientId,
+                Column::CreatedAt,
+                Column::Version,
+                Column::KeyVersion,
+                Column::DeletedAt,
+                Column::DeletedBy,
+                Column::NameId,
+                Column::AddressId,
+                Column::BirthdateId,
+            ])
+            .columns([name::Column::First, name::Column::Middle, name::Column::Surname])
+            .columns([
+                address::Column::AddressLines,
+                address::Column::Sublocality,
+                address::Column::Locality,
+                address::Column::AdministrativeArea,
+                address::Column::PostalCode,
+                address::Column::CountryRegion,
+            ])
+            .columns([
+                birthdate::Column::Day,
+                birthdate::Column::Month,
+                birthdate::Column::Year,
+                birthdate::Column::SealedDate,
+            ])
+            .join(JoinType::InnerJoin, Relation::Name.def())
+            .join(JoinType::InnerJoin, Relation::Address.def())
+            .join(JoinType::InnerJoin, Relation::Birthdate.def())
+    }
+}
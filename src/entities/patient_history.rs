@@ -0,0 +1,131 @@
+use super::patient::{self, StoredRecord};
+
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue::Set, ConnectionTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The kind of change that produced a history entry
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize, ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// The state the patient was in when history recording began
+    #[sea_orm(string_value = "baseline")]
+    Baseline,
+    #[sea_orm(string_value = "created")]
+    Created,
+    #[sea_orm(string_value = "updated")]
+    Updated,
+    #[sea_orm(string_value = "deleted")]
+    Deleted,
+    #[sea_orm(string_value = "restored")]
+    Restored,
+}
+
+/// A patient as it was after one change
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "patient_history")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_deserializing)]
+    pub id: i32,
+    pub patient_id: Uuid,
+    /// The patient's version after the change
+    pub version: i32,
+    pub action: Action,
+    /// The token subject that made the change
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+
+    pub active_flag: bool,
+    pub created_at: DateTime<Utc>,
+    /// Encrypted like the patient itself, when `key_version` is set
+    pub key_version: Option<i32>,
+
+    pub first: String,
+    pub middle: String,
+    pub surname: String,
+
+    pub address_lines: Vec<String>,
+    pub sublocality: String,
+    pub locality: String,
+    pub administrative_area: String,
+    pub postal_code: String,
+    pub country_region: String,
+
+    pub day: Option<i32>,
+    pub month: Option<i32>,
+    pub year: i32,
+    pub sealed_date: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Snapshots the patient as it stands on `db` after a change
+///
+/// Call it inside the transaction that made the change, so the change and its history entry are
+/// stored together. Encrypted fields are copied as they are, so the entry stays encrypted.
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    id: i32,
+    action: Action,
+    changed_by: &str,
+) -> Result<Model, DbErr> {
+    let record = patient::Entity::find_records()
+        .filter(patient::Column::Id.eq(id))
+        .into_model::<StoredRecord>()
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("patient {id}")))?;
+
+    ActiveModel {
+        patient_id: Set(record.patient_id),
+        version: Set(record.version),
+        action: Set(action),
+        changed_by: Set(changed_by.to_string()),
+        changed_at: Set(Utc::now()),
+        active_flag: Set(record.active_flag),
+        created_at: Set(record.created_at),
+        key_version: Set(record.key_version),
+        first: Set(record.first),
+        middle: Set(record.middle),
+        surname: Set(record.surname),
+        address_lines: Set(record.address_lines),
+        sublocality: Set(record.sublocality),
+        locality: Set(record.locality),
+        administrative_area: Set(record.administrative_area),
+        postal_code: Set(record.postal_code),
+        country_region: Set(record.country_region),
+        day: Set(record.day),
+        month: Set(record.month),
+        year: Set(record.year),
+        sealed_date: Set(record.sealed_date),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+}
+
+impl Entity {
+    /// The entry in effect at `as_of`, which is the last one made at or before it
+    pub async fn find_as_of<C: ConnectionTrait>(
+        db: &C,
+        patient_id: Uuid,
+        as_of: DateTime<Utc>,
+    ) -> Result<Option<Model>, DbErr> {
+        Entity::find()
+            .filter(Column::PatientId.eq(patient_id))
+            .filter(Column::ChangedAt.lte(as_of))
+            .order_by_desc(Column::ChangedAt)
+            .order_by_desc(Column::Version)
+            .one(db)
+            .await
+    }
+}
@@ -1,7 +1,57 @@
+use chrono::{DateTime, Utc};
 use sea_orm::entity::prelude::*;
+use sea_orm::Iterable;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+/// Access level granted to a user
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize, ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::None)")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Full access, including destructive operations
+    #[sea_orm(string_value = "admin")]
+    Admin,
+    /// Reads and edits patient records
+    #[sea_orm(string_value = "clinician")]
+    Clinician,
+    /// Registers patients and maintains their demographics
+    #[sea_orm(string_value = "front_desk")]
+    FrontDesk,
+    /// Reads patient records only
+    #[sea_orm(string_value = "read_only")]
+    ReadOnly,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Clinician => "clinician",
+            Role::FrontDesk => "front_desk",
+            Role::ReadOnly => "read_only",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown role \"{s}\""))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize, ToSchema)]
 #[sea_orm(table_name = "user")]
 pub struct Model {
@@ -10,9 +60,31 @@ pub struct Model {
     pub id: i32,
     pub username: String,
     pub password: String,
+    pub role: Role,
+
+    /// Disabled users cannot log in or refresh tokens
+    #[serde(default = "default_active_flag")]
+    pub active_flag: bool,
+
+    #[serde(skip_deserializing)]
+    pub created_at: DateTime<Utc>,
+
+    /// Access tokens issued at or before this instant are rejected
+    pub tokens_revoked_at: Option<DateTime<Utc>>,
+
+    /// The base32-encoded TOTP secret, pending until `totp_enabled_at` is set
+    pub totp_secret: Option<String>,
+    /// Set once the user confirms enrolment, after which logins require a second factor
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    /// The last accepted TOTP time step, so that no code is accepted twice
+    pub totp_last_step: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
+
+fn default_active_flag() -> bool {
+    true
+}
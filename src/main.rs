@@ -35,9 +35,9 @@ pub fn main() -> anyhow::Result<()> {
         .unwrap_or("");
 
     // Creates a src/settings.Settings object to load values prefixed with DOC__
-    let mut settings = settings::Settings::new(config_location, "DOC")?;
+    let settings = settings::Settings::new(config_location, "DOC")?;
 
-    commands::handle(&matches, &mut settings)?;
+    commands::handle(&matches, &settings)?;
 
     Ok(())
 }
@@ -1,5 +1,6 @@
 use config::{Config, Environment, File};
 use serde::Deserialize;
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize, Default, Clone)]
 #[allow(unused)]
@@ -26,6 +27,243 @@ pub struct Tracing {
     pub otlp_endpoint: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_lowercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Passwords to reject in addition to the built-in list of common passwords
+    pub deny_list: Vec<String>,
+    /// The number of most recent passwords, including the current one, that cannot be reused
+    pub history_size: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            require_uppercase: true,
+            require_lowercase: true,
+            require_digit: true,
+            require_symbol: false,
+            deny_list: Vec::new(),
+            history_size: 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct LoginThrottle {
+    /// Consecutive failures for a username before it is locked out
+    pub max_failures: u32,
+    /// Failures from a single client IP before it is locked out
+    pub ip_max_failures: u32,
+    pub lockout_seconds: u64,
+    /// Failures older than this no longer count toward a lockout
+    pub failure_window_seconds: u64,
+    /// The delay required after the first failure, which doubles with each further failure
+    pub backoff_base_milliseconds: u64,
+    pub backoff_max_milliseconds: u64,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            ip_max_failures: 50,
+            lockout_seconds: 900,
+            failure_window_seconds: 900,
+            backoff_base_milliseconds: 500,
+            backoff_max_milliseconds: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct Mfa {
+    /// The account issuer shown in authenticator apps
+    pub issuer: String,
+    /// How long a user has to supply their code after a correct password
+    pub challenge_timeout_seconds: i64,
+    /// The number of recovery codes issued at enrolment
+    pub recovery_codes: usize,
+}
+
+impl Default for Mfa {
+    fn default() -> Self {
+        Self {
+            issuer: "API Doc".to_string(),
+            challenge_timeout_seconds: 300,
+            recovery_codes: 10,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct Redaction {
+    /// Send request payloads to logs and traces unmasked; for local debugging only
+    pub debug: bool,
+    /// Field names to mask in addition to the built-in credentials, names, birth dates and
+    /// addresses
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct Idempotency {
+    /// How long a response is replayed for a retried `Idempotency-Key`
+    pub window_seconds: i64,
+    /// How long a request that hasn't finished holds its key before a retry may take it over,
+    /// which must be longer than any request takes
+    pub claim_timeout_seconds: i64,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Self {
+            window_seconds: 86400,
+            claim_timeout_seconds: 300,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct Retention {
+    /// Permanently purge patients deleted more than this many days ago. Deleted patients are
+    /// kept until purged by hand when this is unset.
+    pub purge_inactive_after_days: Option<u32>,
+    /// How often the server looks for patients past the retention period
+    pub interval_seconds: u64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            purge_inactive_after_days: None,
+            interval_seconds: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct Duplicates {
+    /// The lowest match score, from 0 to 1, at which a new patient is held back as a likely
+    /// duplicate of an existing one, and a pair is listed in the duplicates report
+    pub match_threshold: f32,
+}
+
+impl Default for Duplicates {
+    fn default() -> Self {
+        Self {
+            match_threshold: 0.8,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct Encryption {
+    /// A base64-encoded 256-bit key that wraps the keys encrypting patient fields. Patient
+    /// fields are stored in plaintext when neither this nor `master_key_file` is set.
+    pub master_key: Option<String>,
+    /// A file holding the base64-encoded master key, used when `master_key` is unset
+    pub master_key_file: Option<String>,
+}
+
+// Written out so that the master key can't end up in a log through `{:?}`
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encryption")
+            .field("master_key", &self.master_key.as_ref().map(|_| "[REDACTED]"))
+            .field("master_key_file", &self.master_key_file)
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[allow(unused)]
+pub enum SigningAlgorithm {
+    RS256,
+    ES256,
+    EdDSA,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct SigningKey {
+    /// Sent as the `kid` header of tokens signed with this key
+    pub kid: String,
+    pub algorithm: SigningAlgorithm,
+    /// A PKCS#8 private key in PEM format; RS256 keys may also be PKCS#1
+    pub private_key_path: String,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+#[allow(unused)]
+#[serde(default)]
+pub struct TokenSigning {
+    /// The key that signs new tokens, defaulting to the first key
+    pub active_kid: Option<String>,
+    /// Every key that tokens are verified against. Keep a retired key listed
+    /// until the tokens it signed have expired. Tokens are signed with HS256
+    /// and `token_secret` when this is empty.
+    pub keys: Vec<SigningKey>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Oidc {
+    /// The issuer URL, which must match the `iss` claim and serve
+    /// `/.well-known/openid-configuration`
+    pub issuer: String,
+    /// The value required in the `aud` claim
+    pub audience: String,
+    /// The claim used as the username
+    #[serde(default = "default_oidc_username_claim")]
+    pub username_claim: String,
+    /// The claim holding the provider's role or group names, which may be a
+    /// dotted path such as `realm_access.roles`
+    #[serde(default = "default_oidc_role_claim")]
+    pub role_claim: String,
+    /// Provider role names mapped to local role names. When several match,
+    /// the most privileged local role applies.
+    #[serde(default)]
+    pub role_mapping: HashMap<String, String>,
+    /// The local role for tokens without a mapped role, which are rejected
+    /// if this is unset
+    #[serde(default)]
+    pub default_role: Option<String>,
+    /// How long the provider's signing keys are cached
+    #[serde(default = "default_oidc_jwks_cache_seconds")]
+    pub jwks_cache_seconds: u64,
+}
+fn default_oidc_username_claim() -> String {
+    "sub".to_string()
+}
+fn default_oidc_role_claim() -> String {
+    "roles".to_string()
+}
+// One hour
+fn default_oidc_jwks_cache_seconds() -> u64 {
+    3_600
+}
+
 #[derive(Debug, Deserialize, Default, Clone)]
 #[allow(unused)]
 pub struct Settings {
@@ -39,9 +277,46 @@ pub struct Settings {
     pub token_secret: String,
     #[serde(default)]
     pub token_timeout_seconds: i64,
+    #[serde(default = "default_refresh_token_timeout_seconds")]
+    pub refresh_token_timeout_seconds: i64,
+    #[serde(default)]
+    pub token_signing: TokenSigning,
+    /// An external identity provider whose tokens are accepted alongside
+    /// locally issued ones
+    #[serde(default)]
+    pub oidc: Option<Oidc>,
     #[serde(default)]
     pub tracing: Tracing,
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    #[serde(default)]
+    pub login_throttle: LoginThrottle,
+    #[serde(default)]
+    pub mfa: Mfa,
+    #[serde(default)]
+    pub idempotency: Idempotency,
+    #[serde(default)]
+    pub redaction: Redaction,
+    #[serde(default)]
+    pub encryption: Encryption,
+    #[serde(default)]
+    pub retention: Retention,
+    #[serde(default)]
+    pub duplicates: Duplicates,
+    /// Reject patient updates and deletes without an `If-Match` header with
+    /// 428, rather than only checking the header when it is sent
+    #[serde(default)]
+    pub require_if_match: bool,
+    /// Take the client IP from the last X-Forwarded-For entry, which is
+    /// only safe behind a single proxy that appends it
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
 }
+// Two weeks
+fn default_refresh_token_timeout_seconds() -> i64 {
+    1_209_600
+}
+
 impl Settings {
     pub fn new(location: &str, env_prefix: &str) -> anyhow::Result<Self> {
         let config = Config::builder()
@@ -1,3 +1,9 @@
+use crate::api::audit::AuditWriter;
+use crate::api::auth::keys::SigningKeys;
+use crate::api::auth::oidc::OidcVerifier;
+use crate::api::auth::revocation::RevocationList;
+use crate::api::auth::throttle::FailedLogins;
+use crate::api::encryption::FieldEncryption;
 use crate::settings::Settings;
 use arc_swap::ArcSwap;
 use sea_orm::DatabaseConnection;
@@ -6,13 +12,49 @@ use std::sync::Arc;
 pub struct ApplicationState {
     pub db_conn: ArcSwap<DatabaseConnection>,
     pub settings: ArcSwap<Settings>,
+    pub signing_keys: SigningKeys,
+    pub oidc: Option<OidcVerifier>,
+    pub revocations: RevocationList,
+    pub failed_logins: FailedLogins,
+    pub field_encryption: FieldEncryption,
+    pub audit_writer: AuditWriter,
 }
 
 impl ApplicationState {
-    pub fn new(settings: &Settings, db_conn: DatabaseConnection) -> anyhow::Result<Self> {
+    pub fn new(
+        settings: &Settings,
+        db_conn: DatabaseConnection,
+        field_encryption: FieldEncryption,
+    ) -> anyhow::Result<Self> {
         Ok(Self {
             db_conn: ArcSwap::new(Arc::new(db_conn)),
             settings: ArcSwap::new(Arc::new((*settings).clone())),
+            signing_keys: SigningKeys::new(settings)?,
+            oidc: settings.oidc.as_ref().map(OidcVerifier::new).transpose()?,
+            revocations: RevocationList::new(),
+            failed_logins: FailedLogins::new(),
+            field_encryption,
+            audit_writer: AuditWriter::new(),
         })
     }
 }
+
+#[cfg(test)]
+impl ApplicationState {
+    /// State for calling handlers directly, over a mock database and without encryption
+    pub fn mock(db: DatabaseConnection) -> Arc<Self> {
+        let settings = Settings {
+            token_secret: "secret".to_string(),
+            ..Default::default()
+        };
+        Arc::new(Self::new(&settings, db, FieldEncryption::disabled()).unwrap())
+    }
+
+    /// The statements a handler ran against the mock database
+    pub fn into_transaction_log(self: Arc<Self>) -> Vec<sea_orm::Transaction> {
+        let state = Arc::into_inner(self).expect("a handler still holds the state");
+        Arc::into_inner(state.db_conn.into_inner())
+            .expect("a handler still holds the connection")
+            .into_transaction_log()
+    }
+}